@@ -45,8 +45,20 @@ fn iter_nodes<'a, W: Write>(
         Text(t) => write!(writer, "{:?}", String::from_utf8_lossy(&t))?,
         value => {
             try_node_inline!(value, Code);
-            try_node_inline!(value, FootnoteDefinition);
-            try_node_inline!(value, FootnoteReference);
+            if let FootnoteDefinition(nfd) = value {
+                return write!(
+                    writer,
+                    "FootnoteDefinition({:?})",
+                    String::from_utf8_lossy(&nfd.name)
+                );
+            }
+            if let FootnoteReference(nfr) = value {
+                return write!(
+                    writer,
+                    "FootnoteReference({:?})",
+                    String::from_utf8_lossy(&nfr.name)
+                );
+            }
             try_node_inline!(value, HtmlInline);
 
             let has_blocks = node.children().any(|c| c.data.borrow().value.block());
@@ -0,0 +1,135 @@
+//! Adapter traits for plugins.
+//!
+//! Each plugin has to implement one of the traits available in this module.
+
+use nodes::{AstNode, NodeContainer};
+use parser::ComrakOptions;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Implement this adapter for creating a plugin for custom syntax highlighting of codefence blocks.
+pub trait SyntaxHighlighterAdapter {
+    /// Generates a syntax highlighted HTML output.
+    ///
+    /// lang: Name of the programming language (the first word of the codefence's info string,
+    /// if any).
+    /// code: The source code to be syntax highlighted.
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()>;
+
+    /// Generates the opening `<pre>` tag.
+    ///
+    /// attributes: A map of HTML attributes provided by comrak, such as `lang` when
+    /// `github_pre_lang` is set.
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()>;
+
+    /// Generates the opening `<code>` tag.
+    ///
+    /// attributes: A map of HTML attributes provided by comrak, such as the `language-` class.
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()>;
+}
+
+/// Implement this adapter to override how particular nodes are rendered as HTML.
+///
+/// The HTML formatter calls `enter` before rendering a node's children and `exit` afterwards.
+/// Each returns whether the formatter should fall back to its default rendering for that half of
+/// the node; return `Ok(true)` for any node you don't wish to handle.  The node's children are
+/// rendered by the formatter either way, with images' alt text still rendered as plain text.
+///
+/// The helpers `comrak::html::escape`, `comrak::html::escape_href` and
+/// `comrak::html::write_opening_tag` are available for writing output safely.
+pub trait NodeRendererAdapter {
+    /// Called when the formatter enters `node`.
+    fn enter<'a>(
+        &self,
+        output: &mut dyn Write,
+        node: &'a AstNode<'a>,
+        options: &ComrakOptions,
+    ) -> io::Result<bool>;
+
+    /// Called when the formatter leaves `node`, after its children have been rendered.
+    fn exit<'a>(
+        &self,
+        output: &mut dyn Write,
+        node: &'a AstNode<'a>,
+        options: &ComrakOptions,
+    ) -> io::Result<bool>;
+}
+
+/// Implement this adapter to customise how custom containers are rendered as HTML, such as to
+/// render `::: tabs` as a tab widget.
+///
+/// Each method returns whether the formatter should fall back to its default rendering, a `div`
+/// with the container's name as its class; return `Ok(true)` for any container you don't wish to
+/// handle.  The container's children are rendered by the formatter either way.
+pub trait ContainerRendererAdapter {
+    /// Called to write the opening of `container`, before its children.
+    fn enter(&self, output: &mut dyn Write, container: &NodeContainer) -> io::Result<bool>;
+
+    /// Called to write the closing of `container`, after its children.
+    fn exit(&self, output: &mut dyn Write, container: &NodeContainer) -> io::Result<bool>;
+}
+
+/// Implement this adapter to map the page names of wikilinks to the URLs they link to.
+///
+/// Closures of the form `Fn(&[u8]) -> Vec<u8>` implement this adapter.
+pub trait WikiLinkResolverAdapter {
+    /// Returns the URL to link to for the page named `page`.
+    fn resolve(&self, page: &[u8]) -> Vec<u8>;
+}
+
+impl<F> WikiLinkResolverAdapter for F
+where
+    F: Fn(&[u8]) -> Vec<u8>,
+{
+    fn resolve(&self, page: &[u8]) -> Vec<u8> {
+        self(page)
+    }
+}
+
+/// The kind of node a URL passed to a `UrlRewriterAdapter` belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UrlKind {
+    /// A link's destination.
+    Link,
+
+    /// An image's source.
+    Image,
+
+    /// An autolink's destination, whether written in angle brackets or found by the `autolink`
+    /// extension.
+    Autolink,
+
+    /// A wikilink's destination, after any wikilink resolver has been applied.
+    WikiLink,
+}
+
+/// Implement this adapter to rewrite the URLs of links and images when rendering HTML.
+///
+/// The rewritten URL is checked for safety and escaped like any other.  Closures of the form
+/// `Fn(&[u8], UrlKind) -> Vec<u8>` implement this adapter.
+pub trait UrlRewriterAdapter {
+    /// Returns the URL to render in place of `url`.
+    fn rewrite(&self, url: &[u8], kind: UrlKind) -> Vec<u8>;
+}
+
+impl<F> UrlRewriterAdapter for F
+where
+    F: Fn(&[u8], UrlKind) -> Vec<u8>,
+{
+    fn rewrite(&self, url: &[u8], kind: UrlKind) -> Vec<u8> {
+        self(url, kind)
+    }
+}
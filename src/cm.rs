@@ -1,7 +1,7 @@
 use ctype::{isalpha, isdigit, ispunct, isspace};
 use nodes;
 use nodes::TableAlignment;
-use nodes::{AstNode, ListDelimType, ListType, NodeLink, NodeValue};
+use nodes::{AstNode, ListDelimType, ListType, NodeAttributes, NodeLink, NodeValue, NodeWikiLink};
 use parser::ComrakOptions;
 use scanners;
 use std;
@@ -23,6 +23,57 @@ pub fn format_document<'a>(
     Ok(())
 }
 
+/// Formats a document given as a series of trees, each holding some of its top-level blocks, as
+/// CommonMark.  `blocks` is called with a function to format each tree in turn.  Used by
+/// `event::format_commonmark`.
+pub(crate) fn format_blocks<F>(
+    options: &ComrakOptions,
+    output: &mut dyn Write,
+    blocks: F,
+) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn for<'a> FnMut(&'a AstNode<'a>) -> io::Result<()>) -> io::Result<()>,
+{
+    // How the last tree written ended, once one has been.
+    #[derive(PartialEq)]
+    enum Last {
+        List,
+        FrontMatter,
+        Other,
+    }
+    let mut written = None;
+
+    blocks(&mut |root| {
+        let (first, last) = match (root.first_child(), root.last_child()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(()),
+        };
+
+        // Separate the blocks as `format_node` does siblings.
+        match written {
+            None | Some(Last::FrontMatter) => (),
+            Some(ref written) => {
+                if *written == Last::List
+                    && matches!(
+                        first.data.borrow().value,
+                        NodeValue::CodeBlock(..) | NodeValue::List(..)
+                    )
+                {
+                    output.write_all(b"\n<!-- end list -->\n")?;
+                }
+                output.write_all(b"\n")?;
+            }
+        }
+        written = Some(match last.data.borrow().value {
+            NodeValue::List(..) => Last::List,
+            NodeValue::FrontMatter(..) => Last::FrontMatter,
+            _ => Last::Other,
+        });
+
+        format_document(root, options, output)
+    })
+}
+
 struct CommonMarkFormatter<'a, 'o> {
     node: &'a AstNode<'a>,
     options: &'o ComrakOptions,
@@ -36,7 +87,6 @@ struct CommonMarkFormatter<'a, 'o> {
     no_linebreaks: bool,
     in_tight_list_item: bool,
     custom_escape: Option<fn(&'a AstNode<'a>, u8) -> bool>,
-    footnote_ix: u32,
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -73,7 +123,6 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
             no_linebreaks: false,
             in_tight_list_item: false,
             custom_escape: None,
-            footnote_ix: 0,
         }
     }
 
@@ -185,6 +234,12 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     || c == b'`'
                     || c == b'!'
                     || (c == b'&' && isalpha(nextc))
+                    || (c == b'$' && self.options.extension.math)
+                    || (c == b'=' && self.options.extension.highlight)
+                    || (c == b'+' && self.options.extension.insert)
+                    || (c == b'|' && self.options.extension.spoiler)
+                    || (c == b':' && self.options.extension.shortcodes)
+                    || (c == b'{' && self.options.extension.attributes)
                     || (c == b'!' && nextc == 0x5b)
                     || (self.begin_content
                         && (c == b'-' || c == b'+' || c == b'=')
@@ -230,6 +285,18 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
         self.need_cr = max(self.need_cr, 2);
     }
 
+    // Writes a node's attribute block for the `attributes` extension, if it has any, preceded by
+    // a space if `spaced`.
+    fn write_attributes(&mut self, node: &'a AstNode<'a>, spaced: bool) {
+        let attributes = attributes_block(&node.data.borrow().attributes);
+        if !attributes.is_empty() {
+            if spaced {
+                write!(self, " ").unwrap();
+            }
+            self.write_all(&attributes).unwrap();
+        }
+    }
+
     fn format(&mut self, node: &'a AstNode<'a>) {
         enum Phase {
             Pre,
@@ -281,6 +348,60 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
         false
     }
 
+    fn format_code_block(&mut self, node: &'a AstNode<'a>, info: &[u8], literal: &[u8]) {
+        let first_in_list_item = node.previous_sibling().is_none()
+            && match node.parent() {
+                Some(parent) => {
+                    matches!(parent.data.borrow().value, NodeValue::Item(..))
+                }
+                _ => false,
+            };
+
+        if !first_in_list_item {
+            self.blankline();
+        }
+
+        let attributes = attributes_block(&node.data.borrow().attributes);
+        if info.is_empty()
+            && attributes.is_empty()
+            && (literal.len() > 2
+                && !isspace(literal[0])
+                && !(isspace(literal[literal.len() - 1]) && isspace(literal[literal.len() - 2])))
+            && !first_in_list_item
+        {
+            write!(self, "    ").unwrap();
+            write!(self.prefix, "    ").unwrap();
+            self.write_all(literal).unwrap();
+            let new_len = self.prefix.len() - 4;
+            self.prefix.truncate(new_len);
+        } else {
+            let fence_char = if info.contains(&b'`') || attributes.contains(&b'`') {
+                b'~'
+            } else {
+                b'`'
+            };
+            let numticks = max(3, longest_char_sequence(literal, fence_char) + 1);
+            for _ in 0..numticks {
+                write!(self, "{}", fence_char as char).unwrap();
+            }
+            if !info.is_empty() {
+                write!(self, " ").unwrap();
+                self.write_all(info).unwrap();
+            }
+            if !attributes.is_empty() {
+                write!(self, " ").unwrap();
+                self.write_all(&attributes).unwrap();
+            }
+            self.cr();
+            self.write_all(literal).unwrap();
+            self.cr();
+            for _ in 0..numticks {
+                write!(self, "{}", fence_char as char).unwrap();
+            }
+        }
+        self.blankline();
+    }
+
     fn format_node(&mut self, node: &'a AstNode<'a>, entering: bool) -> bool {
         self.node = node;
         let allow_wrap = self.options.render.width > 0 && !self.options.render.hardbreaks;
@@ -310,6 +431,38 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     self.blankline();
                 }
             }
+            NodeValue::Alert(ref na) => {
+                if entering {
+                    write!(self, "> [!{}]", na.kind.name()).unwrap();
+                    if let Some(ref title) = na.title {
+                        write!(self, " ").unwrap();
+                        self.output(title, false, Escaping::Literal);
+                    }
+                    write!(self.prefix, "> ").unwrap();
+                    self.cr();
+                } else {
+                    let new_len = self.prefix.len() - 2;
+                    self.prefix.truncate(new_len);
+                    self.blankline();
+                }
+            }
+            NodeValue::Container(ref nc) => {
+                let fence = vec![b':'; max(3, nc.fence_length)];
+                if entering {
+                    self.write_all(&fence).unwrap();
+                    write!(self, " ").unwrap();
+                    self.write_all(&nc.name).unwrap();
+                    if !nc.info.is_empty() {
+                        write!(self, " ").unwrap();
+                        self.write_all(&nc.info).unwrap();
+                    }
+                    self.cr();
+                } else {
+                    self.cr();
+                    self.write_all(&fence).unwrap();
+                    self.blankline();
+                }
+            }
             NodeValue::List(..) => {
                 if !entering
                     && match node.next_sibling() {
@@ -391,54 +544,14 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     self.begin_content = true;
                     self.no_linebreaks = true;
                 } else {
+                    self.write_attributes(node, true);
                     self.no_linebreaks = false;
                     self.blankline();
                 }
             }
             NodeValue::CodeBlock(ref ncb) => {
                 if entering {
-                    let first_in_list_item = node.previous_sibling().is_none()
-                        && match node.parent() {
-                            Some(parent) => {
-                                matches!(parent.data.borrow().value, NodeValue::Item(..))
-                            }
-                            _ => false,
-                        };
-
-                    if !first_in_list_item {
-                        self.blankline();
-                    }
-
-                    if ncb.info.is_empty()
-                        && (ncb.literal.len() > 2
-                            && !isspace(ncb.literal[0])
-                            && !(isspace(ncb.literal[ncb.literal.len() - 1])
-                                && isspace(ncb.literal[ncb.literal.len() - 2])))
-                        && !first_in_list_item
-                    {
-                        write!(self, "    ").unwrap();
-                        write!(self.prefix, "    ").unwrap();
-                        self.write_all(&ncb.literal).unwrap();
-                        let new_len = self.prefix.len() - 4;
-                        self.prefix.truncate(new_len);
-                    } else {
-                        let fence_char = if ncb.info.contains(&b'`') { b'~' } else { b'`' };
-                        let numticks = max(3, longest_char_sequence(&ncb.literal, fence_char) + 1);
-                        for _ in 0..numticks {
-                            write!(self, "{}", fence_char as char).unwrap();
-                        }
-                        if !ncb.info.is_empty() {
-                            write!(self, " ").unwrap();
-                            self.write_all(&ncb.info).unwrap();
-                        }
-                        self.cr();
-                        self.write_all(&ncb.literal).unwrap();
-                        self.cr();
-                        for _ in 0..numticks {
-                            write!(self, "{}", fence_char as char).unwrap();
-                        }
-                    }
-                    self.blankline();
+                    self.format_code_block(node, &ncb.info, &ncb.literal);
                 }
             }
             NodeValue::HtmlBlock(ref nhb) => {
@@ -448,6 +561,13 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     self.blankline();
                 }
             }
+            NodeValue::TableOfContents => {
+                if entering {
+                    self.blankline();
+                    write!(self, "[TOC]").unwrap();
+                    self.blankline();
+                }
+            }
             NodeValue::ThematicBreak => {
                 if entering {
                     self.blankline();
@@ -540,11 +660,30 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                 }
             }
             NodeValue::Strikethrough => {
-                write!(self, "~").unwrap();
+                if self.options.extension.subscript {
+                    write!(self, "~~").unwrap();
+                } else {
+                    write!(self, "~").unwrap();
+                }
             }
             NodeValue::Superscript => {
                 write!(self, "^").unwrap();
             }
+            NodeValue::Highlight => {
+                write!(self, "==").unwrap();
+            }
+            NodeValue::Insert => {
+                write!(self, "++").unwrap();
+            }
+            NodeValue::Subscript => {
+                write!(self, "~").unwrap();
+            }
+            NodeValue::SpoileredText => {
+                write!(self, "||").unwrap();
+            }
+            NodeValue::Underline => {
+                write!(self, "__").unwrap();
+            }
             NodeValue::Link(ref nl) => {
                 if is_autolink(node, nl) {
                     if entering {
@@ -568,6 +707,53 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                         write!(self, "\"").unwrap();
                     }
                     write!(self, ")").unwrap();
+                    self.write_attributes(node, false);
+                }
+            }
+            NodeValue::WikiLink(ref nl) => {
+                let title_before_pipe = self.options.extension.wikilinks_title_before_pipe;
+                if entering {
+                    write!(self, "[[").unwrap();
+                    if is_bare_wikilink(node, nl) {
+                        self.write_all(&nl.url).unwrap();
+                        write!(self, "]]").unwrap();
+                        return false;
+                    }
+                    if !title_before_pipe {
+                        self.write_all(&nl.url).unwrap();
+                        write!(self, "|").unwrap();
+                    }
+                    // Within a table, `table_escape` already escapes pipes in the title.
+                    if self.custom_escape.is_none() {
+                        self.custom_escape = Some(wikilink_escape);
+                    }
+                } else {
+                    let in_table = node
+                        .ancestors()
+                        .any(|n| matches!(n.data.borrow().value, NodeValue::Table(..)));
+                    if !in_table {
+                        self.custom_escape = None;
+                    }
+                    if title_before_pipe {
+                        write!(self, "|").unwrap();
+                        self.write_all(&nl.url).unwrap();
+                    }
+                    write!(self, "]]").unwrap();
+                }
+            }
+            NodeValue::ShortCode(ref nsc) => {
+                if entering {
+                    write!(self, ":{}:", nsc.code).unwrap();
+                }
+            }
+            NodeValue::Math(ref nm) => {
+                if entering && nm.fenced {
+                    self.format_code_block(node, b"math", &nm.literal);
+                } else if entering {
+                    let fence: &[u8] = if nm.display { b"$$" } else { b"$" };
+                    self.write_all(fence).unwrap();
+                    self.output(&nm.literal, false, Escaping::Literal);
+                    self.write_all(fence).unwrap();
                 }
             }
             NodeValue::Image(ref nl) => {
@@ -582,6 +768,7 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                         write!(self, "\"").unwrap();
                     }
                     write!(self, ")").unwrap();
+                    self.write_attributes(node, false);
                 }
             }
             NodeValue::Table(..) => {
@@ -636,21 +823,21 @@ impl<'a, 'o> CommonMarkFormatter<'a, 'o> {
                     }
                 }
             }
-            NodeValue::FootnoteDefinition(_) => {
+            NodeValue::FootnoteDefinition(ref nfd) => {
                 if entering {
-                    self.footnote_ix += 1;
-                    let footnote_ix = self.footnote_ix;
-                    writeln!(self, "[^{}]:", footnote_ix).unwrap();
+                    self.write_all(b"[^").unwrap();
+                    self.write_all(&nfd.name).unwrap();
+                    self.write_all(b"]:\n").unwrap();
                     write!(self.prefix, "    ").unwrap();
                 } else {
                     let new_len = self.prefix.len() - 4;
                     self.prefix.truncate(new_len);
                 }
             }
-            NodeValue::FootnoteReference(ref r) => {
+            NodeValue::FootnoteReference(ref nfr) => {
                 if entering {
                     self.write_all(b"[^").unwrap();
-                    self.write_all(r).unwrap();
+                    self.write_all(&nfr.name).unwrap();
                     self.write_all(b"]").unwrap();
                 }
             }
@@ -709,6 +896,10 @@ fn is_autolink<'a>(node: &'a AstNode<'a>, nl: &NodeLink) -> bool {
         return false;
     }
 
+    if !node.data.borrow().attributes.is_empty() {
+        return false;
+    }
+
     if !nl.title.is_empty() {
         return false;
     }
@@ -729,9 +920,52 @@ fn is_autolink<'a>(node: &'a AstNode<'a>, nl: &NodeLink) -> bool {
     real_url == &*link_text
 }
 
+// The attribute block for the `attributes` extension, or an empty one if no attributes are
+// given.  Values are always quoted.
+fn attributes_block(attributes: &NodeAttributes) -> Vec<u8> {
+    if attributes.is_empty() {
+        return vec![];
+    }
+
+    let mut items = vec![];
+    if let Some(ref id) = attributes.id {
+        items.push(format!("#{}", id));
+    }
+    for class in &attributes.classes {
+        items.push(format!(".{}", class));
+    }
+    for (name, value) in &attributes.pairs {
+        items.push(format!(
+            "{}=\"{}\"",
+            name,
+            value.replace('\\', "\\\\").replace('"', "\\\"")
+        ));
+    }
+    format!("{{{}}}", items.join(" ")).into_bytes()
+}
+
 fn table_escape<'a>(node: &'a AstNode<'a>, c: u8) -> bool {
     match node.data.borrow().value {
         NodeValue::Table(..) | NodeValue::TableRow(..) | NodeValue::TableCell => false,
         _ => c == b'|',
     }
 }
+
+fn wikilink_escape<'a>(_node: &'a AstNode<'a>, c: u8) -> bool {
+    c == b'|'
+}
+
+// Whether a wikilink's title is just its page name, so that it can be written as `[[Page]]`.
+fn is_bare_wikilink<'a>(node: &'a AstNode<'a>, nl: &NodeWikiLink) -> bool {
+    let child = match node.first_child() {
+        None => return true,
+        Some(child) => child,
+    };
+    if child.next_sibling().is_some() {
+        return false;
+    }
+    match child.data.borrow().value {
+        NodeValue::Text(ref title) => *title == nl.url,
+        _ => false,
+    }
+}
@@ -0,0 +1,251 @@
+//! An event-based view of a parsed document.
+//!
+//! Renderers can be written as consumers of an `Iterator<Item = Event>` rather than walking the
+//! AST themselves.  `events` parses a document a line at a time, producing the events for each
+//! top-level block as soon as no later line can change it, and `format_html` and
+//! `format_commonmark` likewise build and render one top-level block at a time.
+//!
+//! Streaming has two limits.  A link can only use the link reference definitions before it in
+//! the document, since its block's inlines are parsed before the rest of the document has been
+//! read; later definitions are ignored.  With footnotes enabled, no events are produced until the
+//! whole document has been parsed, since footnotes are numbered, and their definitions moved to
+//! the end, across all of it.
+
+use arena_tree::{Node, NodeEdge, Traverse};
+use cm;
+use html;
+use nodes::{Ast, AstNode, NodeValue};
+use parser::{BlockStream, ComrakOptions};
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use typed_arena::Arena;
+
+/// An event in a document's traversal.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The start of a node.  Every `Start` is matched by an `End` once all of the node's
+    /// children have been produced, even when it has none.
+    Start(NodeValue),
+
+    /// The end of the most recently started node which hasn't yet ended.
+    End,
+
+    /// A `NodeValue::Text` node; these have no children, and so no matching `End`.
+    Text(Vec<u8>),
+}
+
+/// An iterator over the events of a document.  Returned by `events` and `Events::new`.
+pub struct Events<'a, 'o> {
+    blocks: Option<BlockStream<'a, 'o>>,
+    traverse: Option<Traverse<'a, RefCell<Ast>>>,
+    started: bool,
+}
+
+impl<'a, 'o> fmt::Debug for Events<'a, 'o> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("streaming", &self.blocks.is_some())
+            .field("traverse", &self.traverse)
+            .finish()
+    }
+}
+
+impl<'a, 'o> Events<'a, 'o> {
+    /// Walks an already parsed document, or any node within one, yielding its events in
+    /// document order.
+    pub fn new(root: &'a AstNode<'a>) -> Self {
+        Events {
+            blocks: None,
+            traverse: Some(root.traverse()),
+            started: true,
+        }
+    }
+}
+
+impl<'a, 'o> Iterator for Events<'a, 'o> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        if !self.started {
+            self.started = true;
+            return Some(Event::Start(NodeValue::Document));
+        }
+
+        loop {
+            match self.traverse.as_mut().and_then(|traverse| traverse.next()) {
+                Some(NodeEdge::Start(node)) => {
+                    let ast = node.data.borrow();
+                    return Some(match ast.value {
+                        NodeValue::Text(ref text) => Event::Text(text.clone()),
+                        ref value => Event::Start(value.clone()),
+                    });
+                }
+                Some(NodeEdge::End(node)) => {
+                    if let NodeValue::Text(..) = node.data.borrow().value {
+                        continue;
+                    }
+                    return Some(Event::End);
+                }
+                None => {
+                    // Move on to the next block, if streaming; its events follow those of the
+                    // blocks before it within the one document.
+                    match self.blocks.as_mut()?.next_block() {
+                        Some(block) => self.traverse = Some(block.traverse()),
+                        None => {
+                            self.blocks = None;
+                            return Some(Event::End);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parse a Markdown document a line at a time and return an iterator over its events.
+///
+/// The events for each top-level block are produced once the parser has closed it; see the
+/// module documentation for how this affects link reference definitions and footnotes.  The
+/// nodes are allocated in `arena`, and so are kept until it is dropped.
+///
+/// ```
+/// # use comrak::{Arena, ComrakOptions};
+/// use comrak::events;
+/// use comrak::event::Event;
+///
+/// let arena = Arena::new();
+/// let options = ComrakOptions::default();
+/// let text = events(&arena, "Hello, *world*!\n", &options)
+///     .filter_map(|event| match event {
+///         Event::Text(text) => Some(String::from_utf8(text).unwrap()),
+///         _ => None,
+///     })
+///     .collect::<String>();
+/// assert_eq!(text, "Hello, world!");
+/// ```
+pub fn events<'a, 'o>(
+    arena: &'a Arena<AstNode<'a>>,
+    md: &'o str,
+    options: &'o ComrakOptions,
+) -> Events<'a, 'o> {
+    Events {
+        blocks: Some(BlockStream::new(arena, md, options)),
+        traverse: None,
+        started: false,
+    }
+}
+
+/// Build an AST from a stream of events, such as one produced by `events` and then filtered or
+/// transformed.
+///
+/// The stream should start with `Event::Start(NodeValue::Document)`; if it doesn't, the events
+/// are placed in a new document.  Nodes built this way have no source positions.
+pub fn build_tree<'a, I>(arena: &'a Arena<AstNode<'a>>, events: I) -> &'a AstNode<'a>
+where
+    I: IntoIterator<Item = Event>,
+{
+    let alloc = |value| -> &'a AstNode<'a> {
+        let mut ast = Ast::new(value);
+        ast.open = false;
+        arena.alloc(Node::new(RefCell::new(ast)))
+    };
+
+    let root = alloc(NodeValue::Document);
+    let mut stack = vec![root];
+
+    for event in events {
+        match event {
+            // The document's own start and end wrap the whole stream.
+            Event::Start(NodeValue::Document) if stack.len() == 1 => stack.push(root),
+            Event::Start(value) => {
+                let node = alloc(value);
+                stack.last().unwrap().append(node);
+                stack.push(node);
+            }
+            Event::End => {
+                if stack.len() > 1 {
+                    stack.pop();
+                }
+            }
+            Event::Text(text) => {
+                stack.last().unwrap().append(alloc(NodeValue::Text(text)));
+            }
+        }
+    }
+
+    root
+}
+
+// Builds each top-level block of a stream of events into a document of its own, in an arena
+// which lasts only as long as the call to `f` with it.
+fn for_each_block<I>(
+    events: I,
+    f: &mut dyn for<'a> FnMut(&'a AstNode<'a>) -> io::Result<()>,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Event>,
+{
+    let mut block = vec![];
+    let mut depth = 0;
+    for event in events {
+        match event {
+            Event::Start(NodeValue::Document) | Event::End if depth == 0 => continue,
+            Event::Start(..) => depth += 1,
+            Event::End => depth -= 1,
+            Event::Text(..) => (),
+        }
+        block.push(event);
+        if depth == 0 {
+            let arena = Arena::new();
+            f(build_tree(&arena, block.drain(..)))?;
+        }
+    }
+    if !block.is_empty() {
+        let arena = Arena::new();
+        f(build_tree(&arena, block))?;
+    }
+    Ok(())
+}
+
+/// Formats a stream of events as HTML, modified by the given options.
+///
+/// Each top-level block is built back into an AST, in an arena of its own, and formatted before
+/// the next is read from the stream.
+///
+/// ```
+/// # use comrak::{markdown_to_html, Arena, ComrakOptions};
+/// use comrak::event::{self, Event};
+///
+/// let options = ComrakOptions::default();
+/// let arena = Arena::new();
+/// let shouted = comrak::events(&arena, "Hello, *world*!\n", &options).map(|event| match event {
+///     Event::Text(text) => Event::Text(text.to_ascii_uppercase()),
+///     event => event,
+/// });
+///
+/// let mut html = vec![];
+/// event::format_html(shouted, &options, &mut html).unwrap();
+/// assert_eq!(String::from_utf8(html).unwrap(), "<p>HELLO, <em>WORLD</em>!</p>\n");
+/// ```
+pub fn format_html<I>(events: I, options: &ComrakOptions, output: &mut dyn Write) -> io::Result<()>
+where
+    I: IntoIterator<Item = Event>,
+{
+    html::format_blocks(options, output, |format| for_each_block(events, format))
+}
+
+/// Formats a stream of events as CommonMark, modified by the given options.
+///
+/// As with `format_html`, the stream is built back into an AST and formatted a top-level block
+/// at a time.
+pub fn format_commonmark<I>(
+    events: I,
+    options: &ComrakOptions,
+    output: &mut dyn Write,
+) -> io::Result<()>
+where
+    I: IntoIterator<Item = Event>,
+{
+    cm::format_blocks(options, output, |format| for_each_block(events, format))
+}
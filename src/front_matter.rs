@@ -0,0 +1,338 @@
+//! Structured parsing of YAML and TOML front matter.
+//!
+//! Available with the `front-matter` feature.
+
+use nodes::{AstNode, NodeValue};
+use parser::{parse_document, ComrakOptions};
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use toml;
+use typed_arena::Arena;
+
+/// The format of a document's front matter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrontMatterFormat {
+    /// YAML, delimited by `---`.
+    Yaml,
+
+    /// TOML, delimited by `+++`.
+    Toml,
+}
+
+impl FrontMatterFormat {
+    fn from_delimiter(delimiter: &str) -> FrontMatterFormat {
+        if delimiter == "+++" {
+            FrontMatterFormat::Toml
+        } else {
+            FrontMatterFormat::Yaml
+        }
+    }
+}
+
+impl fmt::Display for FrontMatterFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrontMatterFormat::Yaml => f.write_str("YAML"),
+            FrontMatterFormat::Toml => f.write_str("TOML"),
+        }
+    }
+}
+
+/// A value in parsed front matter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrontMatterValue {
+    /// An empty value, such as YAML's `~`.
+    Null,
+
+    /// A boolean.
+    Bool(bool),
+
+    /// An integer.
+    Integer(i64),
+
+    /// A floating point number.
+    Float(f64),
+
+    /// A string.  TOML dates and times are also given as strings, as written.
+    String(String),
+
+    /// An array of values.
+    Array(Vec<FrontMatterValue>),
+
+    /// A table of keys to values.
+    Table(BTreeMap<String, FrontMatterValue>),
+}
+
+impl FrontMatterValue {
+    /// Looks up a key, if this value is a table.
+    pub fn get(&self, key: &str) -> Option<&FrontMatterValue> {
+        match *self {
+            FrontMatterValue::Table(ref table) => table.get(key),
+            _ => None,
+        }
+    }
+
+    /// The string this value holds, if it is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            FrontMatterValue::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// A document's parsed front matter.
+#[derive(Debug, Clone)]
+pub struct FrontMatter {
+    /// The format the front matter was written in.
+    pub format: FrontMatterFormat,
+
+    /// The front matter's keys and values.  This is always a `FrontMatterValue::Table`.
+    pub value: FrontMatterValue,
+}
+
+/// Front matter that could not be parsed.
+#[derive(Debug, Clone)]
+pub struct FrontMatterError {
+    /// The format the front matter was expected to be in.
+    pub format: FrontMatterFormat,
+
+    /// The line of the document the error was found on, if known.
+    pub line: Option<usize>,
+
+    /// A description of the error.
+    pub message: String,
+}
+
+impl fmt::Display for FrontMatterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid {} front matter", self.format)?;
+        if let Some(line) = self.line {
+            write!(f, " at line {}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl Error for FrontMatterError {}
+
+/// Parse a Markdown document to an AST, along with its front matter.
+///
+/// If `options.extension.front_matter_delimiter` is set, front matter using the given delimiter
+/// is read as TOML if the delimiter is `+++`, and as YAML otherwise.  The front matter result is
+/// then `Ok(None)` when there is none, and an error when it is malformed or never closed.
+///
+/// Otherwise, the delimiter is detected from the start of the document: `---` for YAML, or
+/// `+++` for TOML.  Since a document may just as well open with a `---` thematic break, front
+/// matter is only detected when it is closed and its content is a valid YAML mapping or TOML
+/// table; anything else is parsed as ordinary Markdown, with a result of `Ok(None)`.
+///
+/// ```
+/// # use comrak::{Arena, ComrakOptions};
+/// use comrak::front_matter::{parse_document_with_front_matter, FrontMatterFormat};
+///
+/// let arena = Arena::new();
+/// let (_root, front_matter) = parse_document_with_front_matter(
+///     &arena,
+///     "+++\ntitle = \"Hello\"\n+++\n\nText\n",
+///     &ComrakOptions::default(),
+/// );
+/// let front_matter = front_matter.unwrap().unwrap();
+/// assert_eq!(front_matter.format, FrontMatterFormat::Toml);
+/// assert_eq!(front_matter.value.get("title").and_then(|v| v.as_str()), Some("Hello"));
+///
+/// let mut options = ComrakOptions::default();
+/// options.extension.front_matter_delimiter = Some("---".to_string());
+/// let (_root, front_matter) = parse_document_with_front_matter(
+///     &arena,
+///     "---\ntitle: [unclosed\n---\n\nText\n",
+///     &options,
+/// );
+/// assert_eq!(front_matter.unwrap_err().format, FrontMatterFormat::Yaml);
+/// ```
+pub fn parse_document_with_front_matter<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    buffer: &str,
+    options: &ComrakOptions,
+) -> (
+    &'a AstNode<'a>,
+    Result<Option<FrontMatter>, FrontMatterError>,
+) {
+    let (delimiter, detected) = match options.extension.front_matter_delimiter {
+        Some(ref delimiter) => (delimiter.clone(), None),
+        None => match detect_front_matter(buffer) {
+            Some((delimiter, front_matter)) => (delimiter, Some(front_matter)),
+            None => return (parse_document(arena, buffer, options), Ok(None)),
+        },
+    };
+
+    let mut options = options.clone();
+    options.extension.front_matter_delimiter = Some(delimiter.clone());
+    let root = parse_document(arena, buffer, &options);
+    if let Some(front_matter) = detected {
+        return (root, Ok(Some(front_matter)));
+    }
+
+    let format = FrontMatterFormat::from_delimiter(&delimiter);
+
+    let raw = match root.first_child() {
+        Some(node) => match node.data.borrow().value {
+            NodeValue::FrontMatter(ref raw) => Some(String::from_utf8_lossy(raw).into_owned()),
+            _ => None,
+        },
+        None => None,
+    };
+
+    let result = match raw {
+        Some(raw) => parse_front_matter(format, body(&raw)).map(Some),
+        None if opens_with(buffer, &delimiter) => Err(FrontMatterError {
+            format,
+            line: Some(1),
+            message: format!("no closing `{}` delimiter", delimiter),
+        }),
+        None => Ok(None),
+    };
+
+    (root, result)
+}
+
+fn detect_front_matter(buffer: &str) -> Option<(String, FrontMatter)> {
+    ["---", "+++"].iter().find_map(|delimiter| {
+        let body = enclosed_body(buffer, delimiter)?;
+        if body.trim().is_empty() {
+            return None;
+        }
+        let format = FrontMatterFormat::from_delimiter(delimiter);
+        let front_matter = parse_front_matter(format, body).ok()?;
+        Some((delimiter.to_string(), front_matter))
+    })
+}
+
+// The text between the delimiter on the document's first line and the next line consisting only
+// of the delimiter, found as the parser finds front matter.
+fn enclosed_body<'b>(buffer: &'b str, delimiter: &str) -> Option<&'b str> {
+    if !opens_with(buffer, delimiter) {
+        return None;
+    }
+    let buffer = buffer.trim_start_matches('\u{feff}');
+    let start = buffer.find('\n')? + 1;
+
+    let mut end = start;
+    for line in buffer[start..].split_inclusive('\n') {
+        if let Some(line) = line.strip_suffix('\n') {
+            if line.strip_suffix('\r').unwrap_or(line) == delimiter {
+                return Some(&buffer[start..end]);
+            }
+        }
+        end += line.len();
+    }
+    None
+}
+
+fn opens_with(buffer: &str, delimiter: &str) -> bool {
+    let buffer = buffer.trim_start_matches('\u{feff}');
+    buffer.starts_with(delimiter)
+        && matches!(
+            buffer.as_bytes().get(delimiter.len()),
+            Some(b'\r') | Some(b'\n')
+        )
+}
+
+// The front matter between its delimiter lines.  The opening delimiter is always the first line
+// and the closing delimiter the last non-blank one.
+fn body(raw: &str) -> &str {
+    let start = raw.find('\n').map_or(raw.len(), |i| i + 1);
+    let end = raw.trim_end().rfind('\n').map_or(start, |i| i + 1);
+    &raw[start..end.max(start)]
+}
+
+fn parse_front_matter(
+    format: FrontMatterFormat,
+    body: &str,
+) -> Result<FrontMatter, FrontMatterError> {
+    // Lines reported by the YAML and TOML parsers are relative to the body, which starts on the
+    // document's second line.
+    let error = |line: Option<usize>, message: String| FrontMatterError {
+        format,
+        line: line.map(|line| line + 1),
+        message,
+    };
+
+    let value = match format {
+        FrontMatterFormat::Yaml => {
+            let value: serde_yaml::Value = serde_yaml::from_str(body)
+                .map_err(|e| error(e.location().map(|l| l.line()), e.to_string()))?;
+            match value {
+                serde_yaml::Value::Null => FrontMatterValue::Table(BTreeMap::new()),
+                serde_yaml::Value::Mapping(_) => from_yaml(value).map_err(|m| error(None, m))?,
+                _ => {
+                    return Err(error(
+                        Some(1),
+                        "expected a mapping of keys to values".into(),
+                    ))
+                }
+            }
+        }
+        FrontMatterFormat::Toml => {
+            let table: toml::Table = body.parse().map_err(|e: toml::de::Error| {
+                let line = e
+                    .span()
+                    .map(|span| body[..span.start].matches('\n').count() + 1);
+                error(line, e.message().to_string())
+            })?;
+            from_toml(toml::Value::Table(table))
+        }
+    };
+
+    Ok(FrontMatter { format, value })
+}
+
+fn from_yaml(value: serde_yaml::Value) -> Result<FrontMatterValue, String> {
+    Ok(match value {
+        serde_yaml::Value::Null => FrontMatterValue::Null,
+        serde_yaml::Value::Bool(b) => FrontMatterValue::Bool(b),
+        serde_yaml::Value::Number(n) => match n.as_i64() {
+            Some(i) => FrontMatterValue::Integer(i),
+            None => FrontMatterValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_yaml::Value::String(s) => FrontMatterValue::String(s),
+        serde_yaml::Value::Sequence(seq) => {
+            FrontMatterValue::Array(seq.into_iter().map(from_yaml).collect::<Result<_, _>>()?)
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut table = BTreeMap::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml::Value::String(s) => s,
+                    serde_yaml::Value::Bool(b) => b.to_string(),
+                    serde_yaml::Value::Number(n) => n.to_string(),
+                    _ => return Err("mapping keys must be strings, numbers or booleans".into()),
+                };
+                table.insert(key, from_yaml(value)?);
+            }
+            FrontMatterValue::Table(table)
+        }
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value)?,
+    })
+}
+
+fn from_toml(value: toml::Value) -> FrontMatterValue {
+    match value {
+        toml::Value::String(s) => FrontMatterValue::String(s),
+        toml::Value::Integer(i) => FrontMatterValue::Integer(i),
+        toml::Value::Float(f) => FrontMatterValue::Float(f),
+        toml::Value::Boolean(b) => FrontMatterValue::Bool(b),
+        toml::Value::Datetime(d) => FrontMatterValue::String(d.to_string()),
+        toml::Value::Array(array) => {
+            FrontMatterValue::Array(array.into_iter().map(from_toml).collect())
+        }
+        toml::Value::Table(table) => FrontMatterValue::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, from_toml(value)))
+                .collect(),
+        ),
+    }
+}
@@ -1,25 +1,40 @@
+//! The HTML renderer for the CommonMark AST, as well as helper functions.
+
+use adapters::UrlKind;
 use ctype::isspace;
 use nodes::{AstNode, ListType, NodeValue, TableAlignment};
-use parser::ComrakOptions;
+use parser::{ComrakOptions, ComrakPlugins};
 use regex::Regex;
+use sanitize::{RejectedUrl, UrlPolicy};
 use scanners;
 use std::borrow::Cow;
 use std::cell::Cell;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
 use std::str;
+use toc::{self, TocEntry};
 
 /// Formats an AST as HTML, modified by the given options.
 pub fn format_document<'a>(
     root: &'a AstNode<'a>,
     options: &ComrakOptions,
     output: &mut dyn Write,
+) -> io::Result<()> {
+    format_document_with_plugins(root, options, output, &ComrakPlugins::default())
+}
+
+/// Formats an AST as HTML, modified by the given options. Accepts custom plugins.
+pub fn format_document_with_plugins<'a>(
+    root: &'a AstNode<'a>,
+    options: &ComrakOptions,
+    output: &mut dyn Write,
+    plugins: &ComrakPlugins,
 ) -> io::Result<()> {
     let mut writer = WriteWithLast {
         output,
         last_was_lf: Cell::new(true),
     };
-    let mut f = HtmlFormatter::new(options, &mut writer);
+    let mut f = HtmlFormatter::new(options, &mut writer, plugins);
     f.format(root, false)?;
     if f.footnote_ix > 0 {
         f.output.write_all(b"</ol>\n</section>\n")?;
@@ -27,9 +42,33 @@ pub fn format_document<'a>(
     Ok(())
 }
 
-pub struct WriteWithLast<'w> {
+/// Formats a document given as a series of trees, each holding some of its top-level blocks, as
+/// HTML.  `blocks` is called with a function to format each tree in turn.  Used by
+/// `event::format_html`.
+pub(crate) fn format_blocks<F>(
+    options: &ComrakOptions,
+    output: &mut dyn Write,
+    blocks: F,
+) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn for<'a> FnMut(&'a AstNode<'a>) -> io::Result<()>) -> io::Result<()>,
+{
+    let mut writer = WriteWithLast {
+        output,
+        last_was_lf: Cell::new(true),
+    };
+    let plugins = ComrakPlugins::default();
+    let mut f = HtmlFormatter::new(options, &mut writer, &plugins);
+    blocks(&mut |root| f.format(root, false))?;
+    if f.footnote_ix > 0 {
+        f.output.write_all(b"</ol>\n</section>\n")?;
+    }
+    Ok(())
+}
+
+struct WriteWithLast<'w> {
     output: &'w mut dyn Write,
-    pub last_was_lf: Cell<bool>,
+    last_was_lf: Cell<bool>,
 }
 
 impl<'w> Write for WriteWithLast<'w> {
@@ -97,8 +136,13 @@ impl Anchorizer {
         id = REJECTED_CHARS.replace_all(&id, "").to_string();
         id = id.replace(' ', "-");
 
+        self.unique(id)
+    }
+
+    // Returns `id`, with a suffix added if needed to make it unique, and records it.
+    pub(crate) fn unique(&mut self, id: String) -> String {
         let mut uniq = 0;
-        id = loop {
+        let id = loop {
             let anchor = if uniq == 0 {
                 Cow::from(&*id)
             } else {
@@ -116,11 +160,106 @@ impl Anchorizer {
     }
 }
 
+/// Attributes added to links to other sites.  Set with the `external_links` render option.
+///
+/// A link is external if its URL is an `http` or `https` URL, or a protocol-relative one, whose
+/// host is neither one of the internal hosts nor a subdomain of one.  The URL is checked after
+/// any URL rewriter is applied.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ExternalLinks {
+    /// The hosts of the site itself, such as `example.com`.
+    pub internal_hosts: Vec<String>,
+
+    /// The `rel` attribute given to external links, such as `nofollow noopener ugc`.
+    pub rel: Option<String>,
+
+    /// The `target` attribute given to external links, such as `_blank`.
+    pub target: Option<String>,
+
+    /// The `class` attribute given to external links.
+    pub class: Option<String>,
+}
+
+impl ExternalLinks {
+    /// Whether a URL links to another site.
+    pub fn is_external(&self, url: &[u8]) -> bool {
+        let rest = if url.starts_with(b"//") {
+            &url[2..]
+        } else {
+            match url.iter().position(|&c| c == b':') {
+                Some(ix) if url[ix + 1..].starts_with(b"//") => {
+                    match &*url[..ix].to_ascii_lowercase() {
+                        b"http" | b"https" => &url[ix + 3..],
+                        _ => return false,
+                    }
+                }
+                _ => return false,
+            }
+        };
+
+        let authority = &rest[..rest
+            .iter()
+            .position(|&c| matches!(c, b'/' | b'?' | b'#'))
+            .unwrap_or(rest.len())];
+        let host = match authority.iter().rposition(|&c| c == b'@') {
+            Some(ix) => &authority[ix + 1..],
+            None => authority,
+        };
+        let host = match host.iter().rposition(|&c| c == b':') {
+            Some(ix) if !host.ends_with(b"]") => &host[..ix],
+            _ => host,
+        };
+        let host = String::from_utf8_lossy(host).to_lowercase();
+
+        !self.internal_hosts.iter().any(|internal| {
+            let internal = internal.to_lowercase();
+            host == internal
+                || (host.ends_with(&internal) && host[..host.len() - internal.len()].ends_with('.'))
+        })
+    }
+}
+
+pub(crate) fn collect_text<'a>(node: &'a AstNode<'a>, output: &mut Vec<u8>) {
+    match node.data.borrow().value {
+        NodeValue::Text(ref literal) | NodeValue::Code(ref literal) => {
+            output.extend_from_slice(literal)
+        }
+        NodeValue::Math(ref nm) => output.extend_from_slice(&nm.literal),
+        NodeValue::ShortCode(ref nsc) => {
+            if let Some(ref emoji) = nsc.emoji {
+                output.extend_from_slice(emoji.as_bytes())
+            }
+        }
+        NodeValue::LineBreak | NodeValue::SoftBreak => output.push(b' '),
+        _ => {
+            for n in node.children() {
+                collect_text(n, output);
+            }
+        }
+    }
+}
+
+// The anchor ID of a heading for the `header_ids` option, without its prefix: the ID given to it
+// with the `attributes` extension, or else one generated from its text.  Either is made unique
+// in the same way.
+pub(crate) fn heading_id<'a>(anchorizer: &mut Anchorizer, node: &'a AstNode<'a>) -> String {
+    if let Some(ref id) = node.data.borrow().attributes.id {
+        return anchorizer.unique(id.clone());
+    }
+
+    let mut text_content = Vec::with_capacity(20);
+    collect_text(node, &mut text_content);
+    anchorizer.anchorize(String::from_utf8(text_content).unwrap())
+}
+
 struct HtmlFormatter<'o> {
     output: &'o mut WriteWithLast<'o>,
     options: &'o ComrakOptions,
+    plugins: &'o ComrakPlugins<'o>,
     anchorizer: Anchorizer,
     footnote_ix: u32,
+    footnote_total_references: u32,
     written_footnote_ix: u32,
 }
 
@@ -160,6 +299,96 @@ const NEEDS_ESCAPED : [bool; 256] = [
     false, false, false, false, false, false, false, false,
 ];
 
+/// Writes `buffer` to `output`, escaping the characters which are significant in HTML
+/// text and attribute values.
+pub fn escape(output: &mut dyn Write, buffer: &[u8]) -> io::Result<()> {
+    let mut offset = 0;
+    for (i, &byte) in buffer.iter().enumerate() {
+        if NEEDS_ESCAPED[byte as usize] {
+            let esc: &[u8] = match byte {
+                b'"' => b"&quot;",
+                b'&' => b"&amp;",
+                b'<' => b"&lt;",
+                b'>' => b"&gt;",
+                _ => unreachable!(),
+            };
+            output.write_all(&buffer[offset..i])?;
+            output.write_all(esc)?;
+            offset = i + 1;
+        }
+    }
+    output.write_all(&buffer[offset..])?;
+    Ok(())
+}
+
+/// Writes `buffer` to `output` as a URL suitable for an `href` or `src` attribute,
+/// percent-encoding unsafe characters.
+pub fn escape_href(output: &mut dyn Write, buffer: &[u8]) -> io::Result<()> {
+    lazy_static! {
+        static ref HREF_SAFE: [bool; 256] = {
+            let mut a = [false; 256];
+            for &c in b"-_.+!*'(),%#@?=;:/,+&$~abcdefghijklmnopqrstuvwxyz".iter() {
+                a[c as usize] = true;
+            }
+            for &c in b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".iter() {
+                a[c as usize] = true;
+            }
+            a
+        };
+    }
+
+    let size = buffer.len();
+    let mut i = 0;
+
+    while i < size {
+        let org = i;
+        while i < size && HREF_SAFE[buffer[i] as usize] {
+            i += 1;
+        }
+
+        if i > org {
+            output.write_all(&buffer[org..i])?;
+        }
+
+        if i >= size {
+            break;
+        }
+
+        match buffer[i] as char {
+            '&' => {
+                output.write_all(b"&amp;")?;
+            }
+            '\'' => {
+                output.write_all(b"&#x27;")?;
+            }
+            _ => write!(output, "%{:02X}", buffer[i])?,
+        }
+
+        i += 1;
+    }
+
+    Ok(())
+}
+
+/// Writes an opening HTML tag, with the given attributes in name order and their values
+/// escaped.
+pub fn write_opening_tag(
+    output: &mut dyn Write,
+    tag: &str,
+    attributes: &HashMap<String, String>,
+) -> io::Result<()> {
+    let mut names = attributes.keys().collect::<Vec<_>>();
+    names.sort();
+
+    write!(output, "<{}", tag)?;
+    for name in names {
+        write!(output, " {}=\"", name)?;
+        escape(output, attributes[name].as_bytes())?;
+        output.write_all(b"\"")?;
+    }
+    output.write_all(b">")
+}
+
 fn tagfilter(literal: &[u8]) -> bool {
     lazy_static! {
         static ref TAGFILTER_BLACKLIST: [&'static str; 9] = [
@@ -229,17 +458,44 @@ fn tagfilter_block(input: &[u8], o: &mut dyn Write) -> io::Result<()> {
     Ok(())
 }
 
+// The kind of URL a link has, for the URL rewriter.
+
 fn dangerous_url(input: &[u8]) -> bool {
     scanners::dangerous_url(input).is_some()
 }
 
+// Whether an attribute given with the `attributes` extension could run script or load a URL,
+// and so is only rendered with `unsafe_`.  `name` is lowercase.
+fn unsafe_attribute(name: &str) -> bool {
+    const URL_ATTRIBUTES: [&str; 10] = [
+        "href",
+        "src",
+        "srcset",
+        "action",
+        "formaction",
+        "poster",
+        "background",
+        "cite",
+        "data",
+        "xlink:href",
+    ];
+
+    name.starts_with("on") || name == "style" || URL_ATTRIBUTES.contains(&name)
+}
+
 impl<'o> HtmlFormatter<'o> {
-    fn new(options: &'o ComrakOptions, output: &'o mut WriteWithLast<'o>) -> Self {
+    fn new(
+        options: &'o ComrakOptions,
+        output: &'o mut WriteWithLast<'o>,
+        plugins: &'o ComrakPlugins,
+    ) -> Self {
         HtmlFormatter {
             options,
             output,
+            plugins,
             anchorizer: Anchorizer::new(),
             footnote_ix: 0,
+            footnote_total_references: 0,
             written_footnote_ix: 0,
         }
     }
@@ -252,72 +508,153 @@ impl<'o> HtmlFormatter<'o> {
     }
 
     fn escape(&mut self, buffer: &[u8]) -> io::Result<()> {
-        let mut offset = 0;
-        for (i, &byte) in buffer.iter().enumerate() {
-            if NEEDS_ESCAPED[byte as usize] {
-                let esc: &[u8] = match byte {
-                    b'"' => b"&quot;",
-                    b'&' => b"&amp;",
-                    b'<' => b"&lt;",
-                    b'>' => b"&gt;",
-                    _ => unreachable!(),
-                };
-                self.output.write_all(&buffer[offset..i])?;
-                self.output.write_all(esc)?;
-                offset = i + 1;
-            }
-        }
-        self.output.write_all(&buffer[offset..])?;
-        Ok(())
+        escape(self.output, buffer)
     }
 
     fn escape_href(&mut self, buffer: &[u8]) -> io::Result<()> {
-        lazy_static! {
-            static ref HREF_SAFE: [bool; 256] = {
-                let mut a = [false; 256];
-                for &c in b"-_.+!*'(),%#@?=;:/,+&$~abcdefghijklmnopqrstuvwxyz".iter() {
-                    a[c as usize] = true;
-                }
-                for &c in b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789".iter() {
-                    a[c as usize] = true;
-                }
-                a
-            };
-        }
+        escape_href(self.output, buffer)
+    }
 
-        let size = buffer.len();
-        let mut i = 0;
+    // Whether links and images with rejected URLs are rendered as their text alone.  Otherwise,
+    // a URL only matters when entering its node.
+    fn rejected_as_text(&self) -> bool {
+        match self.options.render.url_policy {
+            Some(ref policy) => policy.rejected == RejectedUrl::TextOnly,
+            None => false,
+        }
+    }
 
-        while i < size {
-            let org = i;
-            while i < size && HREF_SAFE[buffer[i] as usize] {
-                i += 1;
+    // The URL rendered for a link or image, after any URL rewriter.
+    fn rendered_url<'u>(&self, url: &'u [u8], kind: UrlKind, entering: bool) -> Cow<'u, [u8]> {
+        match self.plugins.render.url_rewriter {
+            Some(rewriter) if entering || self.rejected_as_text() => {
+                Cow::from(rewriter.rewrite(url, kind))
             }
+            _ => Cow::from(url),
+        }
+    }
 
-            if i > org {
-                self.output.write_all(&buffer[org..i])?;
+    // Whether a link or image URL may be rendered: it must not be dangerous unless `unsafe_` is
+    // set, and must be allowed by the URL policy if there is one.
+    fn url_allowed(&self, url: &[u8]) -> bool {
+        (self.options.render.unsafe_ || !dangerous_url(url))
+            && match self.options.render.url_policy {
+                Some(ref policy) => policy.allows(url),
+                None => true,
             }
+    }
 
-            if i >= size {
-                break;
-            }
+    // Whether a link or image is rendered as its text alone, its URL having been rejected.
+    fn text_only(&self, url: &[u8]) -> bool {
+        self.rejected_as_text() && !self.url_allowed(url)
+    }
 
-            match buffer[i] as char {
-                '&' => {
-                    self.output.write_all(b"&amp;")?;
-                }
-                '\'' => {
-                    self.output.write_all(b"&#x27;")?;
-                }
-                _ => write!(self.output, "%{:02X}", buffer[i])?,
-            }
+    fn write_url(&mut self, url: &[u8]) -> io::Result<()> {
+        let options = self.options;
+        if self.url_allowed(url) {
+            self.escape_href(url)
+        } else if let Some(UrlPolicy {
+            rejected: RejectedUrl::Placeholder(ref placeholder),
+            ..
+        }) = options.render.url_policy
+        {
+            self.escape_href(placeholder.as_bytes())
+        } else {
+            Ok(())
+        }
+    }
 
-            i += 1;
+    // The attributes of an external link, if the URL is one.
+    fn external_link_attributes(&self, url: &[u8]) -> Vec<(&'static str, &'o str)> {
+        let options = self.options;
+        let external = match options.render.external_links {
+            Some(ref external) if self.url_allowed(url) && external.is_external(url) => external,
+            _ => return vec![],
+        };
+
+        let attributes = [
+            ("rel", &external.rel),
+            ("target", &external.target),
+            ("class", &external.class),
+        ];
+        attributes
+            .iter()
+            .filter_map(|&(name, value)| value.as_ref().map(|value| (name, value.as_str())))
+            .collect()
+    }
+
+    // Writes the attributes of an external link, if the URL is one, followed by those given to
+    // the link with the `attributes` extension.
+    fn render_link_attributes<'a>(
+        &mut self,
+        node: &'a AstNode<'a>,
+        url: &[u8],
+        taken: &[&str],
+    ) -> io::Result<()> {
+        let external = self.external_link_attributes(url);
+        for &(name, value) in &external {
+            write!(self.output, " {}=\"", name)?;
+            self.escape(value.as_bytes())?;
+            self.output.write_all(b"\"")?;
         }
 
+        let mut taken = taken.to_vec();
+        taken.extend(external.iter().map(|&(name, _)| name));
+        self.render_attributes(node, &taken)
+    }
+
+    // Writes the attributes given to a node with the `attributes` extension, skipping those the
+    // element already has.
+    fn render_attributes<'a>(&mut self, node: &'a AstNode<'a>, taken: &[&str]) -> io::Result<()> {
+        for (name, value) in self.node_attributes(node, taken) {
+            write!(self.output, " {}=\"", name)?;
+            self.escape(value.as_bytes())?;
+            self.output.write_all(b"\"")?;
+        }
         Ok(())
     }
 
+    // The attributes given to a node with the `attributes` extension which may be rendered,
+    // skipping those named in `taken`.  Event handlers, `style` and URL attributes are only
+    // rendered with `unsafe_`.
+    fn node_attributes<'a>(&self, node: &'a AstNode<'a>, taken: &[&str]) -> Vec<(String, String)> {
+        let ast = node.data.borrow();
+        let attributes = &ast.attributes;
+        if attributes.is_empty() {
+            return vec![];
+        }
+
+        let mut all = vec![];
+        if let Some(ref id) = attributes.id {
+            all.push(("id".to_string(), id.clone()));
+        }
+        if !attributes.classes.is_empty() {
+            all.push(("class".to_string(), attributes.classes.join(" ")));
+        }
+        all.extend(attributes.pairs.iter().cloned());
+
+        let mut written = taken
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        if self.options.render.sourcepos {
+            written.push("data-sourcepos".to_string());
+        }
+
+        let mut rendered = vec![];
+        for (name, value) in all {
+            let lowercase = name.to_ascii_lowercase();
+            if written.contains(&lowercase)
+                || (!self.options.render.unsafe_ && unsafe_attribute(&lowercase))
+            {
+                continue;
+            }
+            written.push(lowercase);
+            rendered.push((name, value));
+        }
+        rendered
+    }
+
     fn format<'a>(&mut self, node: &'a AstNode<'a>, plain: bool) -> io::Result<()> {
         // Traverse the AST iteratively using a work stack, with pre- and
         // post-child-traversal phases. During pre-order traversal render the
@@ -342,6 +679,14 @@ impl<'o> HtmlFormatter<'o> {
                             | NodeValue::HtmlInline(ref literal) => {
                                 self.escape(literal)?;
                             }
+                            NodeValue::Math(ref nm) => {
+                                self.escape(&nm.literal)?;
+                            }
+                            NodeValue::ShortCode(ref nsc) => {
+                                if let Some(ref emoji) = nsc.emoji {
+                                    self.escape(emoji.as_bytes())?;
+                                }
+                            }
                             NodeValue::LineBreak | NodeValue::SoftBreak => {
                                 self.output.write_all(b" ")?;
                             }
@@ -367,43 +712,84 @@ impl<'o> HtmlFormatter<'o> {
         Ok(())
     }
 
-    fn collect_text<'a>(&self, node: &'a AstNode<'a>, output: &mut Vec<u8>) {
-        match node.data.borrow().value {
-            NodeValue::Text(ref literal) | NodeValue::Code(ref literal) => {
-                output.extend_from_slice(literal)
-            }
-            NodeValue::LineBreak | NodeValue::SoftBreak => output.push(b' '),
-            _ => {
-                for n in node.children() {
-                    self.collect_text(n, output);
-                }
+    fn format_node<'a>(&mut self, node: &'a AstNode<'a>, entering: bool) -> io::Result<bool> {
+        if let Some(renderer) = self.plugins.render.node_renderer {
+            let fallback = if entering {
+                renderer.enter(self.output, node, self.options)?
+            } else {
+                renderer.exit(self.output, node, self.options)?
+            };
+            if !fallback {
+                return Ok(entering && matches!(node.data.borrow().value, NodeValue::Image(..)));
             }
         }
-    }
 
-    fn format_node<'a>(&mut self, node: &'a AstNode<'a>, entering: bool) -> io::Result<bool> {
         match node.data.borrow().value {
             NodeValue::Document => (),
             NodeValue::FrontMatter(_) => (),
             NodeValue::BlockQuote => {
                 if entering {
                     self.cr()?;
-                    self.output.write_all(b"<blockquote>\n")?;
+                    self.output.write_all(b"<blockquote")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">\n")?;
                 } else {
                     self.cr()?;
                     self.output.write_all(b"</blockquote>\n")?;
                 }
             }
+            NodeValue::Alert(ref na) => {
+                if entering {
+                    self.cr()?;
+                    write!(
+                        self.output,
+                        "<div class=\"markdown-alert {}\"",
+                        na.kind.css_class()
+                    )?;
+                    self.render_sourcepos(node)?;
+                    self.output
+                        .write_all(b">\n<p class=\"markdown-alert-title\">")?;
+                    match na.title {
+                        Some(ref title) => self.escape(title)?,
+                        None => self.output.write_all(na.kind.default_title().as_bytes())?,
+                    }
+                    self.output.write_all(b"</p>\n")?;
+                } else {
+                    self.cr()?;
+                    self.output.write_all(b"</div>\n")?;
+                }
+            }
+            NodeValue::Container(ref nc) => {
+                self.cr()?;
+                let fallback = match self.plugins.render.container_renderer {
+                    Some(renderer) if entering => renderer.enter(self.output, nc)?,
+                    Some(renderer) => renderer.exit(self.output, nc)?,
+                    None => true,
+                };
+                if !fallback {
+                    // The plugin has rendered this half of the container.
+                } else if entering {
+                    self.output.write_all(b"<div class=\"")?;
+                    self.escape(&nc.name)?;
+                    self.output.write_all(b"\"")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">\n")?;
+                } else {
+                    self.output.write_all(b"</div>\n")?;
+                }
+            }
             NodeValue::List(ref nl) => {
                 if entering {
                     self.cr()?;
                     if nl.list_type == ListType::Bullet {
-                        self.output.write_all(b"<ul>\n")?;
+                        self.output.write_all(b"<ul")?;
                     } else if nl.start == 1 {
-                        self.output.write_all(b"<ol>\n")?;
+                        self.output.write_all(b"<ol")?;
                     } else {
-                        writeln!(self.output, "<ol start=\"{}\">", nl.start)?;
+                        write!(self.output, "<ol start=\"{}\"", nl.start)?;
                     }
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">\n")?;
                 } else if nl.list_type == ListType::Bullet {
                     self.output.write_all(b"</ul>\n")?;
                 } else {
@@ -413,7 +799,9 @@ impl<'o> HtmlFormatter<'o> {
             NodeValue::Item(..) => {
                 if entering {
                     self.cr()?;
-                    self.output.write_all(b"<li>")?;
+                    self.output.write_all(b"<li")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">")?;
                 } else {
                     self.output.write_all(b"</li>\n")?;
                 }
@@ -421,7 +809,9 @@ impl<'o> HtmlFormatter<'o> {
             NodeValue::DescriptionList => {
                 if entering {
                     self.cr()?;
-                    self.output.write_all(b"<dl>")?;
+                    self.output.write_all(b"<dl")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">")?;
                 } else {
                     self.output.write_all(b"</dl>\n")?;
                 }
@@ -429,14 +819,18 @@ impl<'o> HtmlFormatter<'o> {
             NodeValue::DescriptionItem(..) => (),
             NodeValue::DescriptionTerm => {
                 if entering {
-                    self.output.write_all(b"<dt>")?;
+                    self.output.write_all(b"<dt")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">")?;
                 } else {
                     self.output.write_all(b"</dt>\n")?;
                 }
             }
             NodeValue::DescriptionDetails => {
                 if entering {
-                    self.output.write_all(b"<dd>")?;
+                    self.output.write_all(b"<dd")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">")?;
                 } else {
                     self.output.write_all(b"</dd>\n")?;
                 }
@@ -444,14 +838,18 @@ impl<'o> HtmlFormatter<'o> {
             NodeValue::Heading(ref nch) => {
                 if entering {
                     self.cr()?;
-                    write!(self.output, "<h{}>", nch.level)?;
+                    write!(self.output, "<h{}", nch.level)?;
+                    self.render_sourcepos(node)?;
+                    if self.options.extension.header_ids.is_some() {
+                        // The ID is given to the anchor instead.
+                        self.render_attributes(node, &["id"])?;
+                    } else {
+                        self.render_attributes(node, &[])?;
+                    }
+                    self.output.write_all(b">")?;
 
                     if let Some(ref prefix) = self.options.extension.header_ids {
-                        let mut text_content = Vec::with_capacity(20);
-                        self.collect_text(node, &mut text_content);
-
-                        let mut id = String::from_utf8(text_content).unwrap();
-                        id = self.anchorizer.anchorize(id);
+                        let id = heading_id(&mut self.anchorizer, node);
                         write!(
                             self.output,
                             "<a href=\"#{}\" aria-hidden=\"true\" class=\"anchor\" id=\"{}{}\"></a>",
@@ -468,8 +866,52 @@ impl<'o> HtmlFormatter<'o> {
                 if entering {
                     self.cr()?;
 
+                    if let Some(highlighter) = self.plugins.render.codefence_syntax_highlighter {
+                        let mut first_tag = 0;
+                        while first_tag < ncb.info.len() && !isspace(ncb.info[first_tag]) {
+                            first_tag += 1;
+                        }
+                        let lang = String::from_utf8_lossy(&ncb.info[..first_tag]);
+
+                        let mut pre_attributes = HashMap::new();
+                        let mut code_attributes = HashMap::new();
+                        if !lang.is_empty() {
+                            if self.options.render.github_pre_lang {
+                                pre_attributes.insert("lang".to_string(), lang.to_string());
+                            } else {
+                                code_attributes
+                                    .insert("class".to_string(), format!("language-{}", lang));
+                            }
+                        }
+                        if self.options.render.sourcepos {
+                            let ast = node.data.borrow();
+                            if ast.sourcepos.start.line > 0 {
+                                pre_attributes.insert(
+                                    "data-sourcepos".to_string(),
+                                    ast.sourcepos.to_string(),
+                                );
+                            }
+                        }
+                        for (name, value) in self.node_attributes(node, &["lang"]) {
+                            pre_attributes.insert(name, value);
+                        }
+
+                        highlighter.write_pre_tag(self.output, pre_attributes)?;
+                        highlighter.write_code_tag(self.output, code_attributes)?;
+                        highlighter.write_highlighted(
+                            self.output,
+                            if lang.is_empty() { None } else { Some(&lang) },
+                            &String::from_utf8_lossy(&ncb.literal),
+                        )?;
+                        self.output.write_all(b"</code></pre>\n")?;
+                        return Ok(false);
+                    }
+
                     if ncb.info.is_empty() {
-                        self.output.write_all(b"<pre><code>")?;
+                        self.output.write_all(b"<pre")?;
+                        self.render_sourcepos(node)?;
+                        self.render_attributes(node, &[])?;
+                        self.output.write_all(b"><code>")?;
                     } else {
                         let mut first_tag = 0;
                         while first_tag < ncb.info.len() && !isspace(ncb.info[first_tag]) {
@@ -478,15 +920,21 @@ impl<'o> HtmlFormatter<'o> {
 
                         if self.options.render.github_pre_lang {
                             self.output.write_all(b"<pre lang=\"")?;
-                            self.output.write_all(&ncb.info[..first_tag])?;
-                            self.output.write_all(b"\"><code>")?;
+                            self.escape(&ncb.info[..first_tag])?;
+                            self.output.write_all(b"\"")?;
+                            self.render_sourcepos(node)?;
+                            self.render_attributes(node, &["lang"])?;
+                            self.output.write_all(b"><code>")?;
                         } else {
-                            self.output.write_all(b"<pre><code class=\"language-")?;
-                            self.output.write_all(&ncb.info[..first_tag])?;
+                            self.output.write_all(b"<pre")?;
+                            self.render_sourcepos(node)?;
+                            self.render_attributes(node, &[])?;
+                            self.output.write_all(b"><code class=\"language-")?;
+                            self.escape(&ncb.info[..first_tag])?;
                             self.output.write_all(b"\">")?;
                         }
                     }
-                    self.output.write_all(&ncb.literal)?;
+                    self.escape(&ncb.literal)?;
                     self.output.write_all(b"</code></pre>\n")?;
                 }
             }
@@ -495,6 +943,8 @@ impl<'o> HtmlFormatter<'o> {
                     self.cr()?;
                     if self.options.render.escape {
                         self.escape(&nhb.literal)?;
+                    } else if let Some(ref sanitizer) = self.options.render.sanitize {
+                        sanitizer.clean(&nhb.literal, &mut self.output)?;
                     } else if !self.options.render.unsafe_ {
                         self.output.write_all(b"<!-- raw HTML omitted -->")?;
                     } else if self.options.extension.tagfilter {
@@ -505,10 +955,26 @@ impl<'o> HtmlFormatter<'o> {
                     self.cr()?;
                 }
             }
+            NodeValue::TableOfContents => {
+                if entering {
+                    self.cr()?;
+                    self.output.write_all(b"<nav class=\"table-of-contents\"")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">\n")?;
+                    let root = node.ancestors().last().unwrap();
+                    let entries = toc::build(root, self.options);
+                    if !entries.is_empty() {
+                        self.format_toc(&entries)?;
+                    }
+                    self.output.write_all(b"</nav>\n")?;
+                }
+            }
             NodeValue::ThematicBreak => {
                 if entering {
                     self.cr()?;
-                    self.output.write_all(b"<hr />\n")?;
+                    self.output.write_all(b"<hr")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b" />\n")?;
                 }
             }
             NodeValue::Paragraph => {
@@ -530,7 +996,9 @@ impl<'o> HtmlFormatter<'o> {
                 if !tight {
                     if entering {
                         self.cr()?;
-                        self.output.write_all(b"<p>")?;
+                        self.output.write_all(b"<p")?;
+                        self.render_sourcepos(node)?;
+                        self.output.write_all(b">")?;
                     } else {
                         if matches!(
                             node.parent().unwrap().data.borrow().value,
@@ -574,6 +1042,8 @@ impl<'o> HtmlFormatter<'o> {
                 if entering {
                     if self.options.render.escape {
                         self.escape(&literal)?;
+                    } else if let Some(ref sanitizer) = self.options.render.sanitize {
+                        sanitizer.clean(literal, &mut self.output)?;
                     } else if !self.options.render.unsafe_ {
                         self.output.write_all(b"<!-- raw HTML omitted -->")?;
                     } else if self.options.extension.tagfilter && tagfilter(literal) {
@@ -612,27 +1082,129 @@ impl<'o> HtmlFormatter<'o> {
                     self.output.write_all(b"</sup>")?;
                 }
             }
-            NodeValue::Link(ref nl) => {
+            NodeValue::Highlight => {
                 if entering {
+                    self.output.write_all(b"<mark>")?;
+                } else {
+                    self.output.write_all(b"</mark>")?;
+                }
+            }
+            NodeValue::Insert => {
+                if entering {
+                    self.output.write_all(b"<ins>")?;
+                } else {
+                    self.output.write_all(b"</ins>")?;
+                }
+            }
+            NodeValue::Subscript => {
+                if entering {
+                    self.output.write_all(b"<sub>")?;
+                } else {
+                    self.output.write_all(b"</sub>")?;
+                }
+            }
+            NodeValue::SpoileredText => {
+                if entering {
+                    self.output.write_all(b"<span class=\"spoiler\">")?;
+                } else {
+                    self.output.write_all(b"</span>")?;
+                }
+            }
+            NodeValue::Underline => {
+                if entering {
+                    self.output.write_all(b"<u>")?;
+                } else {
+                    self.output.write_all(b"</u>")?;
+                }
+            }
+            NodeValue::Link(ref nl) => {
+                let kind = if nl.autolink {
+                    UrlKind::Autolink
+                } else {
+                    UrlKind::Link
+                };
+                let url = self.rendered_url(&nl.url, kind, entering);
+                if self.text_only(&url) {
+                    // Only the link's text is rendered.
+                } else if entering {
                     self.output.write_all(b"<a href=\"")?;
-                    if self.options.render.unsafe_ || !dangerous_url(&nl.url) {
-                        self.escape_href(&nl.url)?;
-                    }
+                    self.write_url(&url)?;
                     if !nl.title.is_empty() {
                         self.output.write_all(b"\" title=\"")?;
                         self.escape(&nl.title)?;
                     }
-                    self.output.write_all(b"\">")?;
+                    self.output.write_all(b"\"")?;
+                    self.render_link_attributes(node, &url, &["href", "title"])?;
+                    self.output.write_all(b">")?;
                 } else {
                     self.output.write_all(b"</a>")?;
                 }
             }
-            NodeValue::Image(ref nl) => {
+            NodeValue::WikiLink(ref nl) => {
+                let resolved = match self.plugins.render.wikilink_resolver {
+                    Some(resolver) if entering || self.rejected_as_text() => {
+                        Cow::from(resolver.resolve(&nl.url))
+                    }
+                    _ => Cow::from(&nl.url[..]),
+                };
+                let url = self.rendered_url(&resolved, UrlKind::WikiLink, entering);
+                if self.text_only(&url) {
+                    // Only the link's text is rendered.
+                } else if entering {
+                    self.output.write_all(b"<a href=\"")?;
+                    self.write_url(&url)?;
+                    self.output.write_all(b"\" data-wikilink=\"true\"")?;
+                    self.render_link_attributes(node, &url, &["href", "data-wikilink"])?;
+                    self.output.write_all(b">")?;
+                } else {
+                    self.output.write_all(b"</a>")?;
+                }
+            }
+            NodeValue::ShortCode(ref nsc) => {
                 if entering {
-                    self.output.write_all(b"<img src=\"")?;
-                    if self.options.render.unsafe_ || !dangerous_url(&nl.url) {
-                        self.escape_href(&nl.url)?;
+                    match nsc.emoji {
+                        Some(ref emoji) => self.output.write_all(emoji.as_bytes())?,
+                        None => {
+                            let custom = &self.options.extension.custom_shortcodes;
+                            match custom.get(&nsc.code) {
+                                Some(html) => self.output.write_all(html.as_bytes())?,
+                                None => write!(self.output, ":{}:", nsc.code)?,
+                            }
+                        }
                     }
+                }
+            }
+            NodeValue::Math(ref nm) => {
+                if entering && nm.fenced {
+                    self.cr()?;
+                    self.output.write_all(b"<pre")?;
+                    self.render_sourcepos(node)?;
+                    self.render_attributes(node, &[])?;
+                    self.output.write_all(
+                        b"><code class=\"language-math\" data-math-style=\"display\">",
+                    )?;
+                    self.escape(&nm.literal)?;
+                    self.output.write_all(b"</code></pre>\n")?;
+                } else if entering {
+                    self.output.write_all(b"<span")?;
+                    self.render_sourcepos(node)?;
+                    if nm.display {
+                        self.output.write_all(b" data-math-style=\"display\">")?;
+                    } else {
+                        self.output.write_all(b" data-math-style=\"inline\">")?;
+                    }
+                    self.escape(&nm.literal)?;
+                    self.output.write_all(b"</span>")?;
+                }
+            }
+            NodeValue::Image(ref nl) => {
+                let url = self.rendered_url(&nl.url, UrlKind::Image, entering);
+                if self.text_only(&url) {
+                    // Only the image's alt text is rendered.
+                    return Ok(entering);
+                } else if entering {
+                    self.output.write_all(b"<img src=\"")?;
+                    self.write_url(&url)?;
                     self.output.write_all(b"\" alt=\"")?;
                     return Ok(true);
                 } else {
@@ -640,13 +1212,17 @@ impl<'o> HtmlFormatter<'o> {
                         self.output.write_all(b"\" title=\"")?;
                         self.escape(&nl.title)?;
                     }
-                    self.output.write_all(b"\" />")?;
+                    self.output.write_all(b"\"")?;
+                    self.render_attributes(node, &["src", "alt", "title"])?;
+                    self.output.write_all(b" />")?;
                 }
             }
             NodeValue::Table(..) => {
                 if entering {
                     self.cr()?;
-                    self.output.write_all(b"<table>\n")?;
+                    self.output.write_all(b"<table")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">\n")?;
                 } else {
                     if !node
                         .last_child()
@@ -670,7 +1246,9 @@ impl<'o> HtmlFormatter<'o> {
                             self.output.write_all(b"<tbody>\n")?;
                         }
                     }
-                    self.output.write_all(b"<tr>")?;
+                    self.output.write_all(b"<tr")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">")?;
                 } else {
                     self.cr()?;
                     self.output.write_all(b"</tr>")?;
@@ -721,6 +1299,8 @@ impl<'o> HtmlFormatter<'o> {
                         TableAlignment::None => (),
                     }
 
+                    self.render_sourcepos(node)?;
+
                     self.output.write_all(b">")?;
                 } else if in_header {
                     self.output.write_all(b"</th>")?;
@@ -728,14 +1308,20 @@ impl<'o> HtmlFormatter<'o> {
                     self.output.write_all(b"</td>")?;
                 }
             }
-            NodeValue::FootnoteDefinition(_) => {
+            NodeValue::FootnoteDefinition(ref nfd) => {
                 if entering {
                     if self.footnote_ix == 0 {
                         self.output
                             .write_all(b"<section class=\"footnotes\">\n<ol>\n")?;
                     }
                     self.footnote_ix += 1;
-                    writeln!(self.output, "<li id=\"fn{}\">", self.footnote_ix)?;
+                    self.footnote_total_references = nfd.total_references;
+                    self.output.write_all(b"<li id=\"")?;
+                    let ix = self.footnote_ix;
+                    self.write_footnote_id(false, "fn", ix, 1)?;
+                    self.output.write_all(b"\"")?;
+                    self.render_sourcepos(node)?;
+                    self.output.write_all(b">\n")?;
                 } else {
                     if self.put_footnote_backref()? {
                         self.output.write_all(b"\n")?;
@@ -743,14 +1329,14 @@ impl<'o> HtmlFormatter<'o> {
                     self.output.write_all(b"</li>\n")?;
                 }
             }
-            NodeValue::FootnoteReference(ref r) => {
+            NodeValue::FootnoteReference(ref nfr) => {
                 if entering {
-                    let r = str::from_utf8(r).unwrap();
-                    write!(
-                        self.output,
-                        "<sup class=\"footnote-ref\"><a href=\"#fn{}\" id=\"fnref{}\">{}</a></sup>",
-                        r, r, r
-                    )?;
+                    self.output
+                        .write_all(b"<sup class=\"footnote-ref\"><a href=\"")?;
+                    self.write_footnote_id(true, "fn", nfr.ix, 1)?;
+                    self.output.write_all(b"\" id=\"")?;
+                    self.write_footnote_id(false, "fnref", nfr.ix, nfr.ref_num)?;
+                    write!(self.output, "\">{}</a></sup>", nfr.ix)?;
                 }
             }
             NodeValue::TaskItem(checked) => {
@@ -769,17 +1355,82 @@ impl<'o> HtmlFormatter<'o> {
         Ok(false)
     }
 
+    fn render_sourcepos<'a>(&mut self, node: &'a AstNode<'a>) -> io::Result<()> {
+        if self.options.render.sourcepos {
+            let ast = node.data.borrow();
+            if ast.sourcepos.start.line > 0 {
+                write!(self.output, " data-sourcepos=\"{}\"", ast.sourcepos)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn format_toc(&mut self, entries: &[TocEntry]) -> io::Result<()> {
+        self.output.write_all(b"<ul>\n")?;
+        for entry in entries {
+            self.output.write_all(b"<li><a href=\"#")?;
+            self.escape_href(entry.id.as_bytes())?;
+            self.output.write_all(b"\">")?;
+            self.escape(entry.text.as_bytes())?;
+            self.output.write_all(b"</a>")?;
+            if !entry.children.is_empty() {
+                self.output.write_all(b"\n")?;
+                self.format_toc(&entry.children)?;
+            }
+            self.output.write_all(b"</li>\n")?;
+        }
+        self.output.write_all(b"</ul>\n")
+    }
+
     fn put_footnote_backref(&mut self) -> io::Result<bool> {
         if self.written_footnote_ix >= self.footnote_ix {
             return Ok(false);
         }
 
         self.written_footnote_ix = self.footnote_ix;
-        write!(
-            self.output,
-            "<a href=\"#fnref{}\" class=\"footnote-backref\">↩</a>",
-            self.footnote_ix
-        )?;
+        let ix = self.footnote_ix;
+        self.output.write_all(b"<a href=\"")?;
+        self.write_footnote_id(true, "fnref", ix, 1)?;
+        self.output
+            .write_all("\" class=\"footnote-backref\">↩</a>".as_bytes())?;
+        // Subsequent references to the same footnote get numbered back-links, as GitHub does.
+        for ref_num in 2..=self.footnote_total_references {
+            self.output.write_all(b" <a href=\"")?;
+            self.write_footnote_id(true, "fnref", ix, ref_num)?;
+            write!(
+                self.output,
+                "\" class=\"footnote-backref\">↩<sup class=\"footnote-ref\">{}</sup></a>",
+                ref_num
+            )?;
+        }
         Ok(true)
     }
+
+    // Writes the ID of a footnote (`kind` "fn") or footnote reference ("fnref") with the
+    // configured prefix, escaped for an `id` attribute or, as a fragment, for an `href`.
+    fn write_footnote_id(
+        &mut self,
+        href: bool,
+        kind: &str,
+        ix: u32,
+        ref_num: u32,
+    ) -> io::Result<()> {
+        let prefix = self
+            .options
+            .render
+            .footnote_id_prefix
+            .as_ref()
+            .map_or(&[][..], |p| p.as_bytes());
+        if href {
+            self.output.write_all(b"#")?;
+            self.escape_href(prefix)?;
+        } else {
+            self.escape(prefix)?;
+        }
+        write!(self.output, "{}{}", kind, ix)?;
+        if ref_num > 1 {
+            write!(self.output, "-{}", ref_num)?;
+        }
+        Ok(())
+    }
 }
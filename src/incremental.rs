@@ -0,0 +1,308 @@
+//! Incremental re-parsing of a document as it is edited.
+//!
+//! An `IncrementalDocument` keeps the text of a document alongside its AST.  Each edit re-parses
+//! only the top-level blocks around the edited text, reusing the rest of the tree as it is, and
+//! reports the top-level nodes which were replaced.
+//!
+//! Nodes are never freed from an `Arena`, so the arena grows by the re-parsed blocks with each
+//! edit.  With footnotes enabled every edit re-parses the whole document, since footnote numbering
+//! depends on all of it.
+
+use nodes::{AstNode, NodeValue};
+use parser::{parse_blocks_from, parse_document_with_refmap, ComrakOptions, Reference};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use typed_arena::Arena;
+
+/// A parsed document which can be edited and incrementally re-parsed.
+///
+/// ```
+/// # use comrak::{format_html, Arena, ComrakOptions};
+/// use comrak::incremental::IncrementalDocument;
+///
+/// let arena = Arena::new();
+/// let mut doc = IncrementalDocument::new(&arena, "# Title\n\nOne.\n\nTwo.\n", &ComrakOptions::default());
+/// let unchanged = doc.root().first_child().unwrap();
+///
+/// let changed = doc.edit(9..12, "*One*");
+/// assert_eq!(doc.text(), "# Title\n\n*One*.\n\nTwo.\n");
+/// assert!(!changed.iter().any(|n| n.same_node(unchanged)));
+/// assert!(doc.root().first_child().unwrap().same_node(unchanged));
+///
+/// let mut html = vec![];
+/// format_html(doc.root(), &ComrakOptions::default(), &mut html).unwrap();
+/// assert_eq!(String::from_utf8(html).unwrap(), "<h1>Title</h1>\n<p><em>One</em>.</p>\n<p>Two.</p>\n");
+/// ```
+pub struct IncrementalDocument<'a> {
+    arena: &'a Arena<AstNode<'a>>,
+    options: ComrakOptions,
+    text: String,
+    line_starts: Vec<usize>,
+    root: &'a AstNode<'a>,
+    refmap: HashMap<Vec<u8>, Reference>,
+}
+
+impl<'a> fmt::Debug for IncrementalDocument<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IncrementalDocument")
+            .field("text", &self.text)
+            .field("root", &self.root)
+            .finish()
+    }
+}
+
+impl<'a> IncrementalDocument<'a> {
+    /// Parse a document in full, ready to be edited.
+    pub fn new(arena: &'a Arena<AstNode<'a>>, text: &str, options: &ComrakOptions) -> Self {
+        let (root, refmap) = parse_document_with_refmap(arena, text, options);
+        IncrementalDocument {
+            arena,
+            options: options.clone(),
+            text: text.to_string(),
+            line_starts: line_starts(text),
+            root,
+            refmap,
+        }
+    }
+
+    /// The root of the document's AST.  This is the same node throughout the document's life;
+    /// edits replace its children.
+    pub fn root(&self) -> &'a AstNode<'a> {
+        self.root
+    }
+
+    /// The document's current text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replace the given byte range of the text and re-parse the document, returning the new
+    /// top-level nodes.  Top-level nodes not returned are those from before the edit, with their
+    /// source positions moved to follow it.
+    ///
+    /// Blocks are re-parsed from the one before the edit up to the first following block which
+    /// can't be affected by it.  The whole document is re-parsed when the edit changes any link
+    /// reference definitions, when front matter is enabled and the edit is at the start of the
+    /// document, and on every edit when footnotes are enabled.
+    ///
+    /// Panics if the range is out of bounds or doesn't fall on character boundaries.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) -> Vec<&'a AstNode<'a>> {
+        let changed = self.reparse_blocks(&range, replacement);
+
+        self.text.replace_range(range, replacement);
+        self.line_starts = line_starts(&self.text);
+        changed.unwrap_or_else(|| self.reparse_all())
+    }
+
+    // Re-parses the blocks around the edit before it's applied to the text, or returns `None` if
+    // the whole document needs re-parsing.
+    fn reparse_blocks(
+        &mut self,
+        range: &Range<usize>,
+        replacement: &str,
+    ) -> Option<Vec<&'a AstNode<'a>>> {
+        if self.options.extension.footnotes {
+            return None;
+        }
+
+        let blocks = self.root.children().collect::<Vec<_>>();
+        if blocks.is_empty() {
+            return None;
+        }
+        let lines = |node: &AstNode| {
+            let sourcepos = node.data.borrow().sourcepos;
+            (sourcepos.start.line, sourcepos.end.line)
+        };
+
+        // Re-parse from the first block the edit touches (or is adjacent to) up to and including
+        // the block after the last one it does.  Blocks which can continue past a blank line may
+        // be extended by the edit too.
+        let start_line = self.line_of(range.start);
+        let end_line = self.line_of(range.end);
+        let mut first = blocks
+            .iter()
+            .position(|b| lines(b).1 + 1 >= start_line)
+            .unwrap_or(blocks.len() - 1);
+        if first > 0 && continues_past_blank(blocks[first - 1]) {
+            first -= 1;
+        }
+        let mut stop = blocks
+            .iter()
+            .rposition(|b| lines(b).0 <= end_line + 1)
+            .map_or(1, |ix| ix + 2)
+            .max(first + 1)
+            .min(blocks.len());
+
+        // The region starts straight after the block before it, so that it includes any blank
+        // lines or link reference definitions between them.
+        let region_start_line = if first == 0 {
+            1
+        } else {
+            lines(blocks[first - 1]).1 + 1
+        };
+        if region_start_line == 1 && self.options.extension.front_matter_delimiter.is_some() {
+            return None;
+        }
+        let region_start = self.line_starts[region_start_line - 1];
+
+        // The region is tried out, and its old link reference definitions collected, in a scratch
+        // arena, so that only the blocks which replace the old ones are allocated in the
+        // document's.
+        let scratch = Arena::new();
+        loop {
+            // The re-parsed region must be followed by a blank line, so that the block after it
+            // can't be a continuation of anything within it.
+            while stop < blocks.len() && !self.is_blank_line(lines(blocks[stop]).0 - 1) {
+                stop += 1;
+            }
+
+            let region_end = if stop < blocks.len() {
+                self.line_starts[lines(blocks[stop]).0 - 1]
+            } else {
+                self.text.len()
+            };
+            let old_region = &self.text[region_start..region_end];
+            let mut region = String::with_capacity(old_region.len() + replacement.len());
+            region.push_str(&self.text[region_start..range.start]);
+            region.push_str(replacement);
+            region.push_str(&self.text[range.end..region_end]);
+
+            let at_end = stop == blocks.len();
+            let references = match parse_blocks_from(
+                &scratch,
+                &region,
+                &self.options,
+                region_start_line,
+                at_end,
+            ) {
+                Some(parser) => parser.references().clone(),
+                None => {
+                    stop += 1;
+                    continue;
+                }
+            };
+
+            // If the edit changes any link reference definitions, links anywhere in the
+            // document could change.
+            let old_parser =
+                parse_blocks_from(&scratch, old_region, &self.options, region_start_line, true)
+                    .unwrap();
+            if references != *old_parser.references() {
+                return None;
+            }
+
+            let line_delta =
+                line_starts(&region).len() as isize - line_starts(old_region).len() as isize;
+            let region_root = parse_blocks_from(
+                self.arena,
+                &region,
+                &self.options,
+                region_start_line,
+                at_end,
+            )
+            .unwrap()
+            .finish_inlines(self.refmap.clone());
+
+            let changed = region_root.children().collect::<Vec<_>>();
+            for node in &changed {
+                match blocks.get(stop) {
+                    Some(next) => next.insert_before(node),
+                    None => self.root.append(node),
+                }
+            }
+            for block in &blocks[first..stop] {
+                block.detach();
+            }
+
+            for block in &blocks[stop..] {
+                for node in block.descendants() {
+                    let mut ast = node.data.borrow_mut();
+                    ast.sourcepos.start.line = shift(ast.sourcepos.start.line, line_delta);
+                    ast.sourcepos.end.line = shift(ast.sourcepos.end.line, line_delta);
+                }
+            }
+
+            let mut root_ast = self.root.data.borrow_mut();
+            if at_end {
+                root_ast.sourcepos.end = region_root.data.borrow().sourcepos.end;
+            } else {
+                root_ast.sourcepos.end.line = shift(root_ast.sourcepos.end.line, line_delta);
+            }
+
+            return Some(changed);
+        }
+    }
+
+    fn reparse_all(&mut self) -> Vec<&'a AstNode<'a>> {
+        let (root, refmap) = parse_document_with_refmap(self.arena, &self.text, &self.options);
+
+        for child in self.root.children().collect::<Vec<_>>() {
+            child.detach();
+        }
+        let changed = root.children().collect::<Vec<_>>();
+        for node in &changed {
+            self.root.append(node);
+        }
+        self.root.data.borrow_mut().sourcepos = root.data.borrow().sourcepos;
+        self.refmap = refmap;
+
+        changed
+    }
+
+    // The 1-based line the byte offset falls on.
+    fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(ix) => ix + 1,
+            Err(ix) => ix,
+        }
+    }
+
+    fn is_blank_line(&self, line: usize) -> bool {
+        let start = self.line_starts[line - 1];
+        let end = self.line_starts.get(line).map_or(self.text.len(), |&e| e);
+        self.text[start..end]
+            .bytes()
+            .all(|c| c == b' ' || c == b'\t' || c == b'\r' || c == b'\n')
+    }
+}
+
+// The byte offset each line starts at; lines end with `\n`, `\r\n` or `\r`, as in the parser.
+fn line_starts(text: &str) -> Vec<usize> {
+    let bytes = text.as_bytes();
+    let mut starts = vec![0];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                i += 2;
+                starts.push(i);
+            }
+            b'\r' | b'\n' => {
+                i += 1;
+                starts.push(i);
+            }
+            _ => i += 1,
+        }
+    }
+    if starts.len() > 1 && starts[starts.len() - 1] == bytes.len() {
+        starts.pop();
+    }
+    starts
+}
+
+fn continues_past_blank(node: &AstNode) -> bool {
+    match node.data.borrow().value {
+        NodeValue::List(..) | NodeValue::DescriptionList => true,
+        NodeValue::CodeBlock(ref ncb) => !ncb.fenced,
+        _ => false,
+    }
+}
+
+fn shift(line: usize, delta: isize) -> usize {
+    if line == 0 {
+        0
+    } else {
+        (line as isize + delta) as usize
+    }
+}
@@ -0,0 +1,119 @@
+//! JSON export and import of the AST.
+//!
+//! Available with the `serde` feature.  Each node is an object with its `value`, its
+//! `children`, its `sourcepos` when `options.render.sourcepos` is set, and its `attributes` when
+//! it has any.
+
+use arena_tree::Node;
+use nodes::{Ast, AstNode, NodeAttributes, NodeValue, Sourcepos};
+use parser::ComrakOptions;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::Serialize;
+use serde_json;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use typed_arena::Arena;
+
+/// Formats an AST as JSON, modified by the given options.
+///
+/// ```
+/// # use comrak::{format_html, format_json, parse_document, Arena, ComrakOptions};
+/// use comrak::json;
+///
+/// let options = ComrakOptions::default();
+/// let arena = Arena::new();
+/// let root = parse_document(&arena, "Hello, *world*!\n", &options);
+///
+/// let mut output = vec![];
+/// format_json(root, &options, &mut output).unwrap();
+/// let output = String::from_utf8(output).unwrap();
+/// assert!(output.starts_with("{\"value\":\"Document\",\"children\":[{\"value\":\"Paragraph\""));
+///
+/// let root = json::parse_document(&arena, &output).unwrap();
+/// let mut html = vec![];
+/// format_html(root, &options, &mut html).unwrap();
+/// assert_eq!(String::from_utf8(html).unwrap(), "<p>Hello, <em>world</em>!</p>\n");
+/// ```
+pub fn format_document<'a>(
+    root: &'a AstNode<'a>,
+    options: &ComrakOptions,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let node = JsonNode {
+        node: root,
+        sourcepos: options.render.sourcepos,
+    };
+    serde_json::to_writer(output, &node)?;
+    Ok(())
+}
+
+/// Reconstructs an AST from JSON produced by `format_document`, allocating its nodes in the
+/// given arena.  The result can be formatted like any parsed document.
+///
+/// Nodes without a `sourcepos` are given an empty one, and `children` may be omitted for nodes
+/// without any.
+pub fn parse_document<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    json: &str,
+) -> Result<&'a AstNode<'a>, serde_json::Error> {
+    let node: OwnedNode = serde_json::from_str(json)?;
+    Ok(node.build(arena))
+}
+
+struct JsonNode<'a> {
+    node: &'a AstNode<'a>,
+    sourcepos: bool,
+}
+
+impl<'a> Serialize for JsonNode<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ast = self.node.data.borrow();
+        let len = 2 + self.sourcepos as usize + !ast.attributes.is_empty() as usize;
+        let mut state = serializer.serialize_struct("Node", len)?;
+        state.serialize_field("value", &ast.value)?;
+        if self.sourcepos {
+            state.serialize_field("sourcepos", &ast.sourcepos)?;
+        }
+        if !ast.attributes.is_empty() {
+            state.serialize_field("attributes", &ast.attributes)?;
+        }
+        state.serialize_field("children", &JsonChildren(self))?;
+        state.end()
+    }
+}
+
+struct JsonChildren<'n, 'a: 'n>(&'n JsonNode<'a>);
+
+impl<'n, 'a> Serialize for JsonChildren<'n, 'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.node.children().map(|node| JsonNode {
+            node,
+            sourcepos: self.0.sourcepos,
+        }))
+    }
+}
+
+#[derive(Deserialize)]
+struct OwnedNode {
+    value: NodeValue,
+    #[serde(default)]
+    sourcepos: Sourcepos,
+    #[serde(default)]
+    attributes: NodeAttributes,
+    #[serde(default)]
+    children: Vec<OwnedNode>,
+}
+
+impl OwnedNode {
+    fn build<'a>(self, arena: &'a Arena<AstNode<'a>>) -> &'a AstNode<'a> {
+        let mut ast = Ast::new(self.value);
+        ast.sourcepos = self.sourcepos;
+        ast.attributes = self.attributes;
+        ast.open = false;
+        let node = arena.alloc(Node::new(RefCell::new(ast)));
+        for child in self.children {
+            node.append(child.build(arena));
+        }
+        node
+    }
+}
@@ -71,6 +71,8 @@
 )]
 #![allow(unknown_lints, clippy::doc_markdown, cyclomatic_complexity)]
 
+#[cfg(feature = "shortcodes")]
+extern crate emojis;
 extern crate entities;
 #[macro_use]
 extern crate lazy_static;
@@ -80,34 +82,61 @@ extern crate pest_derive;
 #[cfg(test)]
 extern crate propfuzz;
 extern crate regex;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "front-matter")]
+extern crate serde_yaml;
+#[cfg(feature = "syntect")]
+extern crate syntect;
 #[cfg(feature = "benchmarks")]
 extern crate test;
 #[cfg(test)]
 extern crate timebomb;
+#[cfg(feature = "front-matter")]
+extern crate toml;
 extern crate twoway;
 extern crate typed_arena;
 extern crate unicode_categories;
 
+pub mod adapters;
 pub mod arena_tree;
 mod cm;
 mod ctype;
 mod entity;
-mod html;
+pub mod event;
+#[cfg(feature = "front-matter")]
+pub mod front_matter;
+pub mod html;
+pub mod incremental;
+#[cfg(feature = "serde")]
+pub mod json;
 pub mod nodes;
 mod parser;
+pub mod plugins;
+pub mod sanitize;
 mod scanners;
 mod strings;
 #[cfg(test)]
 mod tests;
+pub mod toc;
+mod xml;
 
 pub use cm::format_document as format_commonmark;
+pub use event::{events, Event};
 pub use html::format_document as format_html;
+pub use html::format_document_with_plugins as format_html_with_plugins;
 pub use html::Anchorizer;
+#[cfg(feature = "serde")]
+pub use json::format_document as format_json;
 pub use parser::{
     parse_document, parse_document_with_broken_link_callback, ComrakExtensionOptions,
-    ComrakOptions, ComrakParseOptions, ComrakRenderOptions,
+    ComrakOptions, ComrakParseOptions, ComrakPlugins, ComrakRenderOptions, ComrakRenderPlugins,
 };
 pub use typed_arena::Arena;
+pub use xml::format_document as format_xml;
 
 /// Render Markdown to HTML.
 ///
@@ -119,3 +148,18 @@ pub fn markdown_to_html(md: &str, options: &ComrakOptions) -> String {
     format_html(root, options, &mut s).unwrap();
     String::from_utf8(s).unwrap()
 }
+
+/// Render Markdown to HTML using plugins.
+///
+/// See the documentation of `ComrakRenderPlugins` for an example.
+pub fn markdown_to_html_with_plugins(
+    md: &str,
+    options: &ComrakOptions,
+    plugins: &ComrakPlugins,
+) -> String {
+    let arena = Arena::new();
+    let root = parse_document(&arena, md, options);
+    let mut s = Vec::new();
+    format_html_with_plugins(root, options, &mut s, plugins).unwrap();
+    String::from_utf8(s).unwrap()
+}
@@ -9,6 +9,7 @@ extern crate shell_words;
 #[cfg(not(windows))]
 extern crate xdg;
 
+use comrak::sanitize::Sanitizer;
 use comrak::{
     Arena, ComrakExtensionOptions, ComrakOptions, ComrakParseOptions, ComrakRenderOptions,
 };
@@ -29,6 +30,32 @@ const EXIT_READ_INPUT: i32 = 3;
 fn main() -> Result<(), Box<dyn Error>> {
     let default_config_path = get_default_config_path();
 
+    let mut extensions = vec![
+        "strikethrough",
+        "tagfilter",
+        "table",
+        "autolink",
+        "tasklist",
+        "superscript",
+        "highlight",
+        "insert",
+        "subscript",
+        "spoiler",
+        "underline",
+        "footnotes",
+        "description-lists",
+        "wikilinks",
+        "wikilinks-title-before-pipe",
+        "math",
+        "alerts",
+        "table-of-contents",
+        "attributes",
+        "custom-containers",
+    ];
+    if cfg!(feature = "shortcodes") {
+        extensions.push("shortcodes");
+    }
+
     let app = clap::App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
@@ -90,6 +117,16 @@ if the file does not exist.\
                 .long("escape")
                 .help("Escape raw HTML instead of clobbering it"),
         )
+        .arg(
+            clap::Arg::with_name("sanitize")
+                .long("sanitize")
+                .help("Sanitize raw HTML against the default allowlist instead of clobbering it"),
+        )
+        .arg(
+            clap::Arg::with_name("sourcepos")
+                .long("sourcepos")
+                .help("Include source position attributes in HTML output"),
+        )
         .arg(
             clap::Arg::with_name("extension")
                 .short("e")
@@ -97,16 +134,7 @@ if the file does not exist.\
                 .takes_value(true)
                 .number_of_values(1)
                 .multiple(true)
-                .possible_values(&[
-                    "strikethrough",
-                    "tagfilter",
-                    "table",
-                    "autolink",
-                    "tasklist",
-                    "superscript",
-                    "footnotes",
-                    "description-lists",
-                ])
+                .possible_values(&extensions)
                 .value_name("EXTENSION")
                 .help("Specify an extension name to use"),
         )
@@ -115,7 +143,7 @@ if the file does not exist.\
                 .short("t")
                 .long("to")
                 .takes_value(true)
-                .possible_values(&["html", "commonmark"])
+                .possible_values(&["html", "xml", "commonmark"])
                 .default_value("html")
                 .value_name("FORMAT")
                 .help("Specify output format"),
@@ -143,6 +171,13 @@ if the file does not exist.\
                 .value_name("PREFIX")
                 .help("Use the Comrak header IDs extension, with the given ID prefix"),
         )
+        .arg(
+            clap::Arg::with_name("footnote-id-prefix")
+                .long("footnote-id-prefix")
+                .takes_value(true)
+                .value_name("PREFIX")
+                .help("Prefix footnote and footnote reference IDs with the given string"),
+        )
         .arg(
             clap::Arg::with_name("front-matter-delimiter")
                 .long("front-matter-delimiter")
@@ -186,12 +221,26 @@ if the file does not exist.\
             autolink: exts.remove("autolink") || matches.is_present("gfm"),
             tasklist: exts.remove("tasklist") || matches.is_present("gfm"),
             superscript: exts.remove("superscript"),
+            highlight: exts.remove("highlight"),
+            insert: exts.remove("insert"),
+            subscript: exts.remove("subscript"),
+            spoiler: exts.remove("spoiler"),
+            underline: exts.remove("underline"),
             header_ids: matches.value_of("header-ids").map(|s| s.to_string()),
             footnotes: exts.remove("footnotes"),
             description_lists: exts.remove("description-lists"),
             front_matter_delimiter: matches
                 .value_of("front-matter-delimiter")
                 .map(|s| s.to_string()),
+            wikilinks: exts.remove("wikilinks"),
+            wikilinks_title_before_pipe: exts.remove("wikilinks-title-before-pipe"),
+            math: exts.remove("math"),
+            alerts: exts.remove("alerts"),
+            table_of_contents: exts.remove("table-of-contents"),
+            attributes: exts.remove("attributes"),
+            custom_containers: exts.remove("custom-containers"),
+            shortcodes: exts.remove("shortcodes"),
+            custom_shortcodes: Default::default(),
         },
         parse: ComrakParseOptions {
             smart: matches.is_present("smart"),
@@ -209,6 +258,17 @@ if the file does not exist.\
                 .unwrap_or(0),
             unsafe_: matches.is_present("unsafe"),
             escape: matches.is_present("escape"),
+            sanitize: if matches.is_present("sanitize") {
+                Some(Sanitizer::default())
+            } else {
+                None
+            },
+            url_policy: None,
+            external_links: None,
+            footnote_id_prefix: matches
+                .value_of("footnote-id-prefix")
+                .map(|s| s.to_string()),
+            sourcepos: matches.is_present("sourcepos"),
         },
     };
 
@@ -243,6 +303,7 @@ if the file does not exist.\
 
     let formatter = match matches.value_of("format") {
         Some("html") => comrak::format_html,
+        Some("xml") => comrak::format_xml,
         Some("commonmark") => comrak::format_commonmark,
         _ => panic!("unknown format"),
     };
@@ -2,15 +2,17 @@
 
 use arena_tree::Node;
 use std::cell::RefCell;
+use std::fmt;
 
 /// The core AST node enum.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum NodeValue {
     /// The root of every CommonMark document.  Contains **blocks**.
     Document,
 
     /// Non-Markdown front matter. Treated as an opaque blob.
-    FrontMatter(Vec<u8>),
+    FrontMatter(#[cfg_attr(feature = "serde", serde(with = "bytes"))] Vec<u8>),
 
     /// **Block**. A [block quote](https://github.github.com/gfm/#block-quotes).  Contains other
     /// **blocks**.
@@ -20,6 +22,27 @@ pub enum NodeValue {
     /// ```
     BlockQuote,
 
+    /// **Block**.  A GitHub-style alert, enabled with the `alerts` option.  Written as a block
+    /// quote whose first line is the alert type, optionally followed by a custom title.
+    /// Contains other **blocks**.
+    ///
+    /// ``` md
+    /// > [!WARNING]
+    /// > Critical content demanding immediate user attention.
+    /// ```
+    Alert(NodeAlert),
+
+    /// **Block**.  A custom container, enabled with the `custom_containers` option.  Opened by a
+    /// fence of three or more colons followed by the container's name, and closed by a fence of
+    /// colons at least as long.  Contains other **blocks**.
+    ///
+    /// ``` md
+    /// ::: warning Take care
+    /// Here be *dragons*.
+    /// :::
+    /// ```
+    Container(NodeContainer),
+
     /// **Block**.  A [list](https://github.github.com/gfm/#lists).  Contains
     /// [list items](https://github.github.com/gfm/#list-items).
     ///
@@ -82,9 +105,13 @@ pub enum NodeValue {
     /// children.
     ThematicBreak,
 
-    /// **Block**. A footnote definition.  The `Vec<u8>` is the footnote's name.
-    /// Contains other **blocks**.
-    FootnoteDefinition(Vec<u8>),
+    /// **Block**.  A table of contents placeholder, enabled with the `table_of_contents` option.
+    /// Written as a paragraph consisting only of `[[_TOC_]]` or `[TOC]`, and expanded by the
+    /// HTML formatter into a list of the document's headings.  Has no children.
+    TableOfContents,
+
+    /// **Block**. A footnote definition.  Contains other **blocks**.
+    FootnoteDefinition(NodeFootnoteDefinition),
 
     /// **Block**. A [table](https://github.github.com/gfm/#tables-extension-) per the GFM spec.
     /// Contains table rows.
@@ -99,7 +126,7 @@ pub enum NodeValue {
 
     /// **Inline**.  [Textual content](https://github.github.com/gfm/#textual-content).  All text
     /// in a document will be contained in a `Text` node.
-    Text(Vec<u8>),
+    Text(#[cfg_attr(feature = "serde", serde(with = "bytes"))] Vec<u8>),
 
     /// **Inline**. [Task list item](https://github.github.com/gfm/#task-list-items-extension-). The
     /// `bool` indicates whether it is checked or not.
@@ -114,10 +141,10 @@ pub enum NodeValue {
     LineBreak,
 
     /// **Inline**.  A [code span](https://github.github.com/gfm/#code-spans).
-    Code(Vec<u8>),
+    Code(#[cfg_attr(feature = "serde", serde(with = "bytes"))] Vec<u8>),
 
     /// **Inline**.  [Raw HTML](https://github.github.com/gfm/#raw-html) contained inline.
-    HtmlInline(Vec<u8>),
+    HtmlInline(#[cfg_attr(feature = "serde", serde(with = "bytes"))] Vec<u8>),
 
     /// **Inline**.  [Emphasised](https://github.github.com/gfm/#emphasis-and-strong-emphasis)
     /// text.
@@ -133,6 +160,21 @@ pub enum NodeValue {
     /// **Inline**.  Superscript.  Enabled with `ext_superscript` option.
     Superscript,
 
+    /// **Inline**.  Highlighted text, delimited by `==`.  Enabled with `ext_highlight` option.
+    Highlight,
+
+    /// **Inline**.  Inserted text, delimited by `++`.  Enabled with `ext_insert` option.
+    Insert,
+
+    /// **Inline**.  Subscript, delimited by a single `~`.  Enabled with `ext_subscript` option.
+    Subscript,
+
+    /// **Inline**.  Spoilered text, delimited by `||`.  Enabled with `ext_spoiler` option.
+    SpoileredText,
+
+    /// **Inline**.  Underlined text, delimited by `__`.  Enabled with `ext_underline` option.
+    Underline,
+
     /// **Inline**.  A [link](https://github.github.com/gfm/#links) to some URL, with possible
     /// title.
     Link(NodeLink),
@@ -140,12 +182,43 @@ pub enum NodeValue {
     /// **Inline**.  An [image](https://github.github.com/gfm/#images).
     Image(NodeLink),
 
-    /// **Inline**.  A footnote reference; the `Vec<u8>` is the referent footnote's name.
-    FootnoteReference(Vec<u8>),
+    /// **Inline**.  A footnote reference.
+    FootnoteReference(NodeFootnoteReference),
+
+    /// **Inline**.  A wikilink to some page, enabled with the `wikilinks` option.  Contains
+    /// **inlines**: a title given after the pipe (or before it, with
+    /// `wikilinks_title_before_pipe`), or else the page name as a single **Text** node.
+    ///
+    /// ``` md
+    /// [[Page name]]
+    /// [[Page name|Link title]]
+    /// ```
+    WikiLink(NodeWikiLink),
+
+    /// **Inline**.  A math span, enabled with the `math` option.  Contains the raw TeX
+    /// source, which is not parsed further.
+    ///
+    /// ``` md
+    /// $a^2 + b^2 = c^2$
+    /// $$\sum_{i=1}^n i$$
+    /// ```
+    ///
+    /// A fenced code block whose info string starts with `math` is parsed as display math too;
+    /// that node is a **block** and has `fenced` set.
+    Math(NodeMath),
+
+    /// **Inline**.  An emoji shortcode, enabled with the `shortcodes` option.  Only shortcodes
+    /// for known emoji, or given in `custom_shortcodes`, are recognised.
+    ///
+    /// ``` md
+    /// :tada: :+1:
+    /// ```
+    ShortCode(NodeShortCode),
 }
 
 /// Alignment of a single table cell.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TableAlignment {
     /// Cell content is unaligned.
     None,
@@ -162,24 +235,204 @@ pub enum TableAlignment {
 
 /// The details of a link's destination, or an image's source.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeLink {
     /// The URL for the link destination or image source.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
     pub url: Vec<u8>,
 
     /// The title for the link or image.
     ///
     /// Note this field is used for the `title` attribute by the HTML formatter even for images;
     /// `alt` text is supplied in the image inline text.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
     pub title: Vec<u8>,
+
+    /// Whether the link is an autolink, written in angle brackets or found by the `autolink`
+    /// extension, rather than an inline or reference link.  Always `false` for images.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub autolink: bool,
+}
+
+/// The details of a footnote definition.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeFootnoteDefinition {
+    /// The footnote's name, as written in the source.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
+    pub name: Vec<u8>,
+
+    /// The footnote's number, assigned in order of first reference.  Zero until the document
+    /// has been fully parsed, and for definitions which are never referenced.
+    pub ix: u32,
+
+    /// The number of references to the footnote in the document.
+    pub total_references: u32,
+}
+
+/// The details of a footnote reference.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeFootnoteReference {
+    /// The name of the referent footnote, as written in the source.  Inline footnotes are
+    /// given a name once the document has been fully parsed.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
+    pub name: Vec<u8>,
+
+    /// The referent footnote's number.  Zero until the document has been fully parsed.
+    pub ix: u32,
+
+    /// Which reference to the footnote this is, counting from 1 in document order.  Zero until
+    /// the document has been fully parsed.
+    pub ref_num: u32,
+}
+
+/// The details of a wikilink's target.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeWikiLink {
+    /// The page name the wikilink targets, as written in the source.  The HTML formatter uses it
+    /// as the link's URL unless a wikilink resolver plugin maps it to another.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
+    pub url: Vec<u8>,
+}
+
+/// The details of a math span.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeMath {
+    /// Whether this is display math (`$$...$$`) rather than inline math (`$...$`).
+    pub display: bool,
+
+    /// Whether this was written as a fenced code block with the info string `math`, making it a
+    /// block rather than an inline.  Always implies `display`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fenced: bool,
+
+    /// The TeX source of the math, without the surrounding dollar signs.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
+    pub literal: Vec<u8>,
+}
+
+/// The details of an emoji shortcode.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeShortCode {
+    /// The shortcode's name, without the surrounding colons.
+    pub code: String,
+
+    /// The emoji the shortcode stands for, or `None` for one of the `custom_shortcodes`.
+    pub emoji: Option<String>,
+}
+
+/// The details of an alert.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeAlert {
+    /// The type of alert.
+    pub kind: AlertType,
+
+    /// A custom title given after the alert type, if any.  Without one, the HTML formatter
+    /// uses the alert type's default title.
+    #[cfg_attr(feature = "serde", serde(with = "bytes::option"))]
+    pub title: Option<Vec<u8>>,
+}
+
+/// The details of a custom container.
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeContainer {
+    /// The container's name: the first word after the opening fence.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
+    pub name: Vec<u8>,
+
+    /// The rest of the opening fence's line after the name, with surrounding whitespace
+    /// removed.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
+    pub info: Vec<u8>,
+
+    /// The number of colons in the opening fence.  The closing fence must be at least as long.
+    pub fence_length: usize,
+}
+
+/// The type of an alert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AlertType {
+    /// `[!NOTE]`: useful information users should know, even when skimming.
+    Note,
+
+    /// `[!TIP]`: helpful advice for doing things better or more easily.
+    Tip,
+
+    /// `[!IMPORTANT]`: key information users need to know to achieve their goal.
+    Important,
+
+    /// `[!WARNING]`: urgent info that needs immediate attention to avoid problems.
+    Warning,
+
+    /// `[!CAUTION]`: advises about risks or negative outcomes of certain actions.
+    Caution,
+}
+
+impl AlertType {
+    /// The alert type with the given name, as written between `[!` and `]`, ignoring case.
+    pub fn from_name(name: &[u8]) -> Option<AlertType> {
+        match name.to_ascii_lowercase().as_slice() {
+            b"note" => Some(AlertType::Note),
+            b"tip" => Some(AlertType::Tip),
+            b"important" => Some(AlertType::Important),
+            b"warning" => Some(AlertType::Warning),
+            b"caution" => Some(AlertType::Caution),
+            _ => None,
+        }
+    }
+
+    /// The name of the alert type as written in Markdown, e.g. `NOTE`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AlertType::Note => "NOTE",
+            AlertType::Tip => "TIP",
+            AlertType::Important => "IMPORTANT",
+            AlertType::Warning => "WARNING",
+            AlertType::Caution => "CAUTION",
+        }
+    }
+
+    /// The title shown for an alert of this type when it has no custom title.
+    pub fn default_title(&self) -> &'static str {
+        match *self {
+            AlertType::Note => "Note",
+            AlertType::Tip => "Tip",
+            AlertType::Important => "Important",
+            AlertType::Warning => "Warning",
+            AlertType::Caution => "Caution",
+        }
+    }
+
+    /// The CSS class the HTML formatter gives an alert of this type, after
+    /// `markdown-alert`.
+    pub fn css_class(&self) -> &'static str {
+        match *self {
+            AlertType::Note => "markdown-alert-note",
+            AlertType::Tip => "markdown-alert-tip",
+            AlertType::Important => "markdown-alert-important",
+            AlertType::Warning => "markdown-alert-warning",
+            AlertType::Caution => "markdown-alert-caution",
+        }
+    }
 }
 
 /// The metadata of a list; the kind of list, the delimiter used and so on.
 #[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeList {
     /// The kind of list (bullet (unordered) or ordered).
     pub list_type: ListType,
 
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) marker_offset: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) padding: usize,
 
     /// For ordered lists, the ordinal the list starts at.
@@ -198,13 +451,17 @@ pub struct NodeList {
 
 /// The metadata of a description list
 #[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeDescriptionItem {
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) marker_offset: usize,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) padding: usize,
 }
 
 /// The type of list.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ListType {
     /// A bullet list, i.e. an unordered list.
     Bullet,
@@ -221,6 +478,7 @@ impl Default for ListType {
 
 /// The delimiter for ordered lists, i.e. the character which appears after each number.
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ListDelimType {
     /// A period character `.`.
     Period,
@@ -237,6 +495,7 @@ impl Default for ListDelimType {
 
 /// The metadata and data of a code block (fenced or indented).
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeCodeBlock {
     /// Whether the code block is fenced.
     pub fenced: bool,
@@ -247,20 +506,24 @@ pub struct NodeCodeBlock {
     /// For fenced code blocks, the length of the fence.
     pub fence_length: usize,
 
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) fence_offset: usize,
 
     /// For fenced code blocks, the [info string](https://github.github.com/gfm/#info-string) after
     /// the opening fence, if any.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
     pub info: Vec<u8>,
 
     /// The literal contents of the code block.  As the contents are not interpreted as Markdown at
     /// all, they are contained within this structure, rather than inserted into a child inline of
     /// any kind.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
     pub literal: Vec<u8>,
 }
 
 /// The metadata of a heading.
 #[derive(Default, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeHeading {
     /// The level of the header; from 1 to 6 for ATX headings, 1 or 2 for setext headings.
     pub level: u32,
@@ -271,11 +534,14 @@ pub struct NodeHeading {
 
 /// The metadata of an included HTML block.
 #[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NodeHtmlBlock {
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) block_type: u8,
 
     /// The literal contents of the HTML block.  Per NodeCodeBlock, the content is included here
     /// rather than in any inline.
+    #[cfg_attr(feature = "serde", serde(with = "bytes"))]
     pub literal: Vec<u8>,
 }
 
@@ -286,6 +552,8 @@ impl NodeValue {
             *self,
             NodeValue::Document
                 | NodeValue::BlockQuote
+                | NodeValue::Alert(..)
+                | NodeValue::Container(..)
                 | NodeValue::FootnoteDefinition(_)
                 | NodeValue::List(..)
                 | NodeValue::DescriptionList
@@ -298,9 +566,11 @@ impl NodeValue {
                 | NodeValue::Paragraph
                 | NodeValue::Heading(..)
                 | NodeValue::ThematicBreak
+                | NodeValue::TableOfContents
                 | NodeValue::Table(..)
                 | NodeValue::TableRow(..)
                 | NodeValue::TableCell
+                | NodeValue::Math(NodeMath { fenced: true, .. })
         )
     }
 
@@ -332,6 +602,55 @@ impl NodeValue {
         }
     }
 
+    /// Returns the name of the element this node is rendered as in CommonMark XML.
+    pub fn xml_node_name(&self) -> &'static str {
+        match *self {
+            NodeValue::Document => "document",
+            NodeValue::FrontMatter(_) => "frontmatter",
+            NodeValue::BlockQuote => "block_quote",
+            NodeValue::Alert(..) => "alert",
+            NodeValue::Container(..) => "custom_container",
+            NodeValue::List(..) => "list",
+            NodeValue::Item(..) => "item",
+            NodeValue::DescriptionList => "description_list",
+            NodeValue::DescriptionItem(..) => "description_item",
+            NodeValue::DescriptionTerm => "description_term",
+            NodeValue::DescriptionDetails => "description_details",
+            NodeValue::CodeBlock(..) => "code_block",
+            NodeValue::HtmlBlock(..) => "html_block",
+            NodeValue::Paragraph => "paragraph",
+            NodeValue::Heading(..) => "heading",
+            NodeValue::ThematicBreak => "thematic_break",
+            NodeValue::TableOfContents => "table_of_contents",
+            NodeValue::FootnoteDefinition(..) => "footnote_definition",
+            NodeValue::Table(..) => "table",
+            NodeValue::TableRow(true) => "table_header",
+            NodeValue::TableRow(false) => "table_row",
+            NodeValue::TableCell => "table_cell",
+            NodeValue::Text(..) => "text",
+            NodeValue::TaskItem(..) => "taskitem",
+            NodeValue::SoftBreak => "softbreak",
+            NodeValue::LineBreak => "linebreak",
+            NodeValue::Code(..) => "code",
+            NodeValue::HtmlInline(..) => "html_inline",
+            NodeValue::Emph => "emph",
+            NodeValue::Strong => "strong",
+            NodeValue::Strikethrough => "strikethrough",
+            NodeValue::Superscript => "superscript",
+            NodeValue::Highlight => "highlight",
+            NodeValue::Insert => "insert",
+            NodeValue::Subscript => "subscript",
+            NodeValue::SpoileredText => "spoilered_text",
+            NodeValue::Underline => "underline",
+            NodeValue::Link(..) => "link",
+            NodeValue::Image(..) => "image",
+            NodeValue::FootnoteReference(..) => "footnote_reference",
+            NodeValue::WikiLink(..) => "wikilink",
+            NodeValue::Math(..) => "math",
+            NodeValue::ShortCode(..) => "shortcode",
+        }
+    }
+
     pub(crate) fn accepts_lines(&self) -> bool {
         matches!(
             *self,
@@ -340,20 +659,121 @@ impl NodeValue {
     }
 }
 
+/// A line and column position in the input document.
+///
+/// Both are 1-based; columns are counted in bytes, per `cmark`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LineColumn {
+    /// The 1-based line number.
+    pub line: usize,
+
+    /// The 1-based column number, in bytes.
+    pub column: usize,
+}
+
+impl From<(usize, usize)> for LineColumn {
+    fn from((line, column): (usize, usize)) -> Self {
+        LineColumn { line, column }
+    }
+}
+
+/// The span of the input document a node was parsed from.
+///
+/// Both ends are inclusive, so a single-character node has `start == end`.
+///
+/// ```
+/// # use comrak::{parse_document, Arena, ComrakOptions};
+/// # use comrak::nodes::Sourcepos;
+/// let arena = Arena::new();
+/// let root = parse_document(&arena, "# Hi *there*\n", &ComrakOptions::default());
+/// let heading = root.first_child().unwrap();
+/// let emph = heading.last_child().unwrap();
+/// assert_eq!(heading.data.borrow().sourcepos, Sourcepos::from((1, 1, 1, 12)));
+/// assert_eq!(emph.data.borrow().sourcepos, Sourcepos::from((1, 6, 1, 12)));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sourcepos {
+    /// The position of the first character of the node.
+    pub start: LineColumn,
+
+    /// The position of the last character of the node.
+    pub end: LineColumn,
+}
+
+impl From<(usize, usize, usize, usize)> for Sourcepos {
+    fn from(
+        (start_line, start_column, end_line, end_column): (usize, usize, usize, usize),
+    ) -> Self {
+        Sourcepos {
+            start: (start_line, start_column).into(),
+            end: (end_line, end_column).into(),
+        }
+    }
+}
+
+impl fmt::Display for Sourcepos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.start.line, self.start.column, self.end.line, self.end.column
+        )
+    }
+}
+
+/// Attributes given to a heading, fenced code block, link or image by the `attributes`
+/// extension, from a block such as `{#setup .wide width=400}`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NodeAttributes {
+    /// The identifier, given as `#id`.  If several are given, the last is used.
+    pub id: Option<String>,
+
+    /// The classes, given as `.class`, in order.
+    pub classes: Vec<String>,
+
+    /// Any other attributes, given as `key=value` or `key="value"`, in order.
+    pub pairs: Vec<(String, String)>,
+}
+
+impl NodeAttributes {
+    /// Whether no attributes are given.
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none() && self.classes.is_empty() && self.pairs.is_empty()
+    }
+}
+
 /// A single node in the CommonMark AST.
 ///
 /// The struct contains metadata about the node's position in the original document, and the core
 /// enum, `NodeValue`.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Ast {
     /// The node value itself.
     pub value: NodeValue,
 
-    /// The line in the input document the node starts at.
-    pub start_line: u32,
+    /// The span of the input document the node was parsed from.
+    pub sourcepos: Sourcepos,
+
+    /// The attributes given to the node with the `attributes` extension, if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "NodeAttributes::is_empty")
+    )]
+    pub attributes: NodeAttributes,
 
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) content: Vec<u8>,
+    // The byte offset into each source line at which that line's contribution to `content`
+    // starts; used to map inline positions back to the input.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) line_offsets: Vec<usize>,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) open: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) last_line_blank: bool,
 }
 
@@ -363,11 +783,19 @@ impl Ast {
         Ast {
             value,
             content: vec![],
-            start_line: 0,
+            sourcepos: Sourcepos::default(),
+            attributes: NodeAttributes::default(),
+            line_offsets: vec![],
             open: true,
             last_line_blank: false,
         }
     }
+
+    /// The line in the input document the node starts at.
+    #[deprecated(since = "0.11.0", note = "use `sourcepos.start.line` instead")]
+    pub fn start_line(&self) -> u32 {
+        self.sourcepos.start.line as u32
+    }
 }
 
 /// The type of a node within the document.
@@ -409,6 +837,8 @@ pub(crate) fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) ->
     match node.data.borrow().value {
         NodeValue::Document
         | NodeValue::BlockQuote
+        | NodeValue::Alert(..)
+        | NodeValue::Container(..)
         | NodeValue::FootnoteDefinition(_)
         | NodeValue::DescriptionTerm
         | NodeValue::DescriptionDetails
@@ -428,7 +858,8 @@ pub(crate) fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) ->
         | NodeValue::Emph
         | NodeValue::Strong
         | NodeValue::Link(..)
-        | NodeValue::Image(..) => !child.block(),
+        | NodeValue::Image(..)
+        | NodeValue::WikiLink(..) => !child.block(),
 
         NodeValue::Table(..) => matches!(*child, NodeValue::TableRow(..)),
 
@@ -442,7 +873,14 @@ pub(crate) fn can_contain_type<'a>(node: &'a AstNode<'a>, child: &NodeValue) ->
                 | NodeValue::Strong
                 | NodeValue::Link(..)
                 | NodeValue::Image(..)
+                | NodeValue::WikiLink(..)
+                | NodeValue::Math(..)
                 | NodeValue::Strikethrough
+                | NodeValue::Highlight
+                | NodeValue::Insert
+                | NodeValue::Subscript
+                | NodeValue::SpoileredText
+                | NodeValue::Underline
                 | NodeValue::HtmlInline(..)
         ),
 
@@ -474,3 +912,38 @@ pub(crate) fn containing_block<'a>(node: &'a AstNode<'a>) -> Option<&'a AstNode<
     }
     None
 }
+
+// Serializes byte strings as strings, since their contents are almost always UTF-8 text from the
+// input document.  Invalid UTF-8 is replaced when serializing.
+#[cfg(feature = "serde")]
+mod bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf8_lossy(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        String::deserialize(deserializer).map(String::into_bytes)
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            bytes: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match *bytes {
+                Some(ref bytes) => serializer.serialize_some(&*String::from_utf8_lossy(bytes)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            Option::<String>::deserialize(deserializer).map(|s| s.map(String::into_bytes))
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use ctype::{isalnum, isalpha, isspace};
+use nodes::NodeAttributes;
+use strings;
+
+/// Parses an attribute block such as `{#id .class key=value}` at the start of `input`, returning
+/// the attributes and the length of the block.
+///
+/// Items are separated by whitespace, and at least one must be given.
+pub fn parse(input: &[u8]) -> Option<(NodeAttributes, usize)> {
+    if input.first() != Some(&b'{') {
+        return None;
+    }
+
+    let mut attributes = NodeAttributes::default();
+    let mut pos = 1;
+    loop {
+        let item_end = pos;
+        while pos < input.len() && isspace(input[pos]) {
+            pos += 1;
+        }
+
+        match input.get(pos) {
+            Some(&b'}') if !attributes.is_empty() => return Some((attributes, pos + 1)),
+            _ if pos == item_end && pos > 1 => return None,
+            Some(&b'#') => {
+                let len = name(&input[pos + 1..])?;
+                attributes.id = Some(to_string(&input[pos + 1..pos + 1 + len]));
+                pos += 1 + len;
+            }
+            Some(&b'.') => {
+                let len = name(&input[pos + 1..])?;
+                attributes
+                    .classes
+                    .push(to_string(&input[pos + 1..pos + 1 + len]));
+                pos += 1 + len;
+            }
+            Some(&c) if isalpha(c) || c == b'_' || c == b':' => {
+                let key_len = name(&input[pos..])?;
+                let key = to_string(&input[pos..pos + key_len]);
+                pos += key_len;
+                if input.get(pos) != Some(&b'=') {
+                    return None;
+                }
+                pos += 1;
+                let (value, len) = value(&input[pos..])?;
+                attributes.pairs.push((key, value));
+                pos += len;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Parses an attribute block which ends `input`, ignoring trailing whitespace.  The block must
+/// be at the start of `input` or preceded by whitespace.  Returns the attributes and the offset
+/// at which the block starts.
+pub fn parse_trailing(input: &[u8]) -> Option<(NodeAttributes, usize)> {
+    let input = strings::rtrim_slice(input);
+    if input.last() != Some(&b'}') {
+        return None;
+    }
+
+    for start in (0..input.len()).rev() {
+        if input[start] != b'{' || (start > 0 && !isspace(input[start - 1])) {
+            continue;
+        }
+        match parse(&input[start..]) {
+            Some((attributes, len)) if start + len == input.len() => {
+                return Some((attributes, start))
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+// The length of the identifier, class or key at the start of `input`.
+fn name(input: &[u8]) -> Option<usize> {
+    let len = input
+        .iter()
+        .take_while(|&&c| {
+            isalnum(c) || c >= 0x80 || c == b'-' || c == b'_' || c == b':' || c == b'.'
+        })
+        .count();
+    if len == 0 {
+        None
+    } else {
+        Some(len)
+    }
+}
+
+// A quoted or bare value at the start of `input`, and its length.  Backslash escapes are
+// recognised in quoted values.
+fn value(input: &[u8]) -> Option<(String, usize)> {
+    match input.first() {
+        Some(&quote) if quote == b'"' || quote == b'\'' => {
+            let mut value = vec![];
+            let mut pos = 1;
+            while pos < input.len() {
+                match input[pos] {
+                    c if c == quote => return Some((to_string(&value), pos + 1)),
+                    b'\\' if pos + 1 < input.len() => {
+                        value.push(input[pos + 1]);
+                        pos += 2;
+                    }
+                    c => {
+                        value.push(c);
+                        pos += 1;
+                    }
+                }
+            }
+            None
+        }
+        _ => {
+            let len = input
+                .iter()
+                .take_while(|&&c| !isspace(c) && c != b'}' && c != b'"' && c != b'\'')
+                .count();
+            if len == 0 {
+                None
+            } else {
+                Some((to_string(&input[..len]), len))
+            }
+        }
+    }
+}
+
+fn to_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
@@ -1,5 +1,5 @@
 use ctype::{isalnum, isalpha, isspace};
-use nodes::{AstNode, NodeLink, NodeValue};
+use nodes::{AstNode, LineColumn, NodeLink, NodeValue, Sourcepos};
 use parser::inlines::make_inline;
 use std::str;
 use typed_arena::Arena;
@@ -9,7 +9,9 @@ pub fn process_autolinks<'a>(
     arena: &'a Arena<AstNode<'a>>,
     node: &'a AstNode<'a>,
     contents: &mut Vec<u8>,
+    sourcepos: &mut Sourcepos,
 ) {
+    let start = sourcepos.start;
     let len = contents.len();
     let mut i = 0;
 
@@ -19,19 +21,19 @@ pub fn process_autolinks<'a>(
         while i < len {
             match contents[i] {
                 b':' => {
-                    post_org = url_match(arena, contents, i);
+                    post_org = url_match(arena, contents, i, start);
                     if post_org.is_some() {
                         break;
                     }
                 }
                 b'w' => {
-                    post_org = www_match(arena, contents, i);
+                    post_org = www_match(arena, contents, i, start);
                     if post_org.is_some() {
                         break;
                     }
                 }
                 b'@' => {
-                    post_org = email_match(arena, contents, i);
+                    post_org = email_match(arena, contents, i, start);
                     if post_org.is_some() {
                         break;
                     }
@@ -47,9 +49,17 @@ pub fn process_autolinks<'a>(
             if i + skip < len {
                 let remain = contents[i + skip..].to_vec();
                 assert!(!remain.is_empty());
-                post.insert_after(make_inline(arena, NodeValue::Text(remain)));
+                post.insert_after(make_inline(
+                    arena,
+                    NodeValue::Text(remain),
+                    Sourcepos {
+                        start: offset(start, i + skip),
+                        end: sourcepos.end,
+                    },
+                ));
             }
             contents.truncate(i);
+            sourcepos.end = offset(start, i.saturating_sub(1));
             return;
         }
     }
@@ -59,6 +69,7 @@ fn www_match<'a>(
     arena: &'a Arena<AstNode<'a>>,
     contents: &[u8],
     i: usize,
+    start: LineColumn,
 ) -> Option<(&'a AstNode<'a>, usize, usize)> {
     lazy_static! {
         static ref WWW_DELIMS: [bool; 256] = {
@@ -92,11 +103,21 @@ fn www_match<'a>(
     let mut url = b"http://".to_vec();
     url.extend_from_slice(&contents[i..link_end + i]);
 
-    let inl = make_inline(arena, NodeValue::Link(NodeLink { url, title: vec![] }));
+    let sourcepos = span(start, i, i + link_end);
+    let inl = make_inline(
+        arena,
+        NodeValue::Link(NodeLink {
+            url,
+            title: vec![],
+            autolink: true,
+        }),
+        sourcepos,
+    );
 
     inl.append(make_inline(
         arena,
         NodeValue::Text(contents[i..link_end + i].to_vec()),
+        sourcepos,
     ));
     Some((inl, 0, link_end))
 }
@@ -199,6 +220,7 @@ fn url_match<'a>(
     arena: &'a Arena<AstNode<'a>>,
     contents: &[u8],
     i: usize,
+    start: LineColumn,
 ) -> Option<(&'a AstNode<'a>, usize, usize)> {
     lazy_static! {
         static ref SCHEMES: Vec<&'static [u8]> = vec![b"http", b"https", b"ftp"];
@@ -232,15 +254,18 @@ fn url_match<'a>(
     link_end = autolink_delim(&contents[i..], link_end);
 
     let url = contents[i - rewind..i + link_end].to_vec();
+    let sourcepos = span(start, i - rewind, i + link_end);
     let inl = make_inline(
         arena,
         NodeValue::Link(NodeLink {
             url: url.clone(),
             title: vec![],
+            autolink: true,
         }),
+        sourcepos,
     );
 
-    inl.append(make_inline(arena, NodeValue::Text(url)));
+    inl.append(make_inline(arena, NodeValue::Text(url), sourcepos));
     Some((inl, rewind, rewind + link_end))
 }
 
@@ -248,6 +273,7 @@ fn email_match<'a>(
     arena: &'a Arena<AstNode<'a>>,
     contents: &[u8],
     i: usize,
+    start: LineColumn,
 ) -> Option<(&'a AstNode<'a>, usize, usize)> {
     lazy_static! {
         static ref EMAIL_OK_SET: [bool; 256] = {
@@ -316,11 +342,36 @@ fn email_match<'a>(
     let mut url = b"mailto:".to_vec();
     url.extend_from_slice(&contents[i - rewind..link_end + i]);
 
-    let inl = make_inline(arena, NodeValue::Link(NodeLink { url, title: vec![] }));
+    let sourcepos = span(start, i - rewind, i + link_end);
+    let inl = make_inline(
+        arena,
+        NodeValue::Link(NodeLink {
+            url,
+            title: vec![],
+            autolink: true,
+        }),
+        sourcepos,
+    );
 
     inl.append(make_inline(
         arena,
         NodeValue::Text(contents[i - rewind..link_end + i].to_vec()),
+        sourcepos,
     ));
     Some((inl, rewind, rewind + link_end))
 }
+
+// Text nodes never span lines, so positions within them are column offsets.
+fn offset(start: LineColumn, i: usize) -> LineColumn {
+    LineColumn {
+        line: start.line,
+        column: start.column + i,
+    }
+}
+
+fn span(start: LineColumn, from: usize, to: usize) -> Sourcepos {
+    Sourcepos {
+        start: offset(start, from),
+        end: offset(start, to - 1),
+    }
+}
@@ -1,7 +1,13 @@
 use arena_tree::Node;
-use ctype::{ispunct, isspace};
+use ctype::{isalnum, isdigit, ispunct, isspace};
+#[cfg(feature = "shortcodes")]
+use emojis;
 use entity;
-use nodes::{Ast, AstNode, NodeLink, NodeValue};
+use nodes::{
+    Ast, AstNode, LineColumn, NodeAttributes, NodeFootnoteReference, NodeLink, NodeMath,
+    NodeShortCode, NodeValue, NodeWikiLink, Sourcepos,
+};
+use parser::attributes;
 use parser::{unwrap_into_2, unwrap_into_copy, AutolinkType, Callback, ComrakOptions, Reference};
 use scanners;
 use std::cell::{Cell, RefCell};
@@ -19,6 +25,9 @@ pub struct Subject<'a: 'd, 'r, 'o, 'd, 'i, 'c: 'subj, 'subj> {
     pub arena: &'a Arena<AstNode<'a>>,
     options: &'o ComrakOptions,
     pub input: &'i [u8],
+    line: usize,
+    line_offsets: &'i [usize],
+    line_starts: Vec<usize>,
     pub pos: usize,
     pub refmap: &'r mut HashMap<Vec<u8>, Reference>,
     delimiter_arena: &'d Arena<Delimiter<'a, 'd>>,
@@ -26,6 +35,9 @@ pub struct Subject<'a: 'd, 'r, 'o, 'd, 'i, 'c: 'subj, 'subj> {
     brackets: Vec<Bracket<'a, 'd>>,
     pub backticks: [usize; MAXBACKTICKS + 1],
     pub scanned_for_backticks: bool,
+    // For inline and display math, the position from which a search for closing dollars has
+    // already failed; no search starting at or after it can succeed either.
+    pub unmatched_dollars: [usize; 2],
     special_chars: [bool; 256],
     skip_chars: [bool; 256],
     smart_chars: [bool; 256],
@@ -50,23 +62,36 @@ struct Bracket<'a: 'd, 'd> {
     inl_text: &'a AstNode<'a>,
     position: usize,
     image: bool,
+    inline_footnote: bool,
     active: bool,
     bracket_after: bool,
 }
 
 impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         arena: &'a Arena<AstNode<'a>>,
         options: &'o ComrakOptions,
         input: &'i [u8],
+        line: usize,
+        line_offsets: &'i [usize],
         refmap: &'r mut HashMap<Vec<u8>, Reference>,
         delimiter_arena: &'d Arena<Delimiter<'a, 'd>>,
         callback: Option<&'subj mut Callback<'c>>,
     ) -> Self {
+        let mut line_starts = vec![0];
+        for (i, &c) in input.iter().enumerate() {
+            if c == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
         let mut s = Subject {
             arena,
             options,
             input,
+            line,
+            line_offsets,
+            line_starts,
             pos: 0,
             refmap,
             delimiter_arena,
@@ -74,6 +99,7 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             brackets: vec![],
             backticks: [0; MAXBACKTICKS + 1],
             scanned_for_backticks: false,
+            unmatched_dollars: [usize::MAX; 2],
             special_chars: [false; 256],
             skip_chars: [false; 256],
             smart_chars: [false; 256],
@@ -84,13 +110,28 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         ] {
             s.special_chars[c as usize] = true;
         }
-        if options.extension.strikethrough {
+        if options.extension.strikethrough || options.extension.subscript {
             s.special_chars[b'~' as usize] = true;
             s.skip_chars[b'~' as usize] = true;
         }
-        if options.extension.superscript {
+        if options.extension.superscript || options.extension.footnotes {
             s.special_chars[b'^' as usize] = true;
         }
+        if options.extension.highlight {
+            s.special_chars[b'=' as usize] = true;
+        }
+        if options.extension.insert {
+            s.special_chars[b'+' as usize] = true;
+        }
+        if options.extension.spoiler {
+            s.special_chars[b'|' as usize] = true;
+        }
+        if options.extension.math {
+            s.special_chars[b'$' as usize] = true;
+        }
+        if options.extension.shortcodes {
+            s.special_chars[b':' as usize] = true;
+        }
         for &c in &[b'"', b'\'', b'.', b'-'] {
             s.smart_chars[c as usize] = true;
         }
@@ -119,31 +160,67 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             '-' => new_inl = Some(self.handle_hyphen()),
             '.' => new_inl = Some(self.handle_period()),
             '[' => {
-                self.pos += 1;
-                let inl = make_inline(self.arena, NodeValue::Text(b"[".to_vec()));
-                new_inl = Some(inl);
-                self.push_bracket(false, inl);
+                if let Some(inl) = self.handle_wikilink() {
+                    new_inl = Some(inl);
+                } else {
+                    self.pos += 1;
+                    let inl =
+                        self.make_inline(NodeValue::Text(b"[".to_vec()), self.pos - 1, self.pos);
+                    new_inl = Some(inl);
+                    self.push_bracket(false, inl);
+                }
             }
             ']' => new_inl = self.handle_close_bracket(),
+            ':' if self.options.extension.shortcodes => new_inl = Some(self.handle_colon()),
             '!' => {
                 self.pos += 1;
                 if self.peek_char() == Some(&(b'[')) && self.peek_char_n(1) != Some(&(b'^')) {
                     self.pos += 1;
-                    let inl = make_inline(self.arena, NodeValue::Text(b"![".to_vec()));
+                    let inl =
+                        self.make_inline(NodeValue::Text(b"![".to_vec()), self.pos - 2, self.pos);
                     new_inl = Some(inl);
                     self.push_bracket(true, inl);
                 } else {
-                    new_inl = Some(make_inline(self.arena, NodeValue::Text(b"!".to_vec())));
+                    new_inl = Some(self.make_inline(
+                        NodeValue::Text(b"!".to_vec()),
+                        self.pos - 1,
+                        self.pos,
+                    ));
                 }
             }
             _ => {
-                if self.options.extension.strikethrough && c == '~' {
+                if (self.options.extension.strikethrough || self.options.extension.subscript)
+                    && c == '~'
+                {
                     new_inl = Some(self.handle_delim(b'~'));
+                } else if self.options.extension.footnotes
+                    && c == '^'
+                    && self.peek_char_n(1) == Some(&(b'['))
+                {
+                    self.pos += 2;
+                    let inl =
+                        self.make_inline(NodeValue::Text(b"^[".to_vec()), self.pos - 2, self.pos);
+                    new_inl = Some(inl);
+                    self.push_bracket(false, inl);
+                    self.brackets.last_mut().unwrap().inline_footnote = true;
                 } else if self.options.extension.superscript && c == '^' {
                     new_inl = Some(self.handle_delim(b'^'));
+                } else if self.options.extension.highlight && c == '=' {
+                    new_inl = Some(self.handle_delim(b'='));
+                } else if self.options.extension.insert && c == '+' {
+                    new_inl = Some(self.handle_delim(b'+'));
+                } else if self.options.extension.spoiler && c == '|' {
+                    new_inl = Some(self.handle_delim(b'|'));
+                } else if self.options.extension.math && c == '$' {
+                    new_inl = Some(self.handle_dollars());
                 } else {
+                    let startpos = self.pos;
+                    // A caret which doesn't open an inline footnote is ordinary text.
+                    if c == '^' {
+                        self.pos += 1;
+                    }
                     let endpos = self.find_special_char();
-                    let mut contents = self.input[self.pos..endpos].to_vec();
+                    let mut contents = self.input[startpos..endpos].to_vec();
                     self.pos = endpos;
 
                     if self
@@ -153,7 +230,8 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
                         strings::rtrim(&mut contents);
                     }
 
-                    new_inl = Some(make_inline(self.arena, NodeValue::Text(contents)));
+                    let endpos = startpos + contents.len();
+                    new_inl = Some(self.make_inline(NodeValue::Text(contents), startpos, endpos));
                 }
             }
         }
@@ -234,12 +312,21 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             i['_' as usize] = stack_bottom;
             i['\'' as usize] = stack_bottom;
             i['"' as usize] = stack_bottom;
-            if self.options.extension.strikethrough {
+            if self.options.extension.strikethrough || self.options.extension.subscript {
                 i['~' as usize] = stack_bottom;
             }
             if self.options.extension.superscript {
                 i['^' as usize] = stack_bottom;
             }
+            if self.options.extension.highlight {
+                i['=' as usize] = stack_bottom;
+            }
+            if self.options.extension.insert {
+                i['+' as usize] = stack_bottom;
+            }
+            if self.options.extension.spoiler {
+                i['|' as usize] = stack_bottom;
+            }
         }
 
         // This is traversing the stack from the top to the bottom, setting `closer` to
@@ -308,8 +395,12 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
                 // both get passed.
                 if closer.unwrap().delim_char == b'*'
                     || closer.unwrap().delim_char == b'_'
-                    || (self.options.extension.strikethrough && closer.unwrap().delim_char == b'~')
+                    || ((self.options.extension.strikethrough || self.options.extension.subscript)
+                        && closer.unwrap().delim_char == b'~')
                     || (self.options.extension.superscript && closer.unwrap().delim_char == b'^')
+                    || (self.options.extension.highlight && closer.unwrap().delim_char == b'=')
+                    || (self.options.extension.insert && closer.unwrap().delim_char == b'+')
+                    || (self.options.extension.spoiler && closer.unwrap().delim_char == b'|')
                 {
                     if opener_found {
                         // Finally, here's the happy case where the delimiters
@@ -462,11 +553,16 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         if self.input[self.pos] == b'\n' {
             self.pos += 1;
         }
+        let endpos = self.pos;
         self.skip_spaces();
         if nlpos > 1 && self.input[nlpos - 1] == b' ' && self.input[nlpos - 2] == b' ' {
-            make_inline(self.arena, NodeValue::LineBreak)
+            let mut startpos = nlpos - 2;
+            while startpos > 0 && self.input[startpos - 1] == b' ' {
+                startpos -= 1;
+            }
+            self.make_inline(NodeValue::LineBreak, startpos, endpos)
         } else {
-            make_inline(self.arena, NodeValue::SoftBreak)
+            self.make_inline(NodeValue::SoftBreak, nlpos, endpos)
         }
     }
 
@@ -513,12 +609,16 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         match endpos {
             None => {
                 self.pos = startpos;
-                make_inline(self.arena, NodeValue::Text(vec![b'`'; openticks]))
+                self.make_inline(
+                    NodeValue::Text(vec![b'`'; openticks]),
+                    startpos - openticks,
+                    startpos,
+                )
             }
             Some(endpos) => {
                 let buf = &self.input[startpos..endpos - openticks];
                 let buf = strings::normalize_code(buf);
-                make_inline(self.arena, NodeValue::Code(buf))
+                self.make_inline(NodeValue::Code(buf), startpos - openticks, endpos)
             }
         }
     }
@@ -546,9 +646,22 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         } else {
             self.input[self.pos - numdelims..self.pos].to_vec()
         };
-        let inl = make_inline(self.arena, NodeValue::Text(contents));
+        let inl = self.make_inline(NodeValue::Text(contents), self.pos - numdelims, self.pos);
+
+        // Highlight, insert and spoiler runs must be exactly two long.  With subscript enabled a single
+        // tilde is subscript and a double tilde strikethrough; longer runs are left as text.
+        let pairable = match c {
+            b'=' | b'+' | b'|' => numdelims == 2,
+            b'~' if self.options.extension.subscript => {
+                numdelims == 1 || (numdelims == 2 && self.options.extension.strikethrough)
+            }
+            _ => true,
+        };
 
-        if (can_open || can_close) && (!(c == b'\'' || c == b'"') || self.options.parse.smart) {
+        if (can_open || can_close)
+            && pairable
+            && (!(c == b'\'' || c == b'"') || self.options.parse.smart)
+        {
             self.push_delimiter(c, can_open, can_close, inl);
         }
 
@@ -560,7 +673,7 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         self.pos += 1;
 
         if !self.options.parse.smart || self.peek_char().map_or(false, |&c| c != b'-') {
-            return make_inline(self.arena, NodeValue::Text(vec![b'-']));
+            return self.make_inline(NodeValue::Text(vec![b'-']), start, self.pos);
         }
 
         while self.options.parse.smart && self.peek_char().map_or(false, |&c| c == b'-') {
@@ -587,21 +700,22 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             buf.extend_from_slice(b"\xE2\x80\x93");
         }
 
-        make_inline(self.arena, NodeValue::Text(buf))
+        self.make_inline(NodeValue::Text(buf), start, self.pos)
     }
 
     pub fn handle_period(&mut self) -> &'a AstNode<'a> {
+        let start = self.pos;
         self.pos += 1;
         if self.options.parse.smart && self.peek_char().map_or(false, |&c| c == b'.') {
             self.pos += 1;
             if self.peek_char().map_or(false, |&c| c == b'.') {
                 self.pos += 1;
-                make_inline(self.arena, NodeValue::Text(b"\xE2\x80\xA6".to_vec()))
+                self.make_inline(NodeValue::Text(b"\xE2\x80\xA6".to_vec()), start, self.pos)
             } else {
-                make_inline(self.arena, NodeValue::Text(b"..".to_vec()))
+                self.make_inline(NodeValue::Text(b"..".to_vec()), start, self.pos)
             }
         } else {
-            make_inline(self.arena, NodeValue::Text(b".".to_vec()))
+            self.make_inline(NodeValue::Text(b".".to_vec()), start, self.pos)
         }
     }
 
@@ -732,7 +846,7 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         opener_num_chars -= use_delims;
         closer_num_chars -= use_delims;
 
-        if self.options.extension.strikethrough
+        if (self.options.extension.strikethrough || self.options.extension.subscript)
             && opener_char == b'~'
             && (opener_num_chars != closer_num_chars || opener_num_chars > 0)
         {
@@ -764,17 +878,46 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             delim = delim.unwrap().prev.get();
         }
 
+        // The delimiters consumed are the last of the opener's and the first of the closer's.
+        let emph_sourcepos = {
+            let mut opener_ast = opener.inl.data.borrow_mut();
+            let mut closer_ast = closer.inl.data.borrow_mut();
+            opener_ast.sourcepos.end.column -= use_delims;
+            closer_ast.sourcepos.start.column += use_delims;
+            Sourcepos {
+                start: LineColumn {
+                    line: opener_ast.sourcepos.end.line,
+                    column: opener_ast.sourcepos.end.column + 1,
+                },
+                end: LineColumn {
+                    line: closer_ast.sourcepos.start.line,
+                    column: closer_ast.sourcepos.start.column - 1,
+                },
+            }
+        };
+
         let emph = make_inline(
             self.arena,
-            if self.options.extension.strikethrough && opener_char == b'~' {
+            if self.options.extension.subscript && opener_char == b'~' && use_delims == 1 {
+                NodeValue::Subscript
+            } else if self.options.extension.strikethrough && opener_char == b'~' {
                 NodeValue::Strikethrough
             } else if self.options.extension.superscript && opener_char == b'^' {
                 NodeValue::Superscript
+            } else if self.options.extension.highlight && opener_char == b'=' {
+                NodeValue::Highlight
+            } else if self.options.extension.insert && opener_char == b'+' {
+                NodeValue::Insert
+            } else if self.options.extension.spoiler && opener_char == b'|' {
+                NodeValue::SpoileredText
+            } else if self.options.extension.underline && opener_char == b'_' && use_delims == 2 {
+                NodeValue::Underline
             } else if use_delims == 1 {
                 NodeValue::Emph
             } else {
                 NodeValue::Strong
             },
+            emph_sourcepos,
         );
 
         // Drop all the interior AST nodes into the emphasis node
@@ -807,16 +950,100 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         }
     }
 
+    // Follows GitHub's rules: `$...$` is inline math provided the content neither starts nor
+    // ends with whitespace and the closing dollar isn't followed by a digit, so that prices
+    // like "$5 and $10" stay text.  `$$...$$` is display math with no such restrictions.
+    pub fn handle_dollars(&mut self) -> &'a AstNode<'a> {
+        let startpos = self.pos;
+        let opendollars = self.take_while(b'$');
+        let display = opendollars == 2;
+        let len = self.input.len();
+
+        if opendollars <= 2
+            && self.pos < len
+            && self.pos < self.unmatched_dollars[opendollars - 1]
+            && (display || !isspace(self.input[self.pos]))
+        {
+            let mut pos = self.pos;
+            while pos < len {
+                match self.input[pos] {
+                    b'\\' => pos += 2,
+                    b'$' => {
+                        let closestart = pos;
+                        while pos < len && self.input[pos] == b'$' {
+                            pos += 1;
+                        }
+                        let flanking = display
+                            || !(isspace(self.input[closestart - 1])
+                                || (pos < len && isdigit(self.input[pos])));
+                        if pos - closestart == opendollars && closestart > self.pos && flanking {
+                            let literal = self.input[self.pos..closestart].to_vec();
+                            self.pos = pos;
+                            return self.make_inline(
+                                NodeValue::Math(NodeMath {
+                                    display,
+                                    fenced: false,
+                                    literal,
+                                }),
+                                startpos,
+                                pos,
+                            );
+                        }
+                    }
+                    _ => pos += 1,
+                }
+            }
+            self.unmatched_dollars[opendollars - 1] = self.pos;
+        }
+
+        self.make_inline(NodeValue::Text(vec![b'$'; opendollars]), startpos, self.pos)
+    }
+
+    pub fn handle_colon(&mut self) -> &'a AstNode<'a> {
+        let startpos = self.pos;
+        self.pos += 1;
+
+        let len = self.input[self.pos..]
+            .iter()
+            .take_while(|&&c| isalnum(c) || c == b'_' || c == b'+' || c == b'-')
+            .count();
+        if len > 0 && self.input.get(self.pos + len) == Some(&b':') {
+            let code = str::from_utf8(&self.input[self.pos..self.pos + len]).unwrap();
+            let emoji = if self.options.extension.custom_shortcodes.contains_key(code) {
+                Some(None)
+            } else {
+                emoji_for_shortcode(code).map(Some)
+            };
+
+            if let Some(emoji) = emoji {
+                let code = code.to_string();
+                self.pos += len + 1;
+                return self.make_inline(
+                    NodeValue::ShortCode(NodeShortCode { code, emoji }),
+                    startpos,
+                    self.pos,
+                );
+            }
+        }
+
+        self.make_inline(NodeValue::Text(b":".to_vec()), startpos, self.pos)
+    }
+
     pub fn handle_backslash(&mut self) -> &'a AstNode<'a> {
+        let start = self.pos;
         self.pos += 1;
         if self.peek_char().map_or(false, |&c| ispunct(c)) {
             self.pos += 1;
             // TODO
-            make_inline(self.arena, NodeValue::Text(vec![self.input[self.pos - 1]]))
+            self.make_inline(
+                NodeValue::Text(vec![self.input[self.pos - 1]]),
+                start,
+                self.pos,
+            )
         } else if !self.eof() && self.skip_line_end() {
-            make_inline(self.arena, NodeValue::LineBreak)
+            self.make_inline(NodeValue::LineBreak, start, self.pos)
         } else {
-            make_inline(self.arena, NodeValue::Text(b"\\".to_vec()))
+            self.make_inline(NodeValue::Text(b"\\".to_vec()), start, self.pos)
         }
     }
 
@@ -832,48 +1059,41 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
     }
 
     pub fn handle_entity(&mut self) -> &'a AstNode<'a> {
+        let start = self.pos;
         self.pos += 1;
 
         match entity::unescape(&self.input[self.pos..]) {
-            None => make_inline(self.arena, NodeValue::Text(b"&".to_vec())),
+            None => self.make_inline(NodeValue::Text(b"&".to_vec()), start, self.pos),
             Some((entity, len)) => {
                 self.pos += len;
-                make_inline(self.arena, NodeValue::Text(entity))
+                self.make_inline(NodeValue::Text(entity), start, self.pos)
             }
         }
     }
 
     pub fn handle_pointy_brace(&mut self) -> &'a AstNode<'a> {
+        let start = self.pos;
         self.pos += 1;
 
         if let Some(matchlen) = scanners::autolink_uri(&self.input[self.pos..]) {
-            let inl = make_autolink(
-                self.arena,
-                &self.input[self.pos..self.pos + matchlen - 1],
-                AutolinkType::URI,
-            );
+            let inl = self.make_autolink(self.pos, self.pos + matchlen - 1, AutolinkType::URI);
             self.pos += matchlen;
             return inl;
         }
 
         if let Some(matchlen) = scanners::autolink_email(&self.input[self.pos..]) {
-            let inl = make_autolink(
-                self.arena,
-                &self.input[self.pos..self.pos + matchlen - 1],
-                AutolinkType::Email,
-            );
+            let inl = self.make_autolink(self.pos, self.pos + matchlen - 1, AutolinkType::Email);
             self.pos += matchlen;
             return inl;
         }
 
         if let Some(matchlen) = scanners::html_tag(&self.input[self.pos..]) {
-            let contents = &self.input[self.pos - 1..self.pos + matchlen];
-            let inl = make_inline(self.arena, NodeValue::HtmlInline(contents.to_vec()));
+            let contents = self.input[start..self.pos + matchlen].to_vec();
             self.pos += matchlen;
-            return inl;
+            return self.make_inline(NodeValue::HtmlInline(contents), start, self.pos);
         }
 
-        make_inline(self.arena, NodeValue::Text(b"<".to_vec()))
+        self.make_inline(NodeValue::Text(b"<".to_vec()), start, self.pos)
     }
 
     pub fn push_bracket(&mut self, image: bool, inl_text: &'a AstNode<'a>) {
@@ -886,23 +1106,134 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             inl_text,
             position: self.pos,
             image,
+            inline_footnote: false,
             active: true,
             bracket_after: false,
         });
     }
 
+    pub fn handle_wikilink(&mut self) -> Option<&'a AstNode<'a>> {
+        if !self.options.extension.wikilinks || !self.input[self.pos..].starts_with(b"[[") {
+            return None;
+        }
+
+        let startpos = self.pos;
+        let mut end = startpos + 2;
+        let mut pipe = None;
+        while end < self.input.len() {
+            match self.input[end] {
+                b']' => break,
+                b'[' | b'\r' | b'\n' => return None,
+                b'|' if pipe.is_none() => pipe = Some(end),
+                b'\\' if end + 1 < self.input.len() && ispunct(self.input[end + 1]) => end += 1,
+                _ => (),
+            }
+            end += 1;
+        }
+
+        if !self.input[end..].starts_with(b"]]") {
+            return None;
+        }
+
+        let (url, title) = match pipe {
+            None => ((startpos + 2, end), (startpos + 2, end)),
+            Some(pipe) if self.options.extension.wikilinks_title_before_pipe => {
+                ((pipe + 1, end), (startpos + 2, pipe))
+            }
+            Some(pipe) => ((startpos + 2, pipe), (pipe + 1, end)),
+        };
+
+        let trim = |(mut start, mut end): (usize, usize)| {
+            while start < end && isspace(self.input[start]) {
+                start += 1;
+            }
+            while end > start && isspace(self.input[end - 1]) {
+                end -= 1;
+            }
+            (start, end)
+        };
+        let url = trim(url);
+        let mut title = trim(title);
+        if url.0 == url.1 {
+            return None;
+        }
+        if title.0 == title.1 {
+            title = url;
+        }
+
+        self.pos = end + 2;
+        let inl = self.make_inline(
+            NodeValue::WikiLink(NodeWikiLink {
+                url: self.input[url.0..url.1].to_vec(),
+            }),
+            startpos,
+            self.pos,
+        );
+        if title == url {
+            inl.append(self.make_inline(
+                NodeValue::Text(self.input[title.0..title.1].to_vec()),
+                title.0,
+                title.1,
+            ));
+        } else {
+            self.parse_wikilink_title(inl, title);
+        }
+        Some(inl)
+    }
+
+    // Parses a wikilink's title into its node as inlines.  The title is on a single line, so a
+    // subject over just its bytes, starting at its line and column, keeps source positions right.
+    fn parse_wikilink_title(&mut self, inl: &'a AstNode<'a>, (start, end): (usize, usize)) {
+        let linecol = self.linecol(start);
+        let line_offsets = [linecol.column - 1];
+        let delimiter_arena = Arena::new();
+        let mut subj = Subject::new(
+            self.arena,
+            self.options,
+            &self.input[start..end],
+            linecol.line,
+            &line_offsets,
+            &mut *self.refmap,
+            &delimiter_arena,
+            self.callback.as_deref_mut(),
+        );
+
+        while subj.parse_inline(inl) {}
+
+        subj.process_emphasis(None);
+
+        while subj.pop_bracket() {}
+    }
+
     pub fn handle_close_bracket(&mut self) -> Option<&'a AstNode<'a>> {
         self.pos += 1;
         let initial_pos = self.pos;
 
         let brackets_len = self.brackets.len();
         if brackets_len == 0 {
-            return Some(make_inline(self.arena, NodeValue::Text(b"]".to_vec())));
+            return Some(self.make_inline(NodeValue::Text(b"]".to_vec()), self.pos - 1, self.pos));
         }
 
         if !self.brackets[brackets_len - 1].active {
             self.brackets.pop();
-            return Some(make_inline(self.arena, NodeValue::Text(b"]".to_vec())));
+            return Some(self.make_inline(NodeValue::Text(b"]".to_vec()), self.pos - 1, self.pos));
+        }
+
+        if self.brackets[brackets_len - 1].inline_footnote {
+            if self.brackets[brackets_len - 1]
+                .inl_text
+                .next_sibling()
+                .is_none()
+            {
+                self.brackets.pop();
+                return Some(self.make_inline(
+                    NodeValue::Text(b"]".to_vec()),
+                    self.pos - 1,
+                    self.pos,
+                ));
+            }
+            self.close_inline_footnote();
+            return None;
         }
 
         let is_image = self.brackets[brackets_len - 1].image;
@@ -990,7 +1321,23 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         {
             let text = text.unwrap();
             if text.len() > 1 && text[0] == b'^' {
-                let inl = make_inline(self.arena, NodeValue::FootnoteReference(text[1..].to_vec()));
+                let inl = make_inline(
+                    self.arena,
+                    NodeValue::FootnoteReference(NodeFootnoteReference {
+                        name: text[1..].to_vec(),
+                        ix: 0,
+                        ref_num: 0,
+                    }),
+                    Sourcepos {
+                        start: self.brackets[brackets_len - 1]
+                            .inl_text
+                            .data
+                            .borrow()
+                            .sourcepos
+                            .start,
+                        end: self.linecol(self.pos - 1),
+                    },
+                );
                 self.brackets[brackets_len - 1].inl_text.insert_before(inl);
                 self.brackets[brackets_len - 1]
                     .inl_text
@@ -1007,11 +1354,25 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
 
         self.brackets.pop();
         self.pos = initial_pos;
-        Some(make_inline(self.arena, NodeValue::Text(b"]".to_vec())))
+        Some(self.make_inline(NodeValue::Text(b"]".to_vec()), self.pos - 1, self.pos))
     }
 
     pub fn close_bracket_match(&mut self, is_image: bool, url: Vec<u8>, title: Vec<u8>) {
-        let nl = NodeLink { url, title };
+        let brackets_len = self.brackets.len();
+
+        let mut attributes = NodeAttributes::default();
+        if self.options.extension.attributes {
+            if let Some((parsed, len)) = attributes::parse(&self.input[self.pos..]) {
+                attributes = parsed;
+                self.pos += len;
+            }
+        }
+
+        let nl = NodeLink {
+            url,
+            title,
+            autolink: false,
+        };
         let inl = make_inline(
             self.arena,
             if is_image {
@@ -1019,9 +1380,18 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             } else {
                 NodeValue::Link(nl)
             },
+            Sourcepos {
+                start: self.brackets[brackets_len - 1]
+                    .inl_text
+                    .data
+                    .borrow()
+                    .sourcepos
+                    .start,
+                end: self.linecol(self.pos - 1),
+            },
         );
+        inl.data.borrow_mut().attributes = attributes;
 
-        let mut brackets_len = self.brackets.len();
         self.brackets[brackets_len - 1].inl_text.insert_before(inl);
         let mut tmpch = self.brackets[brackets_len - 1].inl_text.next_sibling();
         while let Some(tmp) = tmpch {
@@ -1032,23 +1402,64 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
         let previous_delimiter = self.brackets[brackets_len - 1].previous_delimiter;
         self.process_emphasis(previous_delimiter);
         self.brackets.pop();
-        brackets_len -= 1;
 
         if !is_image {
-            let mut i = brackets_len as i32 - 1;
-            while i >= 0 {
-                if !self.brackets[i as usize].image {
-                    if !self.brackets[i as usize].active {
-                        break;
-                    } else {
-                        self.brackets[i as usize].active = false;
-                    }
+            self.deactivate_link_openers();
+        }
+    }
+
+    // Links may not contain other links, nor footnote references, so no earlier link opener can
+    // be matched once one has been made.
+    fn deactivate_link_openers(&mut self) {
+        let mut i = self.brackets.len() as i32 - 1;
+        while i >= 0 {
+            if !self.brackets[i as usize].image && !self.brackets[i as usize].inline_footnote {
+                if !self.brackets[i as usize].active {
+                    break;
+                } else {
+                    self.brackets[i as usize].active = false;
                 }
-                i -= 1;
             }
+            i -= 1;
         }
     }
 
+    // Closes an inline footnote `^[...]` as a footnote reference with no name, containing the
+    // footnote's content.  `Parser::process_footnotes` moves the content into a definition and
+    // names both.
+    pub fn close_inline_footnote(&mut self) {
+        let brackets_len = self.brackets.len();
+        let inl = make_inline(
+            self.arena,
+            NodeValue::FootnoteReference(NodeFootnoteReference {
+                name: vec![],
+                ix: 0,
+                ref_num: 0,
+            }),
+            Sourcepos {
+                start: self.brackets[brackets_len - 1]
+                    .inl_text
+                    .data
+                    .borrow()
+                    .sourcepos
+                    .start,
+                end: self.linecol(self.pos - 1),
+            },
+        );
+
+        self.brackets[brackets_len - 1].inl_text.insert_before(inl);
+        let mut tmpch = self.brackets[brackets_len - 1].inl_text.next_sibling();
+        while let Some(tmp) = tmpch {
+            tmpch = tmp.next_sibling();
+            inl.append(tmp);
+        }
+        self.brackets[brackets_len - 1].inl_text.detach();
+        let previous_delimiter = self.brackets[brackets_len - 1].previous_delimiter;
+        self.process_emphasis(previous_delimiter);
+        self.brackets.pop();
+        self.deactivate_link_openers();
+    }
+
     pub fn link_label(&mut self) -> Option<&[u8]> {
         let startpos = self.pos;
 
@@ -1094,6 +1505,42 @@ impl<'a, 'r, 'o, 'd, 'i, 'c, 'subj> Subject<'a, 'r, 'o, 'd, 'i, 'c, 'subj> {
             self.skip_spaces();
         }
     }
+
+    // Maps a byte offset into the input to its line and column in the document.
+    fn linecol(&self, pos: usize) -> LineColumn {
+        let ix = match self.line_starts.binary_search(&pos) {
+            Ok(ix) => ix,
+            Err(ix) => ix - 1,
+        };
+        LineColumn {
+            line: self.line + ix,
+            column: self.line_offsets.get(ix).map_or(0, |&o| o) + pos - self.line_starts[ix] + 1,
+        }
+    }
+
+    // Makes an inline spanning the input bytes from `start` up to but excluding `end`.
+    fn make_inline(&self, value: NodeValue, start: usize, end: usize) -> &'a AstNode<'a> {
+        let sourcepos = Sourcepos {
+            start: self.linecol(start),
+            end: self.linecol(if end > start { end - 1 } else { start }),
+        };
+        make_inline(self.arena, value, sourcepos)
+    }
+
+    fn make_autolink(&self, start: usize, end: usize, kind: AutolinkType) -> &'a AstNode<'a> {
+        let url = &self.input[start..end];
+        let inl = self.make_inline(
+            NodeValue::Link(NodeLink {
+                url: strings::clean_autolink(url, kind),
+                title: vec![],
+                autolink: true,
+            }),
+            start - 1,
+            end + 1,
+        );
+        inl.append(self.make_inline(NodeValue::Text(entity::unescape_html(url)), start, end));
+        inl
+    }
 }
 
 pub fn manual_scan_link_url(input: &[u8]) -> Option<(&[u8], usize)> {
@@ -1163,32 +1610,31 @@ pub fn manual_scan_link_url_2(input: &[u8]) -> Option<(&[u8], usize)> {
     }
 }
 
-pub fn make_inline<'a>(arena: &'a Arena<AstNode<'a>>, value: NodeValue) -> &'a AstNode<'a> {
+pub fn make_inline<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    value: NodeValue,
+    sourcepos: Sourcepos,
+) -> &'a AstNode<'a> {
     let ast = Ast {
         value,
         content: vec![],
-        start_line: 0,
+        sourcepos,
+        attributes: NodeAttributes::default(),
+        line_offsets: vec![],
         open: false,
         last_line_blank: false,
     };
     arena.alloc(Node::new(RefCell::new(ast)))
 }
 
-fn make_autolink<'a>(
-    arena: &'a Arena<AstNode<'a>>,
-    url: &[u8],
-    kind: AutolinkType,
-) -> &'a AstNode<'a> {
-    let inl = make_inline(
-        arena,
-        NodeValue::Link(NodeLink {
-            url: strings::clean_autolink(url, kind),
-            title: vec![],
-        }),
-    );
-    inl.append(make_inline(
-        arena,
-        NodeValue::Text(entity::unescape_html(url)),
-    ));
-    inl
+#[cfg(feature = "shortcodes")]
+fn emoji_for_shortcode(code: &str) -> Option<String> {
+    emojis::get_by_shortcode(code).map(|emoji| emoji.as_str().to_string())
+}
+
+// Without the `shortcodes` feature there's no emoji table, so only custom shortcodes are
+// recognised.
+#[cfg(not(feature = "shortcodes"))]
+fn emoji_for_shortcode(_code: &str) -> Option<String> {
+    None
 }
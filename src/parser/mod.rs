@@ -1,20 +1,29 @@
+mod attributes;
 mod autolink;
 mod inlines;
 mod table;
 
+use adapters::{
+    ContainerRendererAdapter, NodeRendererAdapter, SyntaxHighlighterAdapter, UrlRewriterAdapter,
+    WikiLinkResolverAdapter,
+};
 use arena_tree::Node;
 use ctype::{isdigit, isspace};
 use entity;
+use html::ExternalLinks;
 use nodes;
 use nodes::{
-    Ast, AstNode, ListDelimType, ListType, NodeCodeBlock, NodeDescriptionItem, NodeHeading,
-    NodeHtmlBlock, NodeList, NodeValue,
+    AlertType, Ast, AstNode, ListDelimType, ListType, NodeAlert, NodeAttributes, NodeCodeBlock,
+    NodeContainer, NodeDescriptionItem, NodeFootnoteDefinition, NodeHeading, NodeHtmlBlock,
+    NodeList, NodeMath, NodeValue, Sourcepos,
 };
 use regex::bytes::{Regex, RegexBuilder};
+use sanitize::{Sanitizer, UrlPolicy};
 use scanners;
 use std::cell::RefCell;
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::mem;
 use std::str;
 use strings;
@@ -87,16 +96,178 @@ pub fn parse_document_with_broken_link_callback<'a, 'c>(
     options: &ComrakOptions,
     callback: Option<Callback<'c>>,
 ) -> &'a AstNode<'a> {
-    let root: &'a AstNode<'a> = arena.alloc(Node::new(RefCell::new(Ast {
+    let root = new_document(arena);
+    let mut parser = Parser::new(arena, root, options, callback);
+    parser.feed(buffer);
+    parser.finish()
+}
+
+/// Parse a Markdown document to an AST, also returning the link reference definitions found.
+pub(crate) fn parse_document_with_refmap<'a>(
+    arena: &'a Arena<AstNode<'a>>,
+    buffer: &str,
+    options: &ComrakOptions,
+) -> (&'a AstNode<'a>, HashMap<Vec<u8>, Reference>) {
+    let root = new_document(arena);
+    let mut parser = Parser::new(arena, root, options, None);
+    parser.feed(buffer);
+    parser.finish();
+    (root, parser.refmap)
+}
+
+/// Parse the blocks of a run of lines from the middle of a document, starting at `first_line`.
+/// The blocks are the children of a new document node, and their inlines are parsed by
+/// `Parser::finish_inlines` once the caller knows which link reference definitions apply.
+///
+/// Unless `at_end` is set, `None` is returned if a block is still open at the end of the buffer,
+/// since the lines which follow could continue it.
+pub(crate) fn parse_blocks_from<'a, 'o>(
+    arena: &'a Arena<AstNode<'a>>,
+    buffer: &str,
+    options: &'o ComrakOptions,
+    first_line: usize,
+    at_end: bool,
+) -> Option<Parser<'a, 'o, 'static>> {
+    let root = new_document(arena);
+    let mut parser = Parser::new(arena, root, options, None);
+    parser.line_number = first_line - 1;
+    parser.feed(buffer);
+    if !at_end {
+        if let Some(last) = root.last_child() {
+            if last.data.borrow().open {
+                return None;
+            }
+        }
+    }
+
+    parser.close_blocks();
+    trim_list_sourcepos(root);
+    Some(parser)
+}
+
+/// Parses a document a line at a time, handing back each top-level block once no later line can
+/// change it, with its inlines parsed.  Used by `event::events`.
+///
+/// Inlines are parsed as soon as their block is handed back, so links can only use the link
+/// reference definitions before them.  With footnotes enabled, nothing is handed back until the
+/// whole document has been parsed, since their numbering depends on all of it.
+pub(crate) struct BlockStream<'a, 'o> {
+    parser: Parser<'a, 'o, 'static>,
+    input: &'o [u8],
+    pos: usize,
+    finished: bool,
+}
+
+impl<'a, 'o> BlockStream<'a, 'o> {
+    pub(crate) fn new(
+        arena: &'a Arena<AstNode<'a>>,
+        input: &'o str,
+        options: &'o ComrakOptions,
+    ) -> Self {
+        let root = new_document(arena);
+        let mut parser = Parser::new(arena, root, options, None);
+        let pos = parser.feed_front_matter(input.as_bytes());
+        BlockStream {
+            parser,
+            input: input.as_bytes(),
+            pos,
+            finished: false,
+        }
+    }
+
+    /// The next top-level block, detached from the document, or `None` once all have been
+    /// handed back.
+    pub(crate) fn next_block(&mut self) -> Option<&'a AstNode<'a>> {
+        loop {
+            if let Some(block) = self.ready_block() {
+                block.detach();
+                if !self.parser.options.extension.footnotes {
+                    trim_list_sourcepos(block);
+                    self.parser.process_inlines_node(block);
+                    self.parser.postprocess_text_nodes(block);
+                }
+                return Some(block);
+            }
+
+            if self.finished {
+                return None;
+            } else if self.pos < self.input.len() {
+                self.pos = self.parser.feed_line(self.input, self.pos);
+            } else {
+                if self.parser.options.extension.footnotes {
+                    self.parser.finish();
+                } else {
+                    self.parser.close_blocks();
+                }
+                self.finished = true;
+            }
+        }
+    }
+
+    // The first top-level block, if nothing later can change it.  Only the last block can be
+    // open, but a description list's details can still take the paragraph before them as their
+    // term, and join a description list before that.
+    fn ready_block(&self) -> Option<&'a AstNode<'a>> {
+        let root = self.parser.root;
+        if self.finished {
+            return root.first_child();
+        }
+        if self.parser.options.extension.footnotes {
+            return None;
+        }
+        let following = if self.parser.options.extension.description_lists {
+            2
+        } else {
+            1
+        };
+        if root.children().nth(following).is_some() {
+            root.first_child()
+        } else {
+            None
+        }
+    }
+}
+
+// Lists and items can run on over trailing blank lines, and a list may be closed before its last
+// item is; once everything is closed, end them with their last child instead.
+fn trim_list_sourcepos<'a>(root: &'a AstNode<'a>) {
+    let nodes = root.descendants().collect::<Vec<_>>();
+    for node in nodes.into_iter().rev() {
+        let mut ast = node.data.borrow_mut();
+        if let NodeValue::List(..) | NodeValue::Item(..) = ast.value {
+            if let Some(last_child) = node.last_child() {
+                let last_end = last_child.data.borrow().sourcepos.end;
+                if last_end.line < ast.sourcepos.end.line {
+                    ast.sourcepos.end = last_end;
+                }
+            }
+        }
+    }
+}
+
+fn new_document<'a>(arena: &'a Arena<AstNode<'a>>) -> &'a AstNode<'a> {
+    arena.alloc(Node::new(RefCell::new(Ast {
         value: NodeValue::Document,
         content: vec![],
-        start_line: 0,
+        sourcepos: (1, 1, 0, 0).into(),
+        attributes: NodeAttributes::default(),
+        line_offsets: vec![],
         open: true,
         last_line_blank: false,
-    })));
-    let mut parser = Parser::new(arena, root, options, callback);
-    parser.feed(buffer);
-    parser.finish()
+    })))
+}
+
+// Moves an attribute block ending a heading's content to its attributes.
+fn take_heading_attributes<'a>(node: &'a AstNode<'a>) {
+    let mut ast = node.data.borrow_mut();
+    if !matches!(ast.value, NodeValue::Heading(..)) {
+        return;
+    }
+
+    if let Some((attributes, start)) = attributes::parse_trailing(&ast.content) {
+        ast.attributes = attributes;
+        ast.content.truncate(start);
+    }
 }
 
 type Callback<'c> = &'c mut dyn FnMut(&[u8]) -> Option<(Vec<u8>, Vec<u8>)>;
@@ -106,7 +277,8 @@ pub struct Parser<'a, 'o, 'c> {
     refmap: HashMap<Vec<u8>, Reference>,
     root: &'a AstNode<'a>,
     current: &'a AstNode<'a>,
-    line_number: u32,
+    line_number: usize,
+    curline_len: usize,
     offset: usize,
     column: usize,
     first_nonspace: usize,
@@ -120,6 +292,7 @@ pub struct Parser<'a, 'o, 'c> {
 }
 
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 /// Umbrella options struct.
 pub struct ComrakOptions {
     /// Enable CommonMark extensions.
@@ -133,6 +306,7 @@ pub struct ComrakOptions {
 }
 
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 /// Options to select extensions.
 pub struct ComrakExtensionOptions {
     /// Enables the
@@ -216,6 +390,66 @@ pub struct ComrakExtensionOptions {
     /// ```
     pub superscript: bool,
 
+    /// Enables the highlight extension, rendering `==text==` as `<mark>`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.highlight = true;
+    /// assert_eq!(markdown_to_html("Hello ==world== there.\n", &options),
+    ///            "<p>Hello <mark>world</mark> there.</p>\n");
+    /// ```
+    pub highlight: bool,
+
+    /// Enables the insert extension, rendering `++text++` as `<ins>`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.insert = true;
+    /// assert_eq!(markdown_to_html("Hello ++world++ there.\n", &options),
+    ///            "<p>Hello <ins>world</ins> there.</p>\n");
+    /// ```
+    pub insert: bool,
+
+    /// Enables the subscript extension, rendering `~text~` as `<sub>`.
+    ///
+    /// If `strikethrough` is also enabled, single tildes become subscript and double tildes
+    /// remain strikethrough.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.subscript = true;
+    /// options.extension.strikethrough = true;
+    /// assert_eq!(markdown_to_html("H~2~O is ~~not~~ water.\n", &options),
+    ///            "<p>H<sub>2</sub>O is <del>not</del> water.</p>\n");
+    /// ```
+    pub subscript: bool,
+
+    /// Enables the spoiler extension, rendering `||text||` as a `<span class="spoiler">`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.spoiler = true;
+    /// assert_eq!(markdown_to_html("Hello ||world|| there.\n", &options),
+    ///            "<p>Hello <span class=\"spoiler\">world</span> there.</p>\n");
+    /// ```
+    pub spoiler: bool,
+
+    /// Enables the underline extension, rendering `__text__` as `<u>` instead of `<strong>`.
+    /// `**text**` remains strong.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.underline = true;
+    /// assert_eq!(markdown_to_html("__Hello__ **world**.\n", &options),
+    ///            "<p><u>Hello</u> <strong>world</strong>.</p>\n");
+    /// ```
+    pub underline: bool,
+
     /// Enables the header IDs Comrak extension.
     ///
     /// ```
@@ -232,12 +466,18 @@ pub struct ComrakExtensionOptions {
     /// For usage, see `src/tests.rs`.  The extension is modelled after
     /// [Kramdown](https://kramdown.gettalong.org/syntax.html#footnotes).
     ///
+    /// Inline footnotes such as `^[A greeting.]` are also recognised, as in Pandoc.  They are
+    /// given a definition named `inline-1`, `inline-2` and so on, skipping any name already used
+    /// by another footnote's definition or reference.
+    ///
     /// ```
     /// # use comrak::{markdown_to_html, ComrakOptions};
     /// let mut options = ComrakOptions::default();
     /// options.extension.footnotes = true;
     /// assert_eq!(markdown_to_html("Hi[^x].\n\n[^x]: A greeting.\n", &options),
     ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup>.</p>\n<section class=\"footnotes\">\n<ol>\n<li id=\"fn1\">\n<p>A greeting. <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n</li>\n</ol>\n</section>\n");
+    /// assert_eq!(markdown_to_html("Hi^[A greeting.]\n", &options),
+    ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup></p>\n<section class=\"footnotes\">\n<ol>\n<li id=\"fn1\">\n<p>A greeting. <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n</li>\n</ol>\n</section>\n");
     /// ```
     pub footnotes: bool,
 
@@ -306,9 +546,172 @@ pub struct ComrakExtensionOptions {
     /// assert_eq!(&String::from_utf8(buf).unwrap(), input);
     /// ```
     pub front_matter_delimiter: Option<String>,
+
+    /// Enables the wikilinks extension.
+    ///
+    /// `[[Page]]` links to the page named `Page`, and `[[Page|title]]` does the same with a
+    /// different link title, which is parsed as inlines.  A backslash-escaped `|` or `]` doesn't
+    /// end the page name or title.  Page names are used as URLs as they are; see
+    /// `ComrakRenderPlugins::wikilink_resolver` to map them to URLs yourself.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.wikilinks = true;
+    /// assert_eq!(markdown_to_html("See [[Home]] and [[Other page|this]].\n", &options),
+    ///            "<p>See <a href=\"Home\" data-wikilink=\"true\">Home</a> and \
+    ///             <a href=\"Other%20page\" data-wikilink=\"true\">this</a>.</p>\n");
+    /// ```
+    pub wikilinks: bool,
+
+    /// Wikilinks give the link title before the pipe and the page name after it, i.e.
+    /// `[[title|Page]]`, rather than the other way around.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.wikilinks = true;
+    /// options.extension.wikilinks_title_before_pipe = true;
+    /// assert_eq!(markdown_to_html("[[this|Other page]]\n", &options),
+    ///            "<p><a href=\"Other%20page\" data-wikilink=\"true\">this</a></p>\n");
+    /// ```
+    pub wikilinks_title_before_pipe: bool,
+
+    /// Enables math, written between dollar signs or in a fenced code block with the info
+    /// string `math`.
+    ///
+    /// `$...$` is inline math and `$$...$$` display math.  As on GitHub, inline math may not
+    /// start or end with whitespace, nor be directly followed by a digit.  The TeX source is
+    /// passed through untouched for a client-side renderer such as MathJax or KaTeX.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.math = true;
+    /// assert_eq!(markdown_to_html("$x^2$ costs $5 or $$\\frac{1}{2}$$\n", &options),
+    ///            "<p><span data-math-style=\"inline\">x^2</span> costs $5 or \
+    ///             <span data-math-style=\"display\">\\frac{1}{2}</span></p>\n");
+    ///
+    /// assert_eq!(markdown_to_html("```math\nx^2\n```\n", &options),
+    ///            "<pre><code class=\"language-math\" data-math-style=\"display\">x^2\n</code></pre>\n");
+    /// ```
+    pub math: bool,
+
+    /// Enables GitHub-style alerts: block quotes whose first line is `[!NOTE]`, `[!TIP]`,
+    /// `[!IMPORTANT]`, `[!WARNING]` or `[!CAUTION]`.
+    ///
+    /// Any text after the alert type on that line is used as the alert's title in place of the
+    /// default one.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.alerts = true;
+    /// assert_eq!(markdown_to_html("> [!NOTE]\n> Read this.\n", &options),
+    ///            "<div class=\"markdown-alert markdown-alert-note\">\n\
+    ///             <p class=\"markdown-alert-title\">Note</p>\n\
+    ///             <p>Read this.</p>\n\
+    ///             </div>\n");
+    ///
+    /// assert_eq!(markdown_to_html("> [!tip] Pro tip\n> Read this.\n", &options),
+    ///            "<div class=\"markdown-alert markdown-alert-tip\">\n\
+    ///             <p class=\"markdown-alert-title\">Pro tip</p>\n\
+    ///             <p>Read this.</p>\n\
+    ///             </div>\n");
+    /// ```
+    pub alerts: bool,
+
+    /// Enables the table of contents placeholder: a paragraph consisting only of `[[_TOC_]]` or
+    /// `[TOC]` is replaced by a list of links to the document's headings.
+    ///
+    /// Link targets are the IDs given to headings by `header_ids`, which should usually be set
+    /// too.  See the `toc` module to build a table of contents yourself.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.table_of_contents = true;
+    /// options.extension.header_ids = Some("".to_string());
+    /// assert_eq!(markdown_to_html("[TOC]\n\n# Hi\n", &options),
+    ///            "<nav class=\"table-of-contents\">\n\
+    ///             <ul>\n<li><a href=\"#hi\">Hi</a></li>\n</ul>\n\
+    ///             </nav>\n\
+    ///             <h1><a href=\"#hi\" aria-hidden=\"true\" class=\"anchor\" id=\"hi\"></a>Hi</h1>\n");
+    /// ```
+    pub table_of_contents: bool,
+
+    /// Enables Pandoc-style attribute blocks such as `{#id .class key=value}`, which give
+    /// attributes to the preceding heading, fenced code block, link or image.
+    ///
+    /// A heading's block ends its line, and a code block's ends its info string; a link's or
+    /// image's follows it immediately.  An `id` given to a heading takes the place of the one
+    /// generated by `header_ids`, with a suffix added if it's already in use.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.attributes = true;
+    /// assert_eq!(markdown_to_html("## Setup {#setup .wide}\n\n![chart](c.png){width=400}\n", &options),
+    ///            "<h2 id=\"setup\" class=\"wide\">Setup</h2>\n\
+    ///             <p><img src=\"c.png\" alt=\"chart\" width=\"400\" /></p>\n");
+    /// ```
+    pub attributes: bool,
+
+    /// Enables custom containers: blocks fenced by three or more colons, the opening fence
+    /// followed by the container's name, as in markdown-it-container.  Containers may be nested;
+    /// a closing fence closes the innermost container it is long enough for.
+    ///
+    /// The HTML formatter renders them as a `div` with the name as its class, which can be
+    /// customised with the `container_renderer` plugin.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.custom_containers = true;
+    /// assert_eq!(markdown_to_html("::: note\nHello, *world*!\n:::\n", &options),
+    ///            "<div class=\"note\">\n<p>Hello, <em>world</em>!</p>\n</div>\n");
+    /// ```
+    pub custom_containers: bool,
+
+    /// Enables emoji shortcodes such as `:tada:`, using GitHub's names for emoji.  The HTML
+    /// formatter renders them as the emoji itself, and the CommonMark formatter as the shortcode.
+    ///
+    /// Emoji are only recognised with the `shortcodes` feature, which provides the table of
+    /// names; without it, only `custom_shortcodes` are.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.shortcodes = true;
+    /// # #[cfg(feature = "shortcodes")]
+    /// assert_eq!(markdown_to_html("Shipped :tada: :+1: :not-an-emoji:\n", &options),
+    ///            "<p>Shipped 🎉 👍 :not-an-emoji:</p>\n");
+    /// ```
+    pub shortcodes: bool,
+
+    /// Custom shortcodes recognised by the `shortcodes` extension, mapping each name to the HTML
+    /// it is rendered as, such as an image of a company logo.  They take precedence over emoji of
+    /// the same name.
+    ///
+    /// Names may contain ASCII letters, digits, `_`, `+` and `-`.  The HTML is written as it is,
+    /// whatever the `unsafe_` option.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.shortcodes = true;
+    /// options.extension.custom_shortcodes.insert(
+    ///     "acme".to_string(),
+    ///     "<img class=\"emoji\" src=\"/acme.png\" alt=\":acme:\" />".to_string(),
+    /// );
+    /// assert_eq!(markdown_to_html("Made by :acme:\n", &options),
+    ///            "<p>Made by <img class=\"emoji\" src=\"/acme.png\" alt=\":acme:\" /></p>\n");
+    /// ```
+    pub custom_shortcodes: HashMap<String, String>,
 }
 
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 /// Options for parser functions.
 pub struct ComrakParseOptions {
     /// Punctuation (quotes, full-stops and hyphens) are converted into 'smart' punctuation.
@@ -340,7 +743,8 @@ pub struct ComrakParseOptions {
     pub default_info_string: Option<String>,
 }
 
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 /// Options for formatter functions.
 pub struct ComrakRenderOptions {
     /// [Soft line breaks](http://spec.commonmark.org/0.27/#soft-line-breaks) in the input
@@ -435,9 +839,307 @@ pub struct ComrakRenderOptions {
     ///            "<p>&lt;i&gt;italic text&lt;/i&gt;</p>\n");
     /// ```
     pub escape: bool,
+
+    /// Sanitize raw HTML against an allowlist of tags, attributes and URL schemes, rather than
+    /// omitting it.  Takes precedence over `unsafe_` for raw HTML, but not over `escape`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// use comrak::sanitize::Sanitizer;
+    ///
+    /// let mut options = ComrakOptions::default();
+    /// let input = "<details open onclick=\"evil()\">\n<summary>Hi</summary>\n<script>evil()</script>\n</details>\n\n\
+    ///              Press <kbd>Ctrl</kbd> and <a href=\"javascript:evil()\">this</a>.\n";
+    ///
+    /// options.render.sanitize = Some(Sanitizer::default());
+    /// assert_eq!(markdown_to_html(input, &options),
+    ///            "<details open>\n<summary>Hi</summary>\n\n</details>\n\
+    ///             <p>Press <kbd>Ctrl</kbd> and <a>this</a>.</p>\n");
+    /// ```
+    pub sanitize: Option<Sanitizer>,
+
+    /// Check the URLs of links and images against a policy of allowed or denied schemes, in
+    /// addition to removing dangerous URLs.  The policy applies even when `unsafe_` is set.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// use comrak::sanitize::{RejectedUrl, UrlPolicy};
+    ///
+    /// let mut options = ComrakOptions::default();
+    /// let input = "[a](ftp://x) ![b](ftp://y.png) <https://z>\n";
+    ///
+    /// options.render.url_policy = Some(UrlPolicy::deny(&["ftp"]));
+    /// assert_eq!(markdown_to_html(input, &options),
+    ///            "<p><a href=\"\">a</a> <img src=\"\" alt=\"b\" /> <a href=\"https://z\">https://z</a></p>\n");
+    ///
+    /// let mut policy = UrlPolicy::deny(&["ftp"]);
+    /// policy.rejected = RejectedUrl::Placeholder("#blocked".into());
+    /// options.render.url_policy = Some(policy);
+    /// assert_eq!(markdown_to_html(input, &options),
+    ///            "<p><a href=\"#blocked\">a</a> <img src=\"#blocked\" alt=\"b\" /> <a href=\"https://z\">https://z</a></p>\n");
+    /// ```
+    pub url_policy: Option<UrlPolicy>,
+
+    /// Add attributes such as `rel` and `target` to links to other sites, including autolinks.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// use comrak::html::ExternalLinks;
+    ///
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.autolink = true;
+    /// options.render.external_links = Some(ExternalLinks {
+    ///     internal_hosts: vec!["example.com".into()],
+    ///     rel: Some("nofollow noopener ugc".into()),
+    ///     target: Some("_blank".into()),
+    ///     class: None,
+    /// });
+    ///
+    /// assert_eq!(markdown_to_html("[a](https://docs.example.com/) [b](/c) www.other.org\n", &options),
+    ///            "<p><a href=\"https://docs.example.com/\">a</a> <a href=\"/c\">b</a> \
+    ///             <a href=\"http://www.other.org\" rel=\"nofollow noopener ugc\" target=\"_blank\">www.other.org</a></p>\n");
+    /// ```
+    pub external_links: Option<ExternalLinks>,
+
+    /// Prefix the IDs of footnotes and footnote references with the given string, so that
+    /// several documents rendered onto one page don't produce clashing IDs.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.footnotes = true;
+    /// options.render.footnote_id_prefix = Some("comment-7-".to_string());
+    /// assert_eq!(markdown_to_html("Hi[^note].\n\n[^note]: There.\n", &options),
+    ///            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#comment-7-fn1\" id=\"comment-7-fnref1\">1</a></sup>.</p>\n\
+    ///             <section class=\"footnotes\">\n<ol>\n<li id=\"comment-7-fn1\">\n\
+    ///             <p>There. <a href=\"#comment-7-fnref1\" class=\"footnote-backref\">↩</a></p>\n\
+    ///             </li>\n</ol>\n</section>\n");
+    /// ```
+    pub footnote_id_prefix: Option<String>,
+
+    /// Include source position attributes in HTML output.
+    ///
+    /// Block elements get a `data-sourcepos` attribute giving the start and end line and column
+    /// of the node in the input, as cmark-gfm does.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions};
+    /// let mut options = ComrakOptions::default();
+    /// options.render.sourcepos = true;
+    /// assert_eq!(markdown_to_html("# Hello\n\n> *world*\n", &options),
+    ///            "<h1 data-sourcepos=\"1:1-1:7\">Hello</h1>\n\
+    ///             <blockquote data-sourcepos=\"3:1-3:9\">\n\
+    ///             <p data-sourcepos=\"3:3-3:9\"><em>world</em></p>\n\
+    ///             </blockquote>\n");
+    /// ```
+    pub sourcepos: bool,
+}
+
+#[derive(Default, Debug)]
+/// Umbrella plugins struct.
+pub struct ComrakPlugins<'p> {
+    /// Configure render-time plugins.
+    pub render: ComrakRenderPlugins<'p>,
 }
 
-#[derive(Clone)]
+#[derive(Default)]
+/// Plugins for alternative rendering.
+pub struct ComrakRenderPlugins<'p> {
+    /// Provide a syntax highlighter adapter implementation for syntax
+    /// highlighting of codefence blocks.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html, ComrakOptions, ComrakPlugins, markdown_to_html_with_plugins};
+    /// # use comrak::adapters::SyntaxHighlighterAdapter;
+    /// # use std::collections::HashMap;
+    /// # use std::io::{self, Write};
+    /// let options = ComrakOptions::default();
+    /// let mut plugins = ComrakPlugins::default();
+    /// let input = "```rust\nfn main<'a>();\n```";
+    ///
+    /// assert_eq!(markdown_to_html_with_plugins(input, &options, &plugins),
+    ///            "<pre><code class=\"language-rust\">fn main&lt;'a&gt;();\n</code></pre>\n");
+    ///
+    /// pub struct MockAdapter {}
+    /// impl SyntaxHighlighterAdapter for MockAdapter {
+    ///     fn write_highlighted(&self, output: &mut dyn Write, lang: Option<&str>, code: &str) -> io::Result<()> {
+    ///         write!(output, "<span class=\"lang-{}\">{}</span>", lang.unwrap(), code)
+    ///     }
+    ///
+    ///     fn write_pre_tag(&self, output: &mut dyn Write, _attributes: HashMap<String, String>) -> io::Result<()> {
+    ///         output.write_all(b"<pre lang=\"rust\">")
+    ///     }
+    ///
+    ///     fn write_code_tag(&self, output: &mut dyn Write, _attributes: HashMap<String, String>) -> io::Result<()> {
+    ///         output.write_all(b"<code class=\"language-rust\">")
+    ///     }
+    /// }
+    ///
+    /// let adapter = MockAdapter {};
+    /// plugins.render.codefence_syntax_highlighter = Some(&adapter);
+    ///
+    /// assert_eq!(markdown_to_html_with_plugins(input, &options, &plugins),
+    ///            "<pre lang=\"rust\"><code class=\"language-rust\"><span class=\"lang-rust\">fn main<'a>();\n</span></code></pre>\n");
+    /// ```
+    pub codefence_syntax_highlighter: Option<&'p dyn SyntaxHighlighterAdapter>,
+
+    /// Provide a node renderer to override the HTML rendering of particular nodes.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+    /// # use comrak::adapters::NodeRendererAdapter;
+    /// # use comrak::html::escape_href;
+    /// # use comrak::nodes::{AstNode, NodeValue};
+    /// # use std::io::{self, Write};
+    /// pub struct LazyImages;
+    /// impl NodeRendererAdapter for LazyImages {
+    ///     fn enter<'a>(&self, output: &mut dyn Write, node: &'a AstNode<'a>, _options: &ComrakOptions) -> io::Result<bool> {
+    ///         match node.data.borrow().value {
+    ///             NodeValue::Image(ref nl) => {
+    ///                 output.write_all(b"<img loading=\"lazy\" src=\"")?;
+    ///                 escape_href(output, &nl.url)?;
+    ///                 output.write_all(b"\" alt=\"")?;
+    ///                 Ok(false)
+    ///             }
+    ///             _ => Ok(true),
+    ///         }
+    ///     }
+    ///
+    ///     fn exit<'a>(&self, output: &mut dyn Write, node: &'a AstNode<'a>, _options: &ComrakOptions) -> io::Result<bool> {
+    ///         match node.data.borrow().value {
+    ///             NodeValue::Image(..) => {
+    ///                 output.write_all(b"\" />")?;
+    ///                 Ok(false)
+    ///             }
+    ///             _ => Ok(true),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let renderer = LazyImages;
+    /// let mut plugins = ComrakPlugins::default();
+    /// plugins.render.node_renderer = Some(&renderer);
+    ///
+    /// assert_eq!(markdown_to_html_with_plugins("Look: ![a *cat*](cat.png)\n", &ComrakOptions::default(), &plugins),
+    ///            "<p>Look: <img loading=\"lazy\" src=\"cat.png\" alt=\"a cat\" /></p>\n");
+    /// ```
+    pub node_renderer: Option<&'p dyn NodeRendererAdapter>,
+
+    /// Provide a resolver which maps the page names of wikilinks to the URLs they link to.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.wikilinks = true;
+    ///
+    /// let resolver = |page: &[u8]| {
+    ///     let mut url = b"/wiki/".to_vec();
+    ///     url.extend(page.iter().map(|&c| if c == b' ' { b'_' } else { c }));
+    ///     url
+    /// };
+    /// let mut plugins = ComrakPlugins::default();
+    /// plugins.render.wikilink_resolver = Some(&resolver);
+    ///
+    /// assert_eq!(markdown_to_html_with_plugins("[[Main page]]\n", &options, &plugins),
+    ///            "<p><a href=\"/wiki/Main_page\" data-wikilink=\"true\">Main page</a></p>\n");
+    /// ```
+    pub wikilink_resolver: Option<&'p dyn WikiLinkResolverAdapter>,
+
+    /// Provide a rewriter for the URLs of links and images, such as
+    /// `plugins::base_url::BaseUrlResolver`.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+    /// use comrak::adapters::UrlKind;
+    ///
+    /// let rewriter = |url: &[u8], kind: UrlKind| match kind {
+    ///     UrlKind::Image => [b"/static/", url].concat(),
+    ///     _ => url.to_vec(),
+    /// };
+    /// let mut plugins = ComrakPlugins::default();
+    /// plugins.render.url_rewriter = Some(&rewriter);
+    ///
+    /// assert_eq!(markdown_to_html_with_plugins("[a](a.md) ![b](b.png)\n", &ComrakOptions::default(), &plugins),
+    ///            "<p><a href=\"a.md\">a</a> <img src=\"/static/b.png\" alt=\"b\" /></p>\n");
+    /// ```
+    pub url_rewriter: Option<&'p dyn UrlRewriterAdapter>,
+
+    /// Provide a renderer for custom containers, enabled with the `custom_containers` option.
+    ///
+    /// ```
+    /// # use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+    /// # use comrak::adapters::ContainerRendererAdapter;
+    /// # use comrak::html::escape;
+    /// # use comrak::nodes::NodeContainer;
+    /// # use std::io::{self, Write};
+    /// pub struct Details;
+    /// impl ContainerRendererAdapter for Details {
+    ///     fn enter(&self, output: &mut dyn Write, container: &NodeContainer) -> io::Result<bool> {
+    ///         if container.name != b"details" {
+    ///             return Ok(true);
+    ///         }
+    ///         output.write_all(b"<details>\n<summary>")?;
+    ///         escape(output, &container.info)?;
+    ///         output.write_all(b"</summary>\n")?;
+    ///         Ok(false)
+    ///     }
+    ///
+    ///     fn exit(&self, output: &mut dyn Write, container: &NodeContainer) -> io::Result<bool> {
+    ///         if container.name != b"details" {
+    ///             return Ok(true);
+    ///         }
+    ///         output.write_all(b"</details>\n")?;
+    ///         Ok(false)
+    ///     }
+    /// }
+    ///
+    /// let mut options = ComrakOptions::default();
+    /// options.extension.custom_containers = true;
+    /// let renderer = Details;
+    /// let mut plugins = ComrakPlugins::default();
+    /// plugins.render.container_renderer = Some(&renderer);
+    ///
+    /// assert_eq!(markdown_to_html_with_plugins("::: details More <info>\nHidden.\n:::\n\n::: note\nShown.\n:::\n", &options, &plugins),
+    ///            "<details>\n<summary>More &lt;info&gt;</summary>\n<p>Hidden.</p>\n</details>\n\
+    ///             <div class=\"note\">\n<p>Shown.</p>\n</div>\n");
+    /// ```
+    pub container_renderer: Option<&'p dyn ContainerRendererAdapter>,
+}
+
+impl<'p> fmt::Debug for ComrakRenderPlugins<'p> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ComrakRenderPlugins")
+            .field(
+                "codefence_syntax_highlighter",
+                &self
+                    .codefence_syntax_highlighter
+                    .map(|_| "impl SyntaxHighlighterAdapter"),
+            )
+            .field(
+                "node_renderer",
+                &self.node_renderer.map(|_| "impl NodeRendererAdapter"),
+            )
+            .field(
+                "wikilink_resolver",
+                &self
+                    .wikilink_resolver
+                    .map(|_| "impl WikiLinkResolverAdapter"),
+            )
+            .field(
+                "url_rewriter",
+                &self.url_rewriter.map(|_| "impl UrlRewriterAdapter"),
+            )
+            .field(
+                "container_renderer",
+                &self
+                    .container_renderer
+                    .map(|_| "impl ContainerRendererAdapter"),
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Reference {
     pub url: Vec<u8>,
     pub title: Vec<u8>,
@@ -446,6 +1148,7 @@ pub struct Reference {
 struct FootnoteDefinition<'a> {
     ix: Option<u32>,
     node: &'a AstNode<'a>,
+    total_references: u32,
 }
 
 impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
@@ -461,6 +1164,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             root,
             current: root,
             line_number: 0,
+            curline_len: 0,
             offset: 0,
             column: 0,
             first_nonspace: 0,
@@ -475,9 +1179,17 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
     }
 
     fn feed(&mut self, s: &str) {
-        let mut i = 0;
         let s = s.as_bytes();
+        let mut i = self.feed_front_matter(s);
+        while i < s.len() {
+            i = self.feed_line(s, i);
+        }
+    }
 
+    // Adds the front matter at the start of the input, if enabled and present, returning its
+    // length.
+    fn feed_front_matter(&mut self, s: &[u8]) -> usize {
+        let mut i = 0;
         if let Some(ref delimiter) = self.options.extension.front_matter_delimiter {
             let front_matter_pattern = RegexBuilder::new(&format!(
                 "\\A(?:\u{feff})?{delim}\\r?\\n.*^{delim}\\r?\\n(?:\\r?\\n)?",
@@ -489,11 +1201,21 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             .unwrap();
             if let Some(front_matter_size) = front_matter_pattern.shortest_match(s) {
                 i += front_matter_size;
-                let node = self.add_child(self.root, NodeValue::FrontMatter(s[..i].to_vec()));
+                let front_matter = &s[..i];
+                let lines = front_matter.split(|&c| c == b'\n').collect::<Vec<_>>();
+                let end_column = strings::line_length(lines[lines.len() - 2]);
+                let node =
+                    self.add_child(self.root, NodeValue::FrontMatter(front_matter.to_vec()), 1);
                 self.finalize(node).unwrap();
+                node.data.borrow_mut().sourcepos.end = (lines.len() - 1, end_column).into();
+                self.line_number = lines.len() - 1;
             }
         }
+        i
+    }
 
+    // Processes the line starting at offset `i`, returning the offset of the next one.
+    fn feed_line(&mut self, s: &[u8], mut i: usize) -> usize {
         let sz = s.len();
         let mut linebuf = vec![];
 
@@ -529,6 +1251,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 if i < sz && s[i] == b'\n' {
                     i += 1;
                 }
+                break;
             } else {
                 debug_assert!(eol < sz && s[eol] == b'\0');
                 linebuf.extend_from_slice(&s[i..eol]);
@@ -536,6 +1259,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 i = eol + 1;
             }
         }
+        i
     }
 
     fn find_first_nonspace(&mut self, line: &[u8]) {
@@ -584,6 +1308,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         self.column = 0;
         self.blank = false;
         self.partially_consumed_tab = false;
+        self.curline_len = line.len();
 
         if self.line_number == 0
             && line.len() >= 3
@@ -605,13 +1330,8 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             }
         }
 
-        self.last_line_length = line.len();
-        if self.last_line_length > 0 && line[self.last_line_length - 1] == b'\n' {
-            self.last_line_length -= 1;
-        }
-        if self.last_line_length > 0 && line[self.last_line_length - 1] == b'\r' {
-            self.last_line_length -= 1;
-        }
+        self.last_line_length = strings::line_length(line);
+        self.curline_len = 0;
     }
 
     fn check_open_blocks(
@@ -684,7 +1404,10 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     }
                     continue;
                 }
-                NodeValue::Heading(..) | NodeValue::TableRow(..) | NodeValue::TableCell => {
+                NodeValue::Heading(..)
+                | NodeValue::ThematicBreak
+                | NodeValue::TableRow(..)
+                | NodeValue::TableCell => {
                     return (false, container, should_continue);
                 }
                 NodeValue::FootnoteDefinition(..) => {
@@ -692,6 +1415,16 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                         return (false, container, should_continue);
                     }
                 }
+                NodeValue::Container(ref nc)
+                    if self.closes_container(line, container, nc.fence_length) =>
+                {
+                    while !self.current.same_node(container) {
+                        self.current = self.finalize(self.current).unwrap();
+                    }
+                    self.current = self.finalize_borrowed(container, ast).unwrap();
+                    ast.sourcepos.end = (self.line_number, strings::line_length(line)).into();
+                    return (false, container, false);
+                }
                 _ => {}
             }
         }
@@ -718,7 +1451,8 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 if strings::is_space_or_tab(line[self.offset]) {
                     self.advance_offset(line, 1, true);
                 }
-                *container = self.add_child(*container, NodeValue::BlockQuote);
+                *container =
+                    self.add_child(*container, NodeValue::BlockQuote, self.first_nonspace + 1);
             } else if !indented
                 && unwrap_into(
                     scanners::atx_heading_start(&line[self.first_nonspace..]),
@@ -728,7 +1462,11 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 let heading_startpos = self.first_nonspace;
                 let offset = self.offset;
                 self.advance_offset(line, heading_startpos + matched - offset, false);
-                *container = self.add_child(*container, NodeValue::Heading(NodeHeading::default()));
+                *container = self.add_child(
+                    *container,
+                    NodeValue::Heading(NodeHeading::default()),
+                    heading_startpos + 1,
+                );
 
                 let mut hashpos = line[self.first_nonspace..]
                     .iter()
@@ -761,8 +1499,28 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     info: Vec::with_capacity(10),
                     literal: Vec::new(),
                 };
-                *container = self.add_child(*container, NodeValue::CodeBlock(ncb));
+                *container =
+                    self.add_child(*container, NodeValue::CodeBlock(ncb), first_nonspace + 1);
                 self.advance_offset(line, first_nonspace + matched - offset, false);
+            } else if !indented
+                && self.options.extension.custom_containers
+                && unwrap_into(
+                    scanners::open_container_fence(&line[self.first_nonspace..]),
+                    &mut matched,
+                )
+            {
+                let first_nonspace = self.first_nonspace;
+                let rest = strings::trim_slice(&line[first_nonspace + matched..]);
+                let name_len = rest.iter().position(|&c| isspace(c)).unwrap_or(rest.len());
+                let nc = NodeContainer {
+                    name: rest[..name_len].to_vec(),
+                    info: strings::trim_slice(&rest[name_len..]).to_vec(),
+                    fence_length: matched,
+                };
+                *container =
+                    self.add_child(container, NodeValue::Container(nc), first_nonspace + 1);
+                let adv = line.len() - 1 - self.offset;
+                self.advance_offset(line, adv, false);
             } else if !indented
                 && (unwrap_into(
                     scanners::html_block_start(&line[self.first_nonspace..]),
@@ -780,7 +1538,11 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     literal: Vec::new(),
                 };
 
-                *container = self.add_child(*container, NodeValue::HtmlBlock(nhb));
+                *container = self.add_child(
+                    *container,
+                    NodeValue::HtmlBlock(nhb),
+                    self.first_nonspace + 1,
+                );
             } else if !indented
                 && match container.data.borrow().value {
                     NodeValue::Paragraph => unwrap_into(
@@ -792,7 +1554,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             {
                 let has_content = {
                     let mut ast = container.data.borrow_mut();
-                    self.resolve_reference_link_definitions(&mut ast.content)
+                    self.resolve_reference_link_definitions(&mut ast)
                 };
                 if has_content {
                     container.data.borrow_mut().value = NodeValue::Heading(NodeHeading {
@@ -814,7 +1576,11 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     ),
                 }
             {
-                *container = self.add_child(*container, NodeValue::ThematicBreak);
+                *container = self.add_child(
+                    *container,
+                    NodeValue::ThematicBreak,
+                    self.first_nonspace + 1,
+                );
                 let adv = line.len() - 1 - self.offset;
                 self.advance_offset(line, adv, false);
             } else if !indented
@@ -827,8 +1593,17 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 let mut c = &line[self.first_nonspace + 2..self.first_nonspace + matched];
                 c = c.split(|&e| e == b']').next().unwrap();
                 let offset = self.first_nonspace + matched - self.offset;
+                let start_column = self.first_nonspace + 1;
                 self.advance_offset(line, offset, false);
-                *container = self.add_child(*container, NodeValue::FootnoteDefinition(c.to_vec()));
+                *container = self.add_child(
+                    *container,
+                    NodeValue::FootnoteDefinition(NodeFootnoteDefinition {
+                        name: c.to_vec(),
+                        ix: 0,
+                        total_references: 0,
+                    }),
+                    start_column,
+                );
             } else if !indented
                 && self.options.extension.description_lists
                 && line[self.first_nonspace] == b':'
@@ -852,6 +1627,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 )
             {
                 let offset = self.first_nonspace + matched - self.offset;
+                let start_column = self.first_nonspace + 1;
                 self.advance_offset(line, offset, false);
                 let (save_partially_consumed_tab, save_offset, save_column) =
                     (self.partially_consumed_tab, self.offset, self.column);
@@ -880,11 +1656,12 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     NodeValue::List(ref mnl) => !lists_match(&nl, mnl),
                     _ => true,
                 } {
-                    *container = self.add_child(*container, NodeValue::List(nl));
+                    *container = self.add_child(*container, NodeValue::List(nl), start_column);
                 }
 
-                *container = self.add_child(*container, NodeValue::Item(nl));
+                *container = self.add_child(*container, NodeValue::Item(nl), start_column);
             } else if indented && !maybe_lazy && !self.blank {
+                let start_column = self.offset + 1;
                 self.advance_offset(line, CODE_INDENT, true);
                 let ncb = NodeCodeBlock {
                     fenced: false,
@@ -894,7 +1671,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     info: vec![],
                     literal: Vec::new(),
                 };
-                *container = self.add_child(*container, NodeValue::CodeBlock(ncb));
+                *container = self.add_child(*container, NodeValue::CodeBlock(ncb), start_column);
             } else {
                 let new_container = if !indented && self.options.extension.table {
                     table::try_opening_block(self, *container, line)
@@ -1051,6 +1828,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             *should_continue = false;
             self.advance_offset(line, matched, false);
             self.current = self.finalize_borrowed(container, ast).unwrap();
+            ast.sourcepos.end = (self.line_number, strings::line_length(line)).into();
             return false;
         }
 
@@ -1062,6 +1840,36 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         true
     }
 
+    // Whether the line is a closing fence for the container, rather than content of a fenced
+    // code block or HTML block within it, or the closing fence of a nested container.
+    fn closes_container(
+        &self,
+        line: &[u8],
+        container: &'a AstNode<'a>,
+        fence_length: usize,
+    ) -> bool {
+        if self.indent > 3 {
+            return false;
+        }
+
+        let matched = match scanners::close_container_fence(&line[self.first_nonspace..]) {
+            Some(matched) if matched >= fence_length => matched,
+            _ => return false,
+        };
+
+        let mut node = container;
+        while nodes::last_child_is_open(node) {
+            node = node.last_child().unwrap();
+            match node.data.borrow().value {
+                NodeValue::Container(ref nc) if nc.fence_length <= matched => return false,
+                NodeValue::CodeBlock(ref ncb) if ncb.fenced => return false,
+                NodeValue::HtmlBlock(..) => return false,
+                _ => (),
+            }
+        }
+        true
+    }
+
     fn parse_html_block_prefix(&mut self, t: u8) -> bool {
         match t {
             1 | 2 | 3 | 4 | 5 => true,
@@ -1085,13 +1893,18 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             // is added to it. If not, create a new list.
 
             last_child.detach();
+            let term_start = last_child.data.borrow().sourcepos.start;
 
             let list = match container.last_child() {
                 Some(lc) if node_matches!(lc, NodeValue::DescriptionList) => {
                     reopen_ast_nodes(lc);
                     lc
                 }
-                _ => self.add_child(container, NodeValue::DescriptionList),
+                _ => {
+                    let list = self.add_child(container, NodeValue::DescriptionList, 0);
+                    list.data.borrow_mut().sourcepos.start = term_start;
+                    list
+                }
             };
 
             let metadata = NodeDescriptionItem {
@@ -1099,9 +1912,12 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                 padding: 2,
             };
 
-            let item = self.add_child(list, NodeValue::DescriptionItem(metadata));
-            let term = self.add_child(item, NodeValue::DescriptionTerm);
-            let details = self.add_child(item, NodeValue::DescriptionDetails);
+            let item = self.add_child(list, NodeValue::DescriptionItem(metadata), 0);
+            item.data.borrow_mut().sourcepos.start = term_start;
+            let term = self.add_child(item, NodeValue::DescriptionTerm, 0);
+            term.data.borrow_mut().sourcepos = last_child.data.borrow().sourcepos;
+            let details =
+                self.add_child(item, NodeValue::DescriptionDetails, self.first_nonspace + 1);
 
             term.append(last_child);
 
@@ -1113,13 +1929,18 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
     }
 
-    fn add_child(&mut self, mut parent: &'a AstNode<'a>, value: NodeValue) -> &'a AstNode<'a> {
+    fn add_child(
+        &mut self,
+        mut parent: &'a AstNode<'a>,
+        value: NodeValue,
+        start_column: usize,
+    ) -> &'a AstNode<'a> {
         while !nodes::can_contain_type(parent, &value) {
             parent = self.finalize(parent).unwrap();
         }
 
         let mut child = Ast::new(value);
-        child.start_line = self.line_number;
+        child.sourcepos.start = (self.line_number, start_column).into();
         let node = self.arena.alloc(Node::new(RefCell::new(child)));
         parent.append(node);
         node
@@ -1141,11 +1962,14 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
 
         container.data.borrow_mut().last_line_blank = self.blank
             && match container.data.borrow().value {
-                NodeValue::BlockQuote | NodeValue::Heading(..) | NodeValue::ThematicBreak => false,
+                NodeValue::BlockQuote
+                | NodeValue::Container(..)
+                | NodeValue::Heading(..)
+                | NodeValue::ThematicBreak => false,
                 NodeValue::CodeBlock(ref ncb) => !ncb.fenced,
                 NodeValue::Item(..) => {
                     container.first_child().is_some()
-                        || container.data.borrow().start_line != self.line_number
+                        || container.data.borrow().sourcepos.start.line != self.line_number
                 }
                 _ => true,
             };
@@ -1190,7 +2014,10 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                     };
 
                     if matches_end_condition {
+                        let html_block = container;
                         container = self.finalize(container).unwrap();
+                        html_block.data.borrow_mut().sourcepos.end =
+                            (self.line_number, strings::line_length(line)).into();
                     }
                 }
                 _ => {
@@ -1224,7 +2051,11 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
                             self.add_line(container, &line);
                         }
                     } else {
-                        container = self.add_child(container, NodeValue::Paragraph);
+                        container = self.add_child(
+                            container,
+                            NodeValue::Paragraph,
+                            self.first_nonspace + 1,
+                        );
                         let count = self.first_nonspace - self.offset;
                         self.advance_offset(line, count, false);
                         self.add_line(container, line);
@@ -1239,6 +2070,7 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
     fn add_line(&mut self, node: &'a AstNode<'a>, line: &[u8]) {
         let mut ast = node.data.borrow_mut();
         assert!(ast.open);
+        ast.line_offsets.push(self.offset);
         if self.partially_consumed_tab {
             self.offset += 1;
             let chars_to_tab = TAB_STOP - (self.column % TAB_STOP);
@@ -1251,6 +2083,19 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
     }
 
+    /// The link reference definitions found so far.
+    pub(crate) fn references(&self) -> &HashMap<Vec<u8>, Reference> {
+        &self.refmap
+    }
+
+    /// Parse the inlines of blocks from `parse_blocks_from`, resolving links with `refmap`.
+    pub(crate) fn finish_inlines(mut self, refmap: HashMap<Vec<u8>, Reference>) -> &'a AstNode<'a> {
+        self.refmap = refmap;
+        self.process_inlines();
+        self.postprocess_text_nodes(self.root);
+        self.root
+    }
+
     fn finish(&mut self) -> &'a AstNode<'a> {
         self.finalize_document();
         self.postprocess_text_nodes(self.root);
@@ -1258,26 +2103,31 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
     }
 
     fn finalize_document(&mut self) {
-        while !self.current.same_node(self.root) {
-            self.current = self.finalize(self.current).unwrap();
-        }
-
-        self.finalize(self.root);
+        self.close_blocks();
+        trim_list_sourcepos(self.root);
         self.process_inlines();
         if self.options.extension.footnotes {
             self.process_footnotes();
         }
     }
 
+    // Closes every open block, and then the document itself.
+    fn close_blocks(&mut self) {
+        while !self.current.same_node(self.root) {
+            self.current = self.finalize(self.current).unwrap();
+        }
+        self.finalize(self.root);
+    }
+
     fn finalize(&mut self, node: &'a AstNode<'a>) -> Option<&'a AstNode<'a>> {
         self.finalize_borrowed(node, &mut *node.data.borrow_mut())
     }
 
-    fn resolve_reference_link_definitions(&mut self, content: &mut Vec<u8>) -> bool {
+    fn resolve_reference_link_definitions(&mut self, ast: &mut Ast) -> bool {
         let mut seeked = 0;
         {
             let mut pos = 0;
-            let mut seek: &[u8] = &*content;
+            let mut seek: &[u8] = &ast.content;
             while !seek.is_empty()
                 && seek[0] == b'['
                 && unwrap_into(self.parse_reference_inline(seek), &mut pos)
@@ -1288,10 +2138,76 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
 
         if seeked != 0 {
-            *content = content[seeked..].to_vec();
+            // Reference definitions always run to the end of a line, so whole lines are dropped
+            // from the start of the content; move the start of the node past them.
+            let lines = ast.content[..seeked]
+                .iter()
+                .filter(|&&c| c == b'\n')
+                .count();
+            ast.content = ast.content[seeked..].to_vec();
+            ast.line_offsets.drain(..min(lines, ast.line_offsets.len()));
+            let indent = ast
+                .content
+                .iter()
+                .take_while(|&&c| strings::is_space_or_tab(c))
+                .count();
+            ast.sourcepos.start = (
+                ast.sourcepos.start.line + lines,
+                ast.line_offsets.first().map_or(0, |&o| o) + indent + 1,
+            )
+                .into();
         }
 
-        !strings::is_blank(content)
+        !strings::is_blank(&ast.content)
+    }
+
+    fn resolve_alert(&mut self, node: &'a AstNode<'a>) -> Option<NodeAlert> {
+        let paragraph = node.first_child()?;
+        let mut ast = paragraph.data.borrow_mut();
+        if !matches!(ast.value, NodeValue::Paragraph) || !ast.content.starts_with(b"[!") {
+            return None;
+        }
+
+        let close = ast.content.iter().position(|&c| c == b']')?;
+        let kind = AlertType::from_name(&ast.content[2..close])?;
+        let eol = ast.content[close..]
+            .iter()
+            .position(|&c| strings::is_line_end_char(c))
+            .map_or(ast.content.len(), |p| close + p);
+        let mut title = ast.content[close + 1..eol].to_vec();
+        strings::trim(&mut title);
+
+        // The alert type's line is dropped from the paragraph; whatever's left is the alert's
+        // first paragraph.
+        let mut next = eol;
+        if ast.content.get(next) == Some(&b'\r') {
+            next += 1;
+        }
+        if ast.content.get(next) == Some(&b'\n') {
+            next += 1;
+        }
+        ast.content = ast.content[next..].to_vec();
+        if ast.content.iter().all(|&c| isspace(c)) {
+            drop(ast);
+            paragraph.detach();
+        } else {
+            ast.line_offsets.remove(0);
+            let indent = ast
+                .content
+                .iter()
+                .take_while(|&&c| strings::is_space_or_tab(c))
+                .count();
+            ast.sourcepos.start = (
+                ast.sourcepos.start.line + 1,
+                ast.line_offsets.first().map_or(0, |&o| o) + indent + 1,
+            )
+                .into();
+        }
+
+        Some(NodeAlert {
+            kind,
+            title: if title.is_empty() { None } else { Some(title) },
+        })
     }
 
     fn finalize_borrowed(
@@ -1302,20 +2218,49 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         assert!(ast.open);
         ast.open = false;
 
-        let content = &mut ast.content;
+        if matches!(ast.value, NodeValue::TableRow(..) | NodeValue::TableCell) {
+            // Table rows and cells have their extents set when the row is split up.
+        } else if self.curline_len == 0 {
+            // End of input; the line number has not been incremented.
+            ast.sourcepos.end = (self.line_number, self.last_line_length).into();
+        } else {
+            ast.sourcepos.end = (self.line_number - 1, self.last_line_length).into();
+        }
+
         let parent = node.parent();
 
-        match ast.value {
-            NodeValue::Paragraph => {
-                let has_content = self.resolve_reference_link_definitions(content);
-                if !has_content {
-                    node.detach();
+        if let NodeValue::Paragraph = ast.value {
+            let has_content = self.resolve_reference_link_definitions(ast);
+            if !has_content {
+                node.detach();
+            } else if self.options.extension.table_of_contents {
+                let content = strings::trim_slice(&ast.content);
+                if content == b"[[_TOC_]]" || content == b"[TOC]" {
+                    ast.value = NodeValue::TableOfContents;
                 }
             }
+        }
+
+        if self.options.extension.alerts && matches!(ast.value, NodeValue::BlockQuote) {
+            if let Some(alert) = self.resolve_alert(node) {
+                ast.value = NodeValue::Alert(alert);
+            }
+        }
+
+        let content = &mut ast.content;
+        match ast.value {
             NodeValue::CodeBlock(ref mut ncb) => {
                 if !ncb.fenced {
                     strings::remove_trailing_blank_lines(content);
                     content.push(b'\n');
+
+                    let lines = content.split(|&c| c == b'\n').collect::<Vec<_>>();
+                    let last = lines.len() - 2;
+                    ast.sourcepos.end = (
+                        ast.sourcepos.start.line + last,
+                        ast.line_offsets.get(last).map_or(0, |&o| o) + lines[last].len(),
+                    )
+                        .into();
                 } else {
                     let mut pos = 0;
                     while pos < content.len() {
@@ -1328,6 +2273,13 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
 
                     let mut tmp = entity::unescape_html(&content[..pos]);
                     strings::trim(&mut tmp);
+                    if self.options.extension.attributes {
+                        if let Some((attributes, start)) = attributes::parse_trailing(&tmp) {
+                            ast.attributes = attributes;
+                            tmp.truncate(start);
+                            strings::rtrim(&mut tmp);
+                        }
+                    }
                     strings::unescape(&mut tmp);
                     if tmp.is_empty() {
                         ncb.info = self
@@ -1385,6 +2337,24 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             _ => (),
         }
 
+        if self.options.extension.math {
+            let math = match ast.value {
+                NodeValue::CodeBlock(ref mut ncb)
+                    if ncb.fenced && ncb.info.split(|&c| isspace(c)).next() == Some(b"math") =>
+                {
+                    Some(mem::take(&mut ncb.literal))
+                }
+                _ => None,
+            };
+            if let Some(literal) = math {
+                ast.value = NodeValue::Math(NodeMath {
+                    display: true,
+                    fenced: true,
+                    literal,
+                });
+            }
+        }
+
         parent
     }
 
@@ -1395,6 +2365,9 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
     fn process_inlines_node(&mut self, node: &'a AstNode<'a>) {
         for node in node.descendants() {
             if node.data.borrow().value.contains_inlines() {
+                if self.options.extension.attributes {
+                    take_heading_attributes(node);
+                }
                 self.parse_inlines(node);
             }
         }
@@ -1408,6 +2381,8 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             self.arena,
             self.options,
             content,
+            node_data.sourcepos.start.line,
+            &node_data.line_offsets,
             &mut self.refmap,
             &delimiter_arena,
             self.callback.as_mut(),
@@ -1424,8 +2399,11 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         let mut map = HashMap::new();
         Self::find_footnote_definitions(self.root, &mut map);
 
+        let mut labels = HashSet::new();
+        Self::find_footnote_labels(self.root, &mut labels);
+
         let mut ix = 0;
-        Self::find_footnote_references(self.root, &mut map, &mut ix);
+        self.find_footnote_references(self.root, &mut map, &labels, &mut ix);
 
         if ix > 0 {
             let mut v = map.into_iter().map(|(_, v)| v).collect::<Vec<_>>();
@@ -1433,8 +2411,9 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             for f in v {
                 if f.ix.is_some() {
                     match f.node.data.borrow_mut().value {
-                        NodeValue::FootnoteDefinition(ref mut name) => {
-                            *name = format!("{}", f.ix.unwrap()).into_bytes();
+                        NodeValue::FootnoteDefinition(ref mut nfd) => {
+                            nfd.ix = f.ix.unwrap();
+                            nfd.total_references = f.total_references;
                         }
                         _ => unreachable!(),
                     }
@@ -1449,11 +2428,15 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         map: &mut HashMap<Vec<u8>, FootnoteDefinition<'a>>,
     ) {
         match node.data.borrow().value {
-            NodeValue::FootnoteDefinition(ref name) => {
+            NodeValue::FootnoteDefinition(ref nfd) => {
                 node.detach();
                 map.insert(
-                    strings::normalize_label(name),
-                    FootnoteDefinition { ix: None, node },
+                    strings::normalize_label(&nfd.name),
+                    FootnoteDefinition {
+                        ix: None,
+                        node,
+                        total_references: 0,
+                    },
                 );
             }
             _ => {
@@ -1464,28 +2447,58 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
     }
 
+    // Collects the labels of all references in the document, including undefined ones, so that
+    // inline footnotes aren't given a name that one of them already uses.
+    fn find_footnote_labels(node: &'a AstNode<'a>, labels: &mut HashSet<Vec<u8>>) {
+        if let NodeValue::FootnoteReference(ref nfr) = node.data.borrow().value {
+            if !nfr.name.is_empty() {
+                labels.insert(strings::normalize_label(&nfr.name));
+            }
+        }
+        for n in node.children() {
+            Self::find_footnote_labels(n, labels);
+        }
+    }
+
     fn find_footnote_references(
+        &self,
         node: &'a AstNode<'a>,
-        map: &mut HashMap<Vec<u8>, FootnoteDefinition>,
+        map: &mut HashMap<Vec<u8>, FootnoteDefinition<'a>>,
+        labels: &HashSet<Vec<u8>>,
         ix: &mut u32,
     ) {
         let mut ast = node.data.borrow_mut();
+        let sourcepos = ast.sourcepos;
         let mut replace = None;
         match ast.value {
-            NodeValue::FootnoteReference(ref mut name) => {
-                if let Some(ref mut footnote) = map.get_mut(name) {
+            NodeValue::FootnoteReference(ref mut nfr) => {
+                let mut inline_definition = None;
+                if nfr.name.is_empty() {
+                    let (name, definition) = self.add_inline_footnote(node, sourcepos, map, labels);
+                    nfr.name = name;
+                    inline_definition = Some(definition);
+                }
+
+                if let Some(ref mut footnote) = map.get_mut(&strings::normalize_label(&nfr.name)) {
                     if footnote.ix.is_none() {
                         *ix += 1;
                         footnote.ix = Some(*ix);
                     }
-                    *name = format!("{}", footnote.ix.unwrap()).into_bytes();
+                    footnote.total_references += 1;
+                    nfr.ix = footnote.ix.unwrap();
+                    nfr.ref_num = footnote.total_references;
                 } else {
-                    replace = Some(name.clone());
+                    replace = Some(nfr.name.clone());
+                }
+
+                // References within an inline footnote are numbered after it.
+                if let Some(definition) = inline_definition {
+                    self.find_footnote_references(definition, map, labels, ix);
                 }
             }
             _ => {
                 for n in node.children() {
-                    Self::find_footnote_references(n, map, ix);
+                    self.find_footnote_references(n, map, labels, ix);
                 }
             }
         }
@@ -1499,6 +2512,57 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
     }
 
+    // Moves the content of an inline footnote's reference into a new definition, named so as not
+    // to clash with any other footnote definition or reference.
+    fn add_inline_footnote(
+        &self,
+        reference: &'a AstNode<'a>,
+        sourcepos: Sourcepos,
+        map: &mut HashMap<Vec<u8>, FootnoteDefinition<'a>>,
+        labels: &HashSet<Vec<u8>>,
+    ) -> (Vec<u8>, &'a AstNode<'a>) {
+        let mut n = 1;
+        let name = loop {
+            let name = format!("inline-{}", n).into_bytes();
+            let label = strings::normalize_label(&name);
+            if !map.contains_key(&label) && !labels.contains(&label) {
+                break name;
+            }
+            n += 1;
+        };
+
+        let mut definition = Ast::new(NodeValue::FootnoteDefinition(NodeFootnoteDefinition {
+            name: name.clone(),
+            ix: 0,
+            total_references: 0,
+        }));
+        definition.sourcepos = sourcepos;
+        definition.open = false;
+        let definition = self.arena.alloc(Node::new(RefCell::new(definition)));
+
+        let mut paragraph = Ast::new(NodeValue::Paragraph);
+        paragraph.sourcepos = sourcepos;
+        paragraph.open = false;
+        let paragraph = self.arena.alloc(Node::new(RefCell::new(paragraph)));
+        definition.append(paragraph);
+
+        let mut child = reference.first_child();
+        while let Some(ch) = child {
+            child = ch.next_sibling();
+            paragraph.append(ch);
+        }
+
+        map.insert(
+            strings::normalize_label(&name),
+            FootnoteDefinition {
+                ix: None,
+                node: definition,
+                total_references: 0,
+            },
+        );
+        (name, definition)
+    }
+
     fn postprocess_text_nodes(&mut self, node: &'a AstNode<'a>) {
         let mut stack = vec![node];
         let mut children = vec![];
@@ -1509,31 +2573,34 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             while let Some(n) = nch {
                 let mut this_bracket = false;
                 loop {
-                    match n.data.borrow_mut().value {
+                    let ast = &mut *n.data.borrow_mut();
+                    match ast.value {
                         // Join adjacent text nodes together
                         NodeValue::Text(ref mut root) => {
                             let ns = match n.next_sibling() {
                                 Some(ns) => ns,
                                 _ => {
                                     // Post-process once we are finished joining text nodes
-                                    self.postprocess_text_node(n, root);
+                                    self.postprocess_text_node(n, root, &mut ast.sourcepos);
                                     break;
                                 }
                             };
 
-                            match ns.data.borrow().value {
+                            let ns_ast = ns.data.borrow();
+                            match ns_ast.value {
                                 NodeValue::Text(ref adj) => {
                                     root.extend_from_slice(adj);
+                                    ast.sourcepos.end = ns_ast.sourcepos.end;
                                     ns.detach();
                                 }
                                 _ => {
                                     // Post-process once we are finished joining text nodes
-                                    self.postprocess_text_node(n, root);
+                                    self.postprocess_text_node(n, root, &mut ast.sourcepos);
                                     break;
                                 }
                             }
                         }
-                        NodeValue::Link(..) | NodeValue::Image(..) => {
+                        NodeValue::Link(..) | NodeValue::Image(..) | NodeValue::WikiLink(..) => {
                             this_bracket = true;
                             break;
                         }
@@ -1554,17 +2621,27 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
     }
 
-    fn postprocess_text_node(&mut self, node: &'a AstNode<'a>, text: &mut Vec<u8>) {
+    fn postprocess_text_node(
+        &mut self,
+        node: &'a AstNode<'a>,
+        text: &mut Vec<u8>,
+        sourcepos: &mut Sourcepos,
+    ) {
         if self.options.extension.tasklist {
-            self.process_tasklist(node, text);
+            self.process_tasklist(node, text, sourcepos);
         }
 
         if self.options.extension.autolink {
-            autolink::process_autolinks(self.arena, node, text);
+            autolink::process_autolinks(self.arena, node, text, sourcepos);
         }
     }
 
-    fn process_tasklist(&mut self, node: &'a AstNode<'a>, text: &mut Vec<u8>) {
+    fn process_tasklist(
+        &mut self,
+        node: &'a AstNode<'a>,
+        text: &mut Vec<u8>,
+        sourcepos: &mut Sourcepos,
+    ) {
         lazy_static! {
             static ref TASKLIST: Regex = Regex::new(r"\A(\s*\[([xX ])\])(?:\z|\s)").unwrap();
         }
@@ -1593,7 +2670,18 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
         }
 
         *text = text[end..].to_vec();
-        let checkbox = inlines::make_inline(self.arena, NodeValue::TaskItem(active));
+        let checkbox = inlines::make_inline(
+            self.arena,
+            NodeValue::TaskItem(active),
+            (
+                sourcepos.start.line,
+                sourcepos.start.column,
+                sourcepos.start.line,
+                sourcepos.start.column + end - 1,
+            )
+                .into(),
+        );
+        sourcepos.start.column += end;
         node.insert_before(checkbox);
     }
 
@@ -1605,6 +2693,8 @@ impl<'a, 'o, 'c> Parser<'a, 'o, 'c> {
             self.arena,
             self.options,
             content,
+            0,
+            &[],
             &mut self.refmap,
             &delimiter_arena,
             self.callback.as_mut(),
@@ -3,8 +3,8 @@ use nodes::{Ast, AstNode, NodeValue, TableAlignment};
 use parser::Parser;
 use scanners;
 use std::cell::RefCell;
-use std::cmp::min;
-use strings::trim;
+use std::cmp::{max, min};
+use strings;
 
 pub fn try_opening_block<'a, 'o, 'c>(
     parser: &mut Parser<'a, 'o, 'c>,
@@ -45,6 +45,7 @@ fn try_opening_header<'a, 'o, 'c>(
 
     let mut alignments = vec![];
     for cell in marker_row {
+        let cell = cell.content;
         let left = !cell.is_empty() && cell[0] == b':';
         let right = !cell.is_empty() && cell[cell.len() - 1] == b':';
         alignments.push(if left && right {
@@ -58,15 +59,32 @@ fn try_opening_header<'a, 'o, 'c>(
         });
     }
 
+    let (header_start, header_offset, header_length) = {
+        let ast = container.data.borrow();
+        let offset = ast.line_offsets.first().map_or(0, |&o| o);
+        (
+            ast.sourcepos.start,
+            offset,
+            offset + strings::line_length(&ast.content),
+        )
+    };
+
     let mut child = Ast::new(NodeValue::Table(alignments));
-    child.start_line = parser.line_number;
+    child.sourcepos.start = header_start;
     let table = parser.arena.alloc(Node::new(RefCell::new(child)));
     container.append(table);
 
-    let header = parser.add_child(table, NodeValue::TableRow(true));
-    for header_str in header_row {
-        let header_cell = parser.add_child(header, NodeValue::TableCell);
-        header_cell.data.borrow_mut().content = header_str;
+    let header = parser.add_child(table, NodeValue::TableRow(true), header_start.column);
+    header.data.borrow_mut().sourcepos = (
+        header_start.line,
+        header_start.column,
+        header_start.line,
+        header_length,
+    )
+        .into();
+    for cell in header_row {
+        let header_cell = parser.add_child(header, NodeValue::TableCell, 0);
+        add_cell_content(header_cell, header_start.line, header_offset, cell);
     }
 
     let offset = line.len() - 1 - parser.offset;
@@ -85,18 +103,25 @@ fn try_opening_row<'a, 'o, 'c>(
         return None;
     }
     let this_row = row(&line[parser.first_nonspace..]).unwrap();
-    let new_row = parser.add_child(container, NodeValue::TableRow(false));
-
-    let mut i = 0;
-    while i < min(alignments.len(), this_row.len()) {
-        let cell = parser.add_child(new_row, NodeValue::TableCell);
-        cell.data.borrow_mut().content = this_row[i].clone();
-        i += 1;
+    let new_row = parser.add_child(
+        container,
+        NodeValue::TableRow(false),
+        parser.first_nonspace + 1,
+    );
+    let line_number = parser.line_number;
+    new_row.data.borrow_mut().sourcepos.end = (line_number, strings::line_length(line)).into();
+
+    let cell_count = min(alignments.len(), this_row.len());
+    for cell in this_row.into_iter().take(cell_count) {
+        let cell_node = parser.add_child(new_row, NodeValue::TableCell, 0);
+        add_cell_content(cell_node, line_number, parser.first_nonspace, cell);
     }
 
-    while i < alignments.len() {
-        parser.add_child(new_row, NodeValue::TableCell);
-        i += 1;
+    // Missing cells are empty, and positioned at the end of the row.
+    let end = new_row.data.borrow().sourcepos.end;
+    for _ in cell_count..alignments.len() {
+        let cell_node = parser.add_child(new_row, NodeValue::TableCell, end.column);
+        cell_node.data.borrow_mut().sourcepos.end = end;
     }
 
     let offset = line.len() - 1 - parser.offset;
@@ -105,7 +130,26 @@ fn try_opening_row<'a, 'o, 'c>(
     Some((new_row, false))
 }
 
-fn row(string: &[u8]) -> Option<Vec<Vec<u8>>> {
+struct Cell {
+    start_offset: usize,
+    end_offset: usize,
+    content: Vec<u8>,
+}
+
+fn add_cell_content<'a>(node: &'a AstNode<'a>, line: usize, offset: usize, cell: Cell) {
+    let mut ast = node.data.borrow_mut();
+    ast.sourcepos = (
+        line,
+        offset + cell.start_offset + 1,
+        line,
+        offset + cell.end_offset,
+    )
+        .into();
+    ast.line_offsets = vec![offset + cell.start_offset];
+    ast.content = cell.content;
+}
+
+fn row(string: &[u8]) -> Option<Vec<Cell>> {
     let len = string.len();
     let mut v = vec![];
     let mut offset = 0;
@@ -120,9 +164,16 @@ fn row(string: &[u8]) -> Option<Vec<Vec<u8>>> {
             scanners::table_cell_end(&string[offset + cell_matched..]).unwrap_or(0);
 
         if cell_matched > 0 || pipe_matched > 0 {
-            let mut cell = unescape_pipes(&string[offset..offset + cell_matched]);
-            trim(&mut cell);
-            v.push(cell);
+            let raw = &string[offset..offset + cell_matched];
+            let leading = raw.len() - strings::ltrim_slice(raw).len();
+            let trailing = raw.len() - strings::rtrim_slice(raw).len();
+            let mut content = unescape_pipes(raw);
+            strings::trim(&mut content);
+            v.push(Cell {
+                start_offset: offset + leading,
+                end_offset: max(offset + leading + 1, offset + cell_matched - trailing),
+                content,
+            });
         }
 
         offset += cell_matched + pipe_matched;
@@ -0,0 +1,144 @@
+//! Adapter for resolving relative URLs against a base URL.
+
+use adapters::{UrlKind, UrlRewriterAdapter};
+use scanners;
+use twoway;
+
+/// Resolves the relative URLs of links and images against a base URL, as a browser would for a
+/// page at that URL.
+///
+/// URLs with a scheme are left as they are, as are fragment-only URLs such as `#usage`, so that
+/// links within the page keep working.
+///
+/// ```
+/// # use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+/// use comrak::plugins::base_url::BaseUrlResolver;
+///
+/// let resolver = BaseUrlResolver::new("https://example.com/docs/v1/index.html");
+/// let mut plugins = ComrakPlugins::default();
+/// plugins.render.url_rewriter = Some(&resolver);
+///
+/// assert_eq!(markdown_to_html_with_plugins(
+///                "[a](guide.md) [b](../v2/) [c](/about) ![d](//cdn.example.com/d.png) [e](#top)\n",
+///                &ComrakOptions::default(),
+///                &plugins),
+///            "<p><a href=\"https://example.com/docs/v1/guide.md\">a</a> \
+///             <a href=\"https://example.com/docs/v2/\">b</a> \
+///             <a href=\"https://example.com/about\">c</a> \
+///             <img src=\"https://cdn.example.com/d.png\" alt=\"d\" /> \
+///             <a href=\"#top\">e</a></p>\n");
+/// ```
+#[derive(Debug, Clone)]
+pub struct BaseUrlResolver {
+    // The scheme and authority, e.g. `https://example.com`; empty for a path-only base.
+    origin: Vec<u8>,
+    // The path, always starting with `/`.
+    path: Vec<u8>,
+}
+
+impl BaseUrlResolver {
+    /// Construct a resolver for the given base URL.  The base may also be an absolute path
+    /// alone, such as `/docs/`, in which case resolved URLs are absolute paths.
+    ///
+    /// As in a browser, the last segment of the base's path is replaced by relative URLs; give a
+    /// trailing slash to resolve URLs within a directory.
+    pub fn new(base: &str) -> Self {
+        let base = base.as_bytes();
+        let base = &base[..base
+            .iter()
+            .position(|&c| c == b'?' || c == b'#')
+            .unwrap_or(base.len())];
+
+        let (origin, path) = match twoway::find_bytes(base, b"://") {
+            Some(ix) => {
+                let authority = ix + 3;
+                let path = base[authority..]
+                    .iter()
+                    .position(|&c| c == b'/')
+                    .map_or(base.len(), |p| authority + p);
+                (&base[..path], &base[path..])
+            }
+            None => (&b""[..], base),
+        };
+
+        let mut path = path.to_vec();
+        if path.first() != Some(&b'/') {
+            path.insert(0, b'/');
+        }
+
+        BaseUrlResolver {
+            origin: origin.to_vec(),
+            path,
+        }
+    }
+
+    /// Resolve a URL against the base URL.
+    pub fn resolve(&self, url: &[u8]) -> Vec<u8> {
+        if url.is_empty() || url[0] == b'#' || scanners::scheme(url).is_some() {
+            return url.to_vec();
+        }
+
+        if url.starts_with(b"//") {
+            return match self.origin.iter().position(|&c| c == b':') {
+                Some(ix) => [&self.origin[..=ix], url].concat(),
+                None => url.to_vec(),
+            };
+        }
+
+        let split = url
+            .iter()
+            .position(|&c| c == b'?' || c == b'#')
+            .unwrap_or(url.len());
+        let (path, suffix) = url.split_at(split);
+
+        let path = if path.is_empty() {
+            self.path.clone()
+        } else if path[0] == b'/' {
+            remove_dot_segments(path)
+        } else {
+            let directory = self.path.iter().rposition(|&c| c == b'/').unwrap() + 1;
+            remove_dot_segments(&[&self.path[..directory], path].concat())
+        };
+
+        [&self.origin[..], &path, suffix].concat()
+    }
+}
+
+impl UrlRewriterAdapter for BaseUrlResolver {
+    fn rewrite(&self, url: &[u8], _kind: UrlKind) -> Vec<u8> {
+        self.resolve(url)
+    }
+}
+
+// Removes `.` and `..` segments from an absolute path, per RFC 3986 section 5.2.4.
+fn remove_dot_segments(path: &[u8]) -> Vec<u8> {
+    let mut segments: Vec<&[u8]> = vec![];
+    let mut parts = path[1..].split(|&c| c == b'/').peekable();
+    while let Some(part) = parts.next() {
+        let last = parts.peek().is_none();
+        match part {
+            b"." => {
+                if last {
+                    segments.push(b"");
+                }
+            }
+            b".." => {
+                segments.pop();
+                if last {
+                    segments.push(b"");
+                }
+            }
+            _ => segments.push(part),
+        }
+    }
+
+    let mut output = vec![];
+    for segment in segments {
+        output.push(b'/');
+        output.extend_from_slice(segment);
+    }
+    if output.is_empty() {
+        output.push(b'/');
+    }
+    output
+}
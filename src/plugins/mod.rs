@@ -0,0 +1,5 @@
+//! Plugins for enhancing the default implementation of comrak can be defined in this module.
+
+pub mod base_url;
+#[cfg(feature = "syntect")]
+pub mod syntect;
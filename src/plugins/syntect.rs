@@ -0,0 +1,107 @@
+//! Adapter for the Syntect syntax highlighter plugin.
+
+use adapters::SyntaxHighlighterAdapter;
+use html;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+#[derive(Debug)]
+/// Syntect syntax highlighter plugin.
+///
+/// ```
+/// # use comrak::{markdown_to_html_with_plugins, ComrakOptions, ComrakPlugins};
+/// use comrak::plugins::syntect::SyntectAdapter;
+///
+/// let adapter = SyntectAdapter::new("base16-ocean.dark");
+/// let mut plugins = ComrakPlugins::default();
+/// plugins.render.codefence_syntax_highlighter = Some(&adapter);
+///
+/// let html = markdown_to_html_with_plugins(
+///     "``` rust\nfn main() {}\n```\n",
+///     &ComrakOptions::default(),
+///     &plugins,
+/// );
+/// assert!(html.starts_with("<pre style=\"background-color:#2b303b;\"><code class=\"language-rust\">"));
+/// ```
+pub struct SyntectAdapter<'a> {
+    theme: &'a str,
+    syntax_set: SyntaxSet,
+    theme_set: ThemeSet,
+}
+
+impl<'a> SyntectAdapter<'a> {
+    /// Construct a new `SyntectAdapter` object and set the syntax highlighting theme.
+    ///
+    /// The theme must be one of Syntect's default themes, such as `base16-ocean.dark` or
+    /// `InspiredGitHub`.
+    pub fn new(theme: &'a str) -> Self {
+        SyntectAdapter {
+            theme,
+            syntax_set: SyntaxSet::load_defaults_newlines(),
+            theme_set: ThemeSet::load_defaults(),
+        }
+    }
+
+    fn background(&self) -> Option<Color> {
+        self.theme_set
+            .themes
+            .get(self.theme)
+            .and_then(|theme| theme.settings.background)
+    }
+}
+
+impl<'a> SyntaxHighlighterAdapter for SyntectAdapter<'a> {
+    fn write_highlighted(
+        &self,
+        output: &mut dyn Write,
+        lang: Option<&str>,
+        code: &str,
+    ) -> io::Result<()> {
+        let theme = match self.theme_set.themes.get(self.theme) {
+            Some(theme) => theme,
+            None => return html::escape(output, code.as_bytes()),
+        };
+
+        let syntax = lang
+            .and_then(|lang| self.syntax_set.find_syntax_by_token(lang))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        for line in LinesWithEndings::from(code) {
+            let regions = highlighter
+                .highlight_line(line, &self.syntax_set)
+                .map_err(io::Error::other)?;
+            let html = styled_line_to_highlighted_html(&regions, IncludeBackground::No)
+                .map_err(io::Error::other)?;
+            output.write_all(html.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_pre_tag(
+        &self,
+        output: &mut dyn Write,
+        mut attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        if let Some(c) = self.background() {
+            attributes.insert(
+                "style".to_string(),
+                format!("background-color:#{:02x}{:02x}{:02x};", c.r, c.g, c.b),
+            );
+        }
+        html::write_opening_tag(output, "pre", &attributes)
+    }
+
+    fn write_code_tag(
+        &self,
+        output: &mut dyn Write,
+        attributes: HashMap<String, String>,
+    ) -> io::Result<()> {
+        html::write_opening_tag(output, "code", &attributes)
+    }
+}
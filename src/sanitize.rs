@@ -0,0 +1,480 @@
+//! Sanitizing of raw HTML and of link and image URLs.
+
+use entity;
+use html::escape;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+/// An allowlist of HTML tags, attributes and URL schemes, used to sanitize raw HTML in the
+/// document when rendering.  Enabled with the `sanitize` render option.
+///
+/// Tags not on the list are removed, leaving their contents in place; `script` and `style`
+/// elements are removed along with their contents.  Comments, processing instructions,
+/// declarations and CDATA sections are removed.  Allowed tags keep only the attributes on the
+/// list, and URL attributes such as `href` and `src` are removed if their URL has a scheme not on
+/// the list.
+///
+/// The default allowlist keeps common inline and block formatting tags, such as `<details>`,
+/// `<kbd>` and `<sub>`, and allows `http`, `https` and `mailto` URLs.
+///
+/// ```
+/// # use comrak::{markdown_to_html, ComrakOptions};
+/// use comrak::sanitize::Sanitizer;
+///
+/// let mut sanitizer = Sanitizer::default();
+/// sanitizer.tags.insert("marquee".into());
+///
+/// let mut options = ComrakOptions::default();
+/// options.render.sanitize = Some(sanitizer);
+/// assert_eq!(markdown_to_html("<marquee onclick=\"evil()\">Hi</marquee> <blink>there</blink>\n", &options),
+///            "<p><marquee>Hi</marquee> there</p>\n");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sanitizer {
+    /// The tags which are allowed, in lowercase.
+    pub tags: HashSet<String>,
+
+    /// The attributes allowed on particular tags, in lowercase, keyed by tag.
+    pub tag_attributes: HashMap<String, HashSet<String>>,
+
+    /// The attributes allowed on every allowed tag, in lowercase.
+    pub generic_attributes: HashSet<String>,
+
+    /// The schemes allowed in URL attributes, in lowercase.  URLs without a scheme, such as
+    /// relative URLs and fragments, are always allowed.
+    pub url_schemes: HashSet<String>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Sanitizer {
+        let set = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<HashSet<_>>();
+
+        let tag_attributes = [
+            ("a", &["href"][..]),
+            ("img", &["src", "alt", "width", "height", "align"][..]),
+            ("details", &["open"][..]),
+            ("ol", &["start", "type"][..]),
+            ("li", &["value"][..]),
+            ("td", &["align", "colspan", "rowspan"][..]),
+            ("th", &["align", "colspan", "rowspan"][..]),
+            ("blockquote", &["cite"][..]),
+            ("q", &["cite"][..]),
+            ("del", &["cite", "datetime"][..]),
+            ("ins", &["cite", "datetime"][..]),
+            ("time", &["datetime"][..]),
+            ("div", &["align"][..]),
+            ("p", &["align"][..]),
+        ]
+        .iter()
+        .map(|&(tag, attributes)| (tag.to_string(), set(attributes)))
+        .collect();
+
+        Sanitizer {
+            tags: set(&[
+                "a",
+                "abbr",
+                "b",
+                "bdi",
+                "bdo",
+                "blockquote",
+                "br",
+                "caption",
+                "cite",
+                "code",
+                "dd",
+                "del",
+                "details",
+                "dfn",
+                "div",
+                "dl",
+                "dt",
+                "em",
+                "figcaption",
+                "figure",
+                "h1",
+                "h2",
+                "h3",
+                "h4",
+                "h5",
+                "h6",
+                "hr",
+                "i",
+                "img",
+                "ins",
+                "kbd",
+                "li",
+                "mark",
+                "ol",
+                "p",
+                "pre",
+                "q",
+                "rp",
+                "rt",
+                "ruby",
+                "s",
+                "samp",
+                "small",
+                "span",
+                "strike",
+                "strong",
+                "sub",
+                "summary",
+                "sup",
+                "table",
+                "tbody",
+                "td",
+                "tfoot",
+                "th",
+                "thead",
+                "time",
+                "tr",
+                "tt",
+                "u",
+                "ul",
+                "var",
+                "wbr",
+            ]),
+            tag_attributes,
+            generic_attributes: set(&["title", "lang", "dir"]),
+            url_schemes: set(&["http", "https", "mailto"]),
+        }
+    }
+}
+
+// Elements whose contents are removed along with them when they aren't allowed.
+const DROP_CONTENTS: [&str; 2] = ["script", "style"];
+
+// Attributes whose values are URLs.
+const URL_ATTRIBUTES: [&str; 8] = [
+    "href",
+    "src",
+    "cite",
+    "action",
+    "formaction",
+    "longdesc",
+    "poster",
+    "background",
+];
+
+impl Sanitizer {
+    /// Writes `input`, a fragment of raw HTML, with everything not allowed removed.
+    pub(crate) fn clean(&self, input: &[u8], output: &mut dyn Write) -> io::Result<()> {
+        let size = input.len();
+        let mut i = 0;
+        let mut dropping: Option<String> = None;
+
+        while i < size {
+            let org = i;
+            while i < size && input[i] != b'<' {
+                i += 1;
+            }
+
+            if i > org && dropping.is_none() {
+                output.write_all(&input[org..i])?;
+            }
+
+            if i >= size {
+                break;
+            }
+
+            if let Some(tag) = parse_tag(&input[i..]) {
+                i += tag.len;
+                if let Some(ref name) = dropping {
+                    if tag.closing && tag.name == *name {
+                        dropping = None;
+                    }
+                    continue;
+                }
+                if self.tags.contains(&tag.name) {
+                    self.write_tag(&tag, output)?;
+                } else if !tag.closing && DROP_CONTENTS.contains(&tag.name.as_str()) {
+                    dropping = Some(tag.name);
+                }
+            } else if let Some(len) = markup_len(&input[i..]) {
+                i += len;
+            } else {
+                if dropping.is_none() {
+                    output.write_all(b"&lt;")?;
+                }
+                i += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_tag(&self, tag: &Tag, output: &mut dyn Write) -> io::Result<()> {
+        if tag.closing {
+            return write!(output, "</{}>", tag.name);
+        }
+
+        write!(output, "<{}", tag.name)?;
+        for (name, value) in &tag.attributes {
+            if !self.attribute_allowed(&tag.name, name) {
+                continue;
+            }
+            match *value {
+                Some(ref value) => {
+                    if URL_ATTRIBUTES.contains(&name.as_str()) && !self.url_allowed(value) {
+                        continue;
+                    }
+                    write!(output, " {}=\"", name)?;
+                    escape(output, value)?;
+                    output.write_all(b"\"")?;
+                }
+                None => write!(output, " {}", name)?,
+            }
+        }
+        if tag.self_closing {
+            output.write_all(b" />")
+        } else {
+            output.write_all(b">")
+        }
+    }
+
+    fn attribute_allowed(&self, tag: &str, name: &str) -> bool {
+        if self.generic_attributes.contains(name) {
+            return true;
+        }
+        match self.tag_attributes.get(tag) {
+            Some(attributes) => attributes.contains(name),
+            None => false,
+        }
+    }
+
+    fn url_allowed(&self, url: &[u8]) -> bool {
+        match url_scheme(url) {
+            Some(scheme) => self.url_schemes.contains(&scheme),
+            None => true,
+        }
+    }
+}
+
+/// A policy for the URLs of links and images, including autolinks and wikilinks.  Enabled with
+/// the `url_policy` render option, in addition to the default check for dangerous URLs.
+///
+/// URLs without a scheme, such as relative URLs and fragments, are always allowed.
+///
+/// ```
+/// # use comrak::{markdown_to_html, ComrakOptions};
+/// use comrak::sanitize::{RejectedUrl, UrlPolicy};
+///
+/// let mut policy = UrlPolicy::allow(&["https", "mailto", "tel", "app"]);
+/// policy.rejected = RejectedUrl::TextOnly;
+///
+/// let mut options = ComrakOptions::default();
+/// options.render.url_policy = Some(policy);
+/// assert_eq!(markdown_to_html("[call](tel:123) [open](app://x) [get](ftp://y) [up](../)\n", &options),
+///            "<p><a href=\"tel:123\">call</a> <a href=\"app://x\">open</a> get <a href=\"../\">up</a></p>\n");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UrlPolicy {
+    /// The schemes which are allowed or denied.
+    pub schemes: UrlSchemes,
+
+    /// What to do with links and images whose URL is rejected.
+    pub rejected: RejectedUrl,
+}
+
+impl UrlPolicy {
+    /// A policy allowing only the given schemes, which rejects URLs by removing them.
+    pub fn allow<S: AsRef<str>>(schemes: &[S]) -> UrlPolicy {
+        UrlPolicy {
+            schemes: UrlSchemes::Allow(scheme_set(schemes)),
+            rejected: RejectedUrl::RemoveUrl,
+        }
+    }
+
+    /// A policy allowing all but the given schemes, which rejects URLs by removing them.
+    pub fn deny<S: AsRef<str>>(schemes: &[S]) -> UrlPolicy {
+        UrlPolicy {
+            schemes: UrlSchemes::Deny(scheme_set(schemes)),
+            rejected: RejectedUrl::RemoveUrl,
+        }
+    }
+
+    /// Whether the policy allows the given URL.
+    pub fn allows(&self, url: &[u8]) -> bool {
+        match (url_scheme(url), &self.schemes) {
+            (None, _) => true,
+            (Some(scheme), UrlSchemes::Allow(schemes)) => schemes.contains(&scheme),
+            (Some(scheme), UrlSchemes::Deny(schemes)) => !schemes.contains(&scheme),
+        }
+    }
+}
+
+/// The schemes a `UrlPolicy` allows or denies, in lowercase.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum UrlSchemes {
+    /// Only these schemes are allowed.
+    Allow(HashSet<String>),
+
+    /// All schemes but these are allowed.
+    Deny(HashSet<String>),
+}
+
+/// What to do with a link or image whose URL is rejected by a `UrlPolicy`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RejectedUrl {
+    /// Render the link or image with an empty URL, as is done for dangerous URLs by default.
+    RemoveUrl,
+
+    /// Render only the link's text, or the image's alt text, without the link or image.
+    TextOnly,
+
+    /// Render the link or image with this URL instead.
+    Placeholder(String),
+}
+
+fn scheme_set<S: AsRef<str>>(schemes: &[S]) -> HashSet<String> {
+    schemes
+        .iter()
+        .map(|scheme| scheme.as_ref().to_lowercase())
+        .collect()
+}
+
+// The scheme of a URL in lowercase, if it has one.
+fn url_scheme(url: &[u8]) -> Option<String> {
+    // Browsers ignore whitespace and control characters within URLs.
+    let url = url
+        .iter()
+        .filter(|&&c| c > b' ')
+        .cloned()
+        .collect::<Vec<_>>();
+    match url
+        .iter()
+        .position(|&c| matches!(c, b':' | b'/' | b'?' | b'#'))
+    {
+        Some(ix) if url[ix] == b':' => Some(String::from_utf8_lossy(&url[..ix]).to_lowercase()),
+        _ => None,
+    }
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, Option<Vec<u8>>)>,
+    len: usize,
+}
+
+// Parses an opening or closing tag at the start of `input`, per
+// https://github.github.com/gfm/#raw-html.
+fn parse_tag(input: &[u8]) -> Option<Tag> {
+    let size = input.len();
+    let mut i = 1;
+    let closing = input.get(i) == Some(&b'/');
+    if closing {
+        i += 1;
+    }
+
+    if !input.get(i)?.is_ascii_alphabetic() {
+        return None;
+    }
+    let start = i;
+    while i < size && (input[i].is_ascii_alphanumeric() || input[i] == b'-') {
+        i += 1;
+    }
+    let name = String::from_utf8_lossy(&input[start..i]).to_lowercase();
+
+    let mut attributes = vec![];
+    let mut self_closing = false;
+    loop {
+        let before = i;
+        while i < size && input[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        match *input.get(i)? {
+            b'>' => {
+                i += 1;
+                break;
+            }
+            b'/' if !closing && input.get(i + 1) == Some(&b'>') => {
+                self_closing = true;
+                i += 2;
+                break;
+            }
+            c if !closing && i > before && (c.is_ascii_alphabetic() || c == b'_' || c == b':') => {}
+            _ => return None,
+        }
+
+        let start = i;
+        while i < size
+            && (input[i].is_ascii_alphanumeric() || matches!(input[i], b'_' | b'.' | b':' | b'-'))
+        {
+            i += 1;
+        }
+        let attribute = String::from_utf8_lossy(&input[start..i]).to_lowercase();
+
+        let mut j = i;
+        while j < size && input[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        if input.get(j) != Some(&b'=') {
+            attributes.push((attribute, None));
+            continue;
+        }
+        j += 1;
+        while j < size && input[j].is_ascii_whitespace() {
+            j += 1;
+        }
+
+        let value = match *input.get(j)? {
+            quote @ b'"' | quote @ b'\'' => {
+                let len = input[j + 1..].iter().position(|&c| c == quote)?;
+                i = j + len + 2;
+                &input[j + 1..j + 1 + len]
+            }
+            _ => {
+                let start = j;
+                while j < size
+                    && !input[j].is_ascii_whitespace()
+                    && !matches!(input[j], b'"' | b'\'' | b'=' | b'<' | b'>' | b'`')
+                {
+                    j += 1;
+                }
+                if j == start {
+                    return None;
+                }
+                i = j;
+                &input[start..j]
+            }
+        };
+        attributes.push((attribute, Some(entity::unescape_html(value))));
+    }
+
+    Some(Tag {
+        name,
+        closing,
+        self_closing,
+        attributes,
+        len: i,
+    })
+}
+
+// The length of a comment, processing instruction, declaration or CDATA section at the start of
+// `input`.  An unterminated one extends to the end of the input.
+fn markup_len(input: &[u8]) -> Option<usize> {
+    let (open, close): (&[u8], &[u8]) = if input.starts_with(b"<!--") {
+        (b"<!--", b"-->")
+    } else if input.starts_with(b"<![CDATA[") {
+        (b"<![CDATA[", b"]]>")
+    } else if input.starts_with(b"<?") {
+        (b"<?", b"?>")
+    } else if input.starts_with(b"<!") && input.len() > 2 && input[2].is_ascii_alphabetic() {
+        (b"<!", b">")
+    } else {
+        return None;
+    };
+
+    Some(
+        input[open.len()..]
+            .windows(close.len())
+            .position(|w| w == close)
+            .map_or(input.len(), |ix| open.len() + ix + close.len()),
+    )
+}
@@ -85,6 +85,22 @@ pub fn close_code_fence(line: &[u8]) -> Option<usize> {
     search(Rule::close_code_fence, line)
 }
 
+#[inline(always)]
+pub fn open_container_fence(line: &[u8]) -> Option<usize> {
+    if line[0] != b':' {
+        return None;
+    }
+    search(Rule::open_container_fence, line)
+}
+
+#[inline(always)]
+pub fn close_container_fence(line: &[u8]) -> Option<usize> {
+    if line[0] != b':' {
+        return None;
+    }
+    search(Rule::close_container_fence, line)
+}
+
 #[inline(always)]
 pub fn html_block_start(line: &[u8]) -> Option<usize> {
     lazy_static! {
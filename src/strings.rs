@@ -116,6 +116,17 @@ pub fn is_space_or_tab(ch: u8) -> bool {
     matches!(ch, 9 | 32)
 }
 
+pub fn line_length(line: &[u8]) -> usize {
+    let mut len = line.len();
+    if len > 0 && line[len - 1] == b'\n' {
+        len -= 1;
+    }
+    if len > 0 && line[len - 1] == b'\r' {
+        len -= 1;
+    }
+    len
+}
+
 pub fn chop_trailing_hashtags(line: &mut Vec<u8>) {
     rtrim(line);
 
@@ -162,8 +173,7 @@ pub fn rtrim_slice(mut i: &[u8]) -> &[u8] {
     i
 }
 
-pub fn trim_slice(mut i: &[u8]) -> &[u8] {
-    i = rtrim_slice(i);
+pub fn ltrim_slice(mut i: &[u8]) -> &[u8] {
     let mut len = i.len();
     while len > 0 && isspace(i[0]) {
         i = &i[1..];
@@ -172,6 +182,10 @@ pub fn trim_slice(mut i: &[u8]) -> &[u8] {
     i
 }
 
+pub fn trim_slice(i: &[u8]) -> &[u8] {
+    ltrim_slice(rtrim_slice(i))
+}
+
 fn shift_buf_left(buf: &mut [u8], n: usize) {
     assert!(n <= buf.len());
     let keep = buf.len() - n;
@@ -1,10 +1,16 @@
+use adapters::{NodeRendererAdapter, SyntaxHighlighterAdapter};
 use cm;
-use html;
+use html::{self, ExternalLinks};
+use nodes::{AstNode, NodeValue};
 use propfuzz::prelude::*;
+use sanitize::{RejectedUrl, Sanitizer, UrlPolicy};
+use std::collections::HashMap;
+use std::io::{self, Write};
 use timebomb::timeout_ms;
+use xml;
 use {
     parse_document, Arena, ComrakExtensionOptions, ComrakOptions, ComrakParseOptions,
-    ComrakRenderOptions,
+    ComrakPlugins, ComrakRenderOptions,
 };
 
 #[propfuzz]
@@ -17,10 +23,24 @@ fn fuzz_doesnt_crash(md: String) {
             autolink: true,
             tasklist: true,
             superscript: true,
+            highlight: true,
+            insert: true,
+            subscript: true,
+            spoiler: true,
+            underline: true,
             header_ids: Some("user-content-".to_string()),
             footnotes: true,
             description_lists: true,
             front_matter_delimiter: None,
+            wikilinks: true,
+            wikilinks_title_before_pipe: false,
+            math: true,
+            alerts: true,
+            table_of_contents: true,
+            attributes: true,
+            custom_containers: true,
+            shortcodes: true,
+            custom_shortcodes: HashMap::new(),
         },
         parse: ComrakParseOptions {
             smart: true,
@@ -32,6 +52,11 @@ fn fuzz_doesnt_crash(md: String) {
             width: 80,
             unsafe_: true,
             escape: false,
+            sanitize: None,
+            url_policy: None,
+            external_links: None,
+            footnote_id_prefix: Some("fn-".to_string()),
+            sourcepos: false,
         },
     };
 
@@ -132,6 +157,48 @@ fn codefence() {
     );
 }
 
+#[test]
+fn codefence_info_string_escaped() {
+    html(
+        concat!("``` a\"onclick=\"x()\"<b>\n", "code\n", "```\n"),
+        concat!(
+            "<pre><code class=\"language-a&quot;onclick=&quot;x()&quot;&lt;b&gt;\">code\n",
+            "</code></pre>\n"
+        ),
+    );
+
+    html_opts(
+        concat!("``` a\"onclick=\"x()\"<b>\n", "code\n", "```\n"),
+        concat!(
+            "<pre lang=\"a&quot;onclick=&quot;x()&quot;&lt;b&gt;\"><code>code\n",
+            "</code></pre>\n"
+        ),
+        |opts| opts.render.github_pre_lang = true,
+    );
+}
+
+#[test]
+fn codefence_literal_escaped() {
+    html(
+        concat!(
+            "```\n",
+            "</code></pre><script>alert(\"&amp;\")</script>\n",
+            "```\n",
+            "\n",
+            "text\n",
+            "\n",
+            "    <b>indented</b> & more\n"
+        ),
+        concat!(
+            "<pre><code>&lt;/code&gt;&lt;/pre&gt;&lt;script&gt;alert(&quot;&amp;amp;&quot;)&lt;/script&gt;\n",
+            "</code></pre>\n",
+            "<p>text</p>\n",
+            "<pre><code>&lt;b&gt;indented&lt;/b&gt; &amp; more\n",
+            "</code></pre>\n"
+        ),
+    );
+}
+
 #[test]
 fn lists() {
     html(
@@ -522,6 +589,41 @@ fn tagfilter() {
     );
 }
 
+#[test]
+fn sanitize() {
+    html_opts(
+        concat!(
+            "<div align=\"center\" style=\"color: red\" onmouseover=\"evil()\">\n",
+            "<!-- a comment -->\n",
+            "<img src=\"/logo.png\" alt=\"Logo\" onerror=\"evil()\"><iframe src=\"x\"></iframe>\n",
+            "<style>p { display: none }</style>\n",
+            "</div>\n",
+            "\n",
+            "<a href=\"jav&#x61;script:evil()\">x</a> <a href=\"HTTPS://example.com\" target=_blank>y</a>\n",
+            "<sub>2</sub> <kbd title='a \"key\"'>K</kbd> <x-y>z</x-y>\n",
+        ),
+        concat!(
+            "<div align=\"center\">\n",
+            "\n",
+            "<img src=\"/logo.png\" alt=\"Logo\">\n",
+            "\n",
+            "</div>\n",
+            "<p><a>x</a> <a href=\"HTTPS://example.com\">y</a>\n",
+            "<sub>2</sub> <kbd title=\"a &quot;key&quot;\">K</kbd> z</p>\n",
+        ),
+        |opts| opts.render.sanitize = Some(Sanitizer::default()),
+    );
+
+    html_opts(
+        "<b>bold</b>\n",
+        "<p>&lt;b&gt;bold&lt;/b&gt;</p>\n",
+        |opts| {
+            opts.render.sanitize = Some(Sanitizer::default());
+            opts.render.escape = true;
+        },
+    );
+}
+
 #[test]
 fn tasklist() {
     html_opts!(
@@ -595,6 +697,91 @@ fn superscript() {
     );
 }
 
+#[test]
+fn highlight() {
+    html_opts!(
+        [extension.highlight],
+        concat!(
+            "This is ==highlighted==.\n",
+            "\n",
+            "But =not this= or ===this===, and a == b.\n"
+        ),
+        concat!(
+            "<p>This is <mark>highlighted</mark>.</p>\n",
+            "<p>But =not this= or ===this===, and a == b.</p>\n"
+        ),
+    );
+    html_opts!(
+        [extension.highlight],
+        "Escaped \\=\\=c\\=\\=.\n",
+        "<p>Escaped ==c==.</p>\n",
+    );
+}
+
+#[test]
+fn insert() {
+    html_opts!(
+        [extension.insert],
+        concat!(
+            "This is ++inserted **text**++.\n",
+            "\n",
+            "But not a ++ b.\n"
+        ),
+        concat!(
+            "<p>This is <ins>inserted <strong>text</strong></ins>.</p>\n",
+            "<p>But not a ++ b.</p>\n"
+        ),
+    );
+    html_opts!(
+        [extension.insert],
+        "Escaped \\+\\+c\\+\\+.\n",
+        "<p>Escaped ++c++.</p>\n",
+    );
+}
+
+#[test]
+fn subscript() {
+    html_opts!(
+        [extension.subscript],
+        concat!("H~2~O, but not ~~this~~.\n"),
+        concat!("<p>H<sub>2</sub>O, but not ~~this~~.</p>\n"),
+    );
+    html_opts!(
+        [extension.subscript, extension.strikethrough],
+        concat!("H~2~O is ~~not~~ water.\n"),
+        concat!("<p>H<sub>2</sub>O is <del>not</del> water.</p>\n"),
+    );
+}
+
+#[test]
+fn spoiler() {
+    html_opts!(
+        [extension.spoiler],
+        concat!("The killer is ||the butler||.\n", "\n", "But a | b || c.\n"),
+        concat!(
+            "<p>The killer is <span class=\"spoiler\">the butler</span>.</p>\n",
+            "<p>But a | b || c.</p>\n"
+        ),
+    );
+    html_opts!(
+        [extension.spoiler],
+        "Escaped \\|\\|b\\|\\|.\n",
+        "<p>Escaped ||b||.</p>\n",
+    );
+}
+
+#[test]
+fn underline() {
+    html_opts!(
+        [extension.underline],
+        concat!("__underlined__, **strong**, _emph_ and ___both___.\n"),
+        concat!(
+            "<p><u>underlined</u>, <strong>strong</strong>, <em>emph</em> and \
+             <em><u>both</u></em>.</p>\n"
+        ),
+    );
+}
+
 #[test]
 fn header_ids() {
     html_opts(
@@ -715,7 +902,7 @@ fn footnote_in_table() {
             "</thead>\n",
             "<tbody>\n",
             "<tr>\n",
-            "<td>foot <sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup></td>\n",
+            "<td>foot <sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1-2\">1</a></sup></td>\n",
             "<td>note</td>\n",
             "</tr>\n",
             "</tbody>\n",
@@ -723,13 +910,183 @@ fn footnote_in_table() {
             "<section class=\"footnotes\">\n",
             "<ol>\n",
             "<li id=\"fn1\">\n",
-            "<p>a footnote <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n",
+            "<p>a footnote <a href=\"#fnref1\" class=\"footnote-backref\">↩</a> \
+             <a href=\"#fnref1-2\" class=\"footnote-backref\">↩<sup class=\"footnote-ref\">2</sup></a></p>\n",
             "</li>\n",
             "</ol>\n",
             "</section>\n",
         ));
 }
 
+#[test]
+fn footnote_multiple_references() {
+    html_opts!(
+        [extension.footnotes],
+        concat!(
+            "a[^x] b[^y] c[^x] d[^x]\n",
+            "\n",
+            "[^x]: X.\n",
+            "\n",
+            "        code\n",
+            "\n",
+            "[^y]: Y.\n"
+        ),
+        concat!(
+            "<p>a<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup> \
+             b<sup class=\"footnote-ref\"><a href=\"#fn2\" id=\"fnref2\">2</a></sup> \
+             c<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1-2\">1</a></sup> \
+             d<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1-3\">1</a></sup></p>\n",
+            "<section class=\"footnotes\">\n",
+            "<ol>\n",
+            "<li id=\"fn1\">\n",
+            "<p>X.</p>\n",
+            "<pre><code>code\n",
+            "</code></pre>\n",
+            "<a href=\"#fnref1\" class=\"footnote-backref\">↩</a> \
+             <a href=\"#fnref1-2\" class=\"footnote-backref\">↩<sup class=\"footnote-ref\">2</sup></a> \
+             <a href=\"#fnref1-3\" class=\"footnote-backref\">↩<sup class=\"footnote-ref\">3</sup></a>\n",
+            "</li>\n",
+            "<li id=\"fn2\">\n",
+            "<p>Y. <a href=\"#fnref2\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "</ol>\n",
+            "</section>\n"
+        ),
+    );
+}
+
+#[test]
+fn inline_footnotes() {
+    html_opts!(
+        [extension.footnotes],
+        concat!(
+            "Inline^[a *short* note] and labelled[^inline-1].\n",
+            "\n",
+            "Not a footnote: x^2 ^[].\n",
+            "\n",
+            "Undefined: A^[x] and [^inline-2].\n",
+            "\n",
+            "[^inline-1]: A clashing name.\n"
+        ),
+        concat!(
+            "<p>Inline<sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup> and \
+             labelled<sup class=\"footnote-ref\"><a href=\"#fn2\" id=\"fnref2\">2</a></sup>.</p>\n",
+            "<p>Not a footnote: x^2 ^[].</p>\n",
+            "<p>Undefined: A<sup class=\"footnote-ref\"><a href=\"#fn3\" id=\"fnref3\">3</a></sup> \
+             and [^inline-2].</p>\n",
+            "<section class=\"footnotes\">\n",
+            "<ol>\n",
+            "<li id=\"fn1\">\n",
+            "<p>a <em>short</em> note <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "<li id=\"fn2\">\n",
+            "<p>A clashing name. <a href=\"#fnref2\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "<li id=\"fn3\">\n",
+            "<p>x <a href=\"#fnref3\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "</ol>\n",
+            "</section>\n",
+        ),
+    );
+}
+
+#[test]
+fn inline_footnotes_in_links_and_across_paragraphs() {
+    html_opts!(
+        [extension.footnotes],
+        concat!("[x ^[y]](u)\n", "\n", "^[a\n", "\n", "b]\n"),
+        concat!(
+            "<p>[x <sup class=\"footnote-ref\"><a href=\"#fn1\" id=\"fnref1\">1</a></sup>](u)</p>\n",
+            "<p>^[a</p>\n",
+            "<p>b]</p>\n",
+            "<section class=\"footnotes\">\n",
+            "<ol>\n",
+            "<li id=\"fn1\">\n",
+            "<p>y <a href=\"#fnref1\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "</ol>\n",
+            "</section>\n",
+        ),
+    );
+}
+
+#[test]
+fn inline_footnotes_commonmark() {
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.extension.footnotes = true;
+    let root = parse_document(&arena, "Hi^[a *note*] and[^x].\n\n[^x]: X.\n", &options);
+    let mut md = vec![];
+    cm::format_document(root, &options, &mut md).unwrap();
+    compare_strs(
+        &String::from_utf8(md).unwrap(),
+        concat!(
+            "Hi[^inline-1] and[^x].\n",
+            "\n",
+            "[^inline-1]:\n",
+            "    a *note*\n",
+            "\n",
+            "[^x]:\n",
+            "    X.\n",
+        ),
+        "commonmark",
+    );
+}
+
+#[test]
+fn footnote_names_and_id_prefix() {
+    let input = concat!("Hi[^My-Note].\n", "\n", "[^my-note]: There.\n");
+
+    html_opts(
+        input,
+        concat!(
+            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#c1-fn1\" id=\"c1-fnref1\">1</a></sup>.</p>\n",
+            "<section class=\"footnotes\">\n",
+            "<ol>\n",
+            "<li id=\"c1-fn1\">\n",
+            "<p>There. <a href=\"#c1-fnref1\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "</ol>\n",
+            "</section>\n",
+        ),
+        |opts| {
+            opts.extension.footnotes = true;
+            opts.render.footnote_id_prefix = Some("c1-".to_string());
+        },
+    );
+
+    html_opts(
+        "Hi[^1].\n\n[^1]: There.\n",
+        concat!(
+            "<p>Hi<sup class=\"footnote-ref\"><a href=\"#a%22bfn1\" id=\"a&quot;bfnref1\">1</a></sup>.</p>\n",
+            "<section class=\"footnotes\">\n",
+            "<ol>\n",
+            "<li id=\"a&quot;bfn1\">\n",
+            "<p>There. <a href=\"#a%22bfnref1\" class=\"footnote-backref\">↩</a></p>\n",
+            "</li>\n",
+            "</ol>\n",
+            "</section>\n",
+        ),
+        |opts| {
+            opts.extension.footnotes = true;
+            opts.render.footnote_id_prefix = Some("a\"b".to_string());
+        },
+    );
+
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    options.extension.footnotes = true;
+    let root = parse_document(&arena, input, &options);
+    let mut md = vec![];
+    cm::format_document(root, &options, &mut md).unwrap();
+    compare_strs(
+        &String::from_utf8(md).unwrap(),
+        concat!("Hi[^My-Note].\n", "\n", "[^my-note]:\n", "    There.\n"),
+        "commonmark",
+    );
+}
+
 #[test]
 fn regression_back_to_back_ranges() {
     html(
@@ -753,6 +1110,17 @@ fn pathological_emphases() {
     timeout_ms(move || html(&s, &exp), 4000);
 }
 
+#[test]
+fn pathological_dollars() {
+    let s = "$a ".repeat(40000);
+    let exp = format!("<p>{}</p>\n", s.trim_end());
+
+    timeout_ms(
+        move || html_opts(&s, &exp, |opts| opts.extension.math = true),
+        4000,
+    );
+}
+
 #[test]
 fn no_panic_on_empty_bookended_atx_headers() {
     html("#  #", "<h1></h1>\n");
@@ -1007,51 +1375,1356 @@ fn description_lists() {
     );
 }
 
+fn sourcepos_opts<F>(input: &str, expected: &[&str], opts: F)
+where
+    F: Fn(&mut ComrakOptions),
+{
+    let arena = Arena::new();
+    let mut options = ComrakOptions::default();
+    opts(&mut options);
+
+    let root = parse_document(&arena, input, &options);
+    let actual = root
+        .descendants()
+        .map(|n| {
+            let ast = n.data.borrow();
+            let kind = format!("{:?}", ast.value);
+            let kind = kind
+                .split(|c| c == '(' || c == ' ')
+                .next()
+                .unwrap()
+                .to_string();
+            format!("{} {}", kind, ast.sourcepos)
+        })
+        .collect::<Vec<_>>();
+    compare_strs(&actual.join("\n"), &expected.join("\n"), "sourcepos");
+}
+
 #[test]
-fn case_insensitive_safety() {
-    html(
-        "[a](javascript:a) [b](Javascript:b) [c](jaVascript:c) [d](data:xyz) [e](Data:xyz) [f](vbscripT:f) [g](FILE:g)\n",
-        "<p><a href=\"\">a</a> <a href=\"\">b</a> <a href=\"\">c</a> <a href=\"\">d</a> <a href=\"\">e</a> <a href=\"\">f</a> <a href=\"\">g</a></p>\n",
+fn sourcepos_blocks() {
+    sourcepos_opts(
+        concat!(
+            "# Hi *there*\n",
+            "\n",
+            "> quote\n",
+            "> more\n",
+            "\n",
+            "- a\n",
+            "- b  \n",
+            "  c\n",
+            "\n",
+            "```\n",
+            "code\n",
+            "```\n",
+            "\n",
+            "Setext\n",
+            "======\n",
+        ),
+        &[
+            "Document 1:1-15:6",
+            "Heading 1:1-1:12",
+            "Text 1:3-1:5",
+            "Emph 1:6-1:12",
+            "Text 1:7-1:11",
+            "BlockQuote 3:1-4:6",
+            "Paragraph 3:3-4:6",
+            "Text 3:3-3:7",
+            "SoftBreak 3:8-3:8",
+            "Text 4:3-4:6",
+            "List 6:1-8:3",
+            "Item 6:1-6:3",
+            "Paragraph 6:3-6:3",
+            "Text 6:3-6:3",
+            "Item 7:1-8:3",
+            "Paragraph 7:3-8:3",
+            "Text 7:3-7:3",
+            "LineBreak 7:4-7:6",
+            "Text 8:3-8:3",
+            "CodeBlock 10:1-12:3",
+            "Heading 14:1-15:6",
+            "Text 14:1-14:6",
+        ],
+        |_| (),
     );
 }
 
 #[test]
-fn exercise_full_api() {
-    let arena = ::Arena::new();
-    let default_options = ::ComrakOptions::default();
-    let node = ::parse_document(&arena, "# My document\n", &default_options);
-    let mut buffer = vec![];
-
-    // Use every member of the exposed API without any defaults.
-    // Not looking for specific outputs, just want to know if the API changes shape.
-
-    let _: std::io::Result<()> = ::format_commonmark(node, &default_options, &mut buffer);
-
-    let _: std::io::Result<()> = ::format_html(node, &default_options, &mut buffer);
-
-    let _: String = ::Anchorizer::new().anchorize("header".to_string());
+#[allow(deprecated)]
+fn start_line_accessor() {
+    let arena = Arena::new();
+    let root = parse_document(&arena, "a\n\n> b\n", &ComrakOptions::default());
+    let lines = root
+        .descendants()
+        .map(|n| n.data.borrow().start_line())
+        .collect::<Vec<_>>();
+    assert_eq!(lines, vec![1, 1, 1, 3, 3, 3]);
+}
 
-    let _: &::nodes::AstNode = ::parse_document(&arena, "document", &default_options);
+#[test]
+fn sourcepos_inlines() {
+    sourcepos_opts(
+        concat!(
+            "Go to [**the** site](http://example.com) or\n",
+            "`here`, www.example.com -- ok...\n",
+        ),
+        &[
+            "Document 1:1-2:32",
+            "Paragraph 1:1-2:32",
+            "Text 1:1-1:6",
+            "Link 1:7-1:40",
+            "Strong 1:8-1:14",
+            "Text 1:10-1:12",
+            "Text 1:15-1:19",
+            "Text 1:41-1:43",
+            "SoftBreak 1:44-1:44",
+            "Code 2:1-2:6",
+            "Text 2:7-2:8",
+            "Link 2:9-2:23",
+            "Text 2:9-2:23",
+            "Text 2:24-2:32",
+        ],
+        |opts| {
+            opts.extension.autolink = true;
+            opts.parse.smart = true;
+        },
+    );
 
-    let _: &::nodes::AstNode = ::parse_document_with_broken_link_callback(
-        &arena,
-        "document",
-        &default_options,
-        Some(&mut |_: &[u8]| Some((b"abc".to_vec(), b"xyz".to_vec()))),
+    sourcepos_opts(
+        "> x [[P| a *b*]]\n",
+        &[
+            "Document 1:1-1:16",
+            "BlockQuote 1:1-1:16",
+            "Paragraph 1:3-1:16",
+            "Text 1:3-1:4",
+            "WikiLink 1:5-1:16",
+            "Text 1:10-1:11",
+            "Emph 1:12-1:14",
+            "Text 1:13-1:13",
+        ],
+        |opts| opts.extension.wikilinks = true,
     );
+}
 
-    let _ = ::ComrakOptions {
-        extension: ::ComrakExtensionOptions {
-            strikethrough: false,
-            tagfilter: false,
-            table: false,
-            autolink: false,
+#[test]
+fn sourcepos_html() {
+    let mut options = ComrakOptions::default();
+    options.extension.table = true;
+    options.render.sourcepos = true;
+    let arena = Arena::new();
+    let root = parse_document(
+        &arena,
+        concat!(
+            "Title\n",
+            "=====\n",
+            "\n",
+            "1. one\n",
+            "2. two\n",
+            "\n",
+            "---\n",
+            "\n",
+            "    code\n",
+            "\n",
+            "| a |\n",
+            "|---|\n",
+            "| b |\n",
+        ),
+        &options,
+    );
+    let mut output = vec![];
+    html::format_document(root, &options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        concat!(
+            "<h1 data-sourcepos=\"1:1-2:5\">Title</h1>\n",
+            "<ol data-sourcepos=\"4:1-5:6\">\n",
+            "<li data-sourcepos=\"4:1-4:6\">one</li>\n",
+            "<li data-sourcepos=\"5:1-5:6\">two</li>\n",
+            "</ol>\n",
+            "<hr data-sourcepos=\"7:1-7:3\" />\n",
+            "<pre data-sourcepos=\"9:1-9:8\"><code>code\n",
+            "</code></pre>\n",
+            "<table data-sourcepos=\"11:1-13:5\">\n",
+            "<thead>\n",
+            "<tr data-sourcepos=\"11:1-11:5\">\n",
+            "<th data-sourcepos=\"11:3-11:3\">a</th>\n",
+            "</tr>\n",
+            "</thead>\n",
+            "<tbody>\n",
+            "<tr data-sourcepos=\"13:1-13:5\">\n",
+            "<td data-sourcepos=\"13:3-13:3\">b</td>\n",
+            "</tr>\n",
+            "</tbody>\n",
+            "</table>\n",
+        ),
+        "regular",
+    );
+}
+
+#[test]
+fn xml_output() {
+    let mut options = ComrakOptions::default();
+    options.extension.table = true;
+    options.extension.tasklist = true;
+    let arena = Arena::new();
+    let root = parse_document(
+        &arena,
+        concat!(
+            "# Hi *there*\n",
+            "\n",
+            "1. [a](/u \"t\") & `c`\n",
+            "\n",
+            "| a |\n",
+            "|:-|\n",
+            "| - [ ] b |\n",
+            "\n",
+            "- [x] done\n",
+        ),
+        &options,
+    );
+    let mut output = vec![];
+    xml::format_document(root, &options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n",
+            "<document xmlns=\"http://commonmark.org/xml/1.0\">\n",
+            "  <heading level=\"1\">\n",
+            "    <text xml:space=\"preserve\">Hi </text>\n",
+            "    <emph>\n",
+            "      <text xml:space=\"preserve\">there</text>\n",
+            "    </emph>\n",
+            "  </heading>\n",
+            "  <list type=\"ordered\" start=\"1\" delim=\"period\" tight=\"true\">\n",
+            "    <item>\n",
+            "      <paragraph>\n",
+            "        <link destination=\"/u\" title=\"t\">\n",
+            "          <text xml:space=\"preserve\">a</text>\n",
+            "        </link>\n",
+            "        <text xml:space=\"preserve\"> &amp; </text>\n",
+            "        <code xml:space=\"preserve\">c</code>\n",
+            "      </paragraph>\n",
+            "    </item>\n",
+            "  </list>\n",
+            "  <table>\n",
+            "    <table_header>\n",
+            "      <table_cell align=\"left\">\n",
+            "        <text xml:space=\"preserve\">a</text>\n",
+            "      </table_cell>\n",
+            "    </table_header>\n",
+            "    <table_row>\n",
+            "      <table_cell align=\"left\">\n",
+            "        <text xml:space=\"preserve\">- [ ] b</text>\n",
+            "      </table_cell>\n",
+            "    </table_row>\n",
+            "  </table>\n",
+            "  <list type=\"bullet\" tight=\"true\">\n",
+            "    <item>\n",
+            "      <paragraph>\n",
+            "        <taskitem completed=\"true\" />\n",
+            "        <text xml:space=\"preserve\">done</text>\n",
+            "      </paragraph>\n",
+            "    </item>\n",
+            "  </list>\n",
+            "</document>\n",
+        ),
+        "xml",
+    );
+}
+
+#[test]
+fn syntax_highlighter_plugin() {
+    struct MockAdapter;
+
+    impl SyntaxHighlighterAdapter for MockAdapter {
+        fn write_highlighted(
+            &self,
+            output: &mut dyn Write,
+            lang: Option<&str>,
+            code: &str,
+        ) -> io::Result<()> {
+            write!(output, "<!-- {:?} -->", lang)?;
+            html::escape(output, code.to_uppercase().as_bytes())
+        }
+
+        fn write_pre_tag(
+            &self,
+            output: &mut dyn Write,
+            attributes: HashMap<String, String>,
+        ) -> io::Result<()> {
+            html::write_opening_tag(output, "pre", &attributes)
+        }
+
+        fn write_code_tag(
+            &self,
+            output: &mut dyn Write,
+            mut attributes: HashMap<String, String>,
+        ) -> io::Result<()> {
+            attributes.insert("data-mock".to_string(), "<yes>".to_string());
+            html::write_opening_tag(output, "code", &attributes)
+        }
+    }
+
+    let adapter = MockAdapter;
+    let mut plugins = ComrakPlugins::default();
+    plugins.render.codefence_syntax_highlighter = Some(&adapter);
+
+    let mut options = ComrakOptions::default();
+    let input = "``` rust yum\nfn main<'a>();\n```\n\n    indented\n";
+    assert_eq!(
+        ::markdown_to_html_with_plugins(input, &options, &plugins),
+        concat!(
+            "<pre><code class=\"language-rust\" data-mock=\"&lt;yes&gt;\">",
+            "<!-- Some(\"rust\") -->FN MAIN&lt;'A&gt;();\n</code></pre>\n",
+            "<pre><code data-mock=\"&lt;yes&gt;\"><!-- None -->INDENTED\n</code></pre>\n",
+        ),
+    );
+
+    options.render.github_pre_lang = true;
+    options.render.sourcepos = true;
+    assert_eq!(
+        ::markdown_to_html_with_plugins(input, &options, &plugins),
+        concat!(
+            "<pre data-sourcepos=\"1:1-3:3\" lang=\"rust\"><code data-mock=\"&lt;yes&gt;\">",
+            "<!-- Some(\"rust\") -->FN MAIN&lt;'A&gt;();\n</code></pre>\n",
+            "<pre data-sourcepos=\"5:1-5:12\"><code data-mock=\"&lt;yes&gt;\">",
+            "<!-- None -->INDENTED\n</code></pre>\n",
+        ),
+    );
+}
+
+#[test]
+fn node_renderer_plugin() {
+    struct PermalinkHeadings;
+
+    impl NodeRendererAdapter for PermalinkHeadings {
+        fn enter<'a>(
+            &self,
+            output: &mut dyn Write,
+            node: &'a AstNode<'a>,
+            _options: &ComrakOptions,
+        ) -> io::Result<bool> {
+            match node.data.borrow().value {
+                NodeValue::Heading(ref nh) => {
+                    write!(
+                        output,
+                        "<h{}><a class=\"permalink\" href=\"#\">🔗</a>",
+                        nh.level
+                    )?;
+                    Ok(false)
+                }
+                NodeValue::Link(ref nl) => {
+                    output.write_all(b"<a rel=\"nofollow\" href=\"")?;
+                    html::escape_href(output, &nl.url)?;
+                    output.write_all(b"\">")?;
+                    Ok(false)
+                }
+                _ => Ok(true),
+            }
+        }
+
+        fn exit<'a>(
+            &self,
+            _output: &mut dyn Write,
+            _node: &'a AstNode<'a>,
+            _options: &ComrakOptions,
+        ) -> io::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    let renderer = PermalinkHeadings;
+    let mut plugins = ComrakPlugins::default();
+    plugins.render.node_renderer = Some(&renderer);
+
+    assert_eq!(
+        ::markdown_to_html_with_plugins(
+            "## Hi *there*\n\nSee [the docs](<a b>).\n",
+            &ComrakOptions::default(),
+            &plugins
+        ),
+        concat!(
+            "<h2><a class=\"permalink\" href=\"#\">🔗</a>Hi <em>there</em></h2>\n",
+            "<p>See <a rel=\"nofollow\" href=\"a%20b\">the docs</a>.</p>\n",
+        ),
+    );
+}
+
+#[test]
+fn wikilinks() {
+    html_opts!(
+        [extension.wikilinks],
+        concat!(
+            "[[Home]], [[Other page | the *other* page]] and [[ |nope]].\n",
+            "[[not\nthis]] [[javascript:alert(1)]]\n",
+        ),
+        concat!(
+            "<p><a href=\"Home\" data-wikilink=\"true\">Home</a>, ",
+            "<a href=\"Other%20page\" data-wikilink=\"true\">the <em>other</em> page</a> ",
+            "and [[ |nope]].\n",
+            "[[not\nthis]] <a href=\"\" data-wikilink=\"true\">javascript:alert(1)</a></p>\n",
+        ),
+    );
+
+    html_opts!(
+        [extension.wikilinks, extension.wikilinks_title_before_pipe],
+        "[[the title|Page]] [[Page]]\n",
+        concat!(
+            "<p><a href=\"Page\" data-wikilink=\"true\">the title</a> ",
+            "<a href=\"Page\" data-wikilink=\"true\">Page</a></p>\n",
+        ),
+    );
+
+    html_opts!(
+        [extension.wikilinks, extension.wikilinks_title_before_pipe],
+        "[[a \\| *b* \\]\\] c|Page]]\n",
+        "<p><a href=\"Page\" data-wikilink=\"true\">a | <em>b</em> ]] c</a></p>\n",
+    );
+
+    html("[[Home]]\n", "<p>[[Home]]</p>\n");
+}
+
+#[test]
+fn wikilinks_commonmark() {
+    let mut options = ComrakOptions::default();
+    options.extension.wikilinks = true;
+    options.extension.wikilinks_title_before_pipe = true;
+
+    // A title set on the AST, as from the JSON loader, is escaped so that it reparses the same.
+    let arena = Arena::new();
+    let root = parse_document(&arena, "[[Page]]\n", &options);
+    let wikilink = root.first_child().unwrap().first_child().unwrap();
+    wikilink.first_child().unwrap().data.borrow_mut().value =
+        ::nodes::NodeValue::Text(b"x|y]]z".to_vec());
+
+    let mut md = vec![];
+    cm::format_document(root, &options, &mut md).unwrap();
+    assert_eq!(
+        String::from_utf8(md.clone()).unwrap(),
+        "[[x\\|y\\]\\]z|Page]]\n"
+    );
+
+    let root = parse_document(&arena, &String::from_utf8(md).unwrap(), &options);
+    let mut out = vec![];
+    html::format_document(root, &options, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<p><a href=\"Page\" data-wikilink=\"true\">x|y]]z</a></p>\n"
+    );
+}
+
+#[test]
+fn math() {
+    html_opts!(
+        [extension.math],
+        concat!(
+            "$a_1 < b$ and $$\\sum_{i=1}^n i$$ but not `$code$`.\n",
+            "$\\$$, $*a*$ and \\$x$.\n",
+        ),
+        concat!(
+            "<p><span data-math-style=\"inline\">a_1 &lt; b</span> and ",
+            "<span data-math-style=\"display\">\\sum_{i=1}^n i</span> but not <code>$code$</code>.\n",
+            "<span data-math-style=\"inline\">\\$</span>, ",
+            "<span data-math-style=\"inline\">*a*</span> and $x$.</p>\n",
+        ),
+    );
+
+    html_opts!(
+        [extension.math],
+        "$ x$, $x $ and $x$1 aren't math, nor is $5.\n",
+        "<p>$ x$, $x $ and $x$1 aren't math, nor is $5.</p>\n",
+    );
+
+    html_opts!(
+        [extension.math],
+        "```math\n\\frac{1}{2}\n```\n",
+        "<pre><code class=\"language-math\" data-math-style=\"display\">\\frac{1}{2}\n</code></pre>\n",
+    );
+
+    html("$x$\n", "<p>$x$</p>\n");
+}
+
+#[test]
+fn math_code_fence() {
+    let mut options = ComrakOptions::default();
+    options.extension.math = true;
+    let arena = Arena::new();
+    let root = parse_document(&arena, "- ```math\n  x < y\n  ```\n", &options);
+    let math = root
+        .first_child()
+        .unwrap()
+        .first_child()
+        .unwrap()
+        .first_child()
+        .unwrap();
+    match math.data.borrow().value {
+        ::nodes::NodeValue::Math(ref nm) => {
+            assert!(nm.display && nm.fenced);
+            assert_eq!(nm.literal, b"x < y\n");
+        }
+        ref other => panic!("expected math, got {:?}", other),
+    }
+
+    let mut output = vec![];
+    xml::format_document(math, &options, &mut output).unwrap();
+    compare_strs(
+        &String::from_utf8(output).unwrap(),
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n",
+            "<math display=\"true\" fenced=\"true\" xml:space=\"preserve\">x &lt; y\n</math>\n",
+        ),
+        "xml",
+    );
+
+    html_opts!(
+        [extension.math],
+        "- ```math\n  x < y\n  ```\n",
+        "<ul>\n<li>\n<pre><code class=\"language-math\" data-math-style=\"display\">x &lt; y\n</code></pre>\n</li>\n</ul>\n",
+    );
+    html(
+        "```math\nx\n```\n",
+        "<pre><code class=\"language-math\">x\n</code></pre>\n",
+    );
+}
+
+#[test]
+fn alerts() {
+    html_opts!(
+        [extension.alerts],
+        concat!(
+            "> [!WARNING]\n",
+            "> Don't *panic*.\n",
+            ">\n",
+            "> - Really.\n",
+            "\n",
+            "> [!caution] Mind the <gap>\n",
+            "\n",
+            "> [!NOPE]\n",
+            "> Just a quote.\n",
+        ),
+        concat!(
+            "<div class=\"markdown-alert markdown-alert-warning\">\n",
+            "<p class=\"markdown-alert-title\">Warning</p>\n",
+            "<p>Don't <em>panic</em>.</p>\n",
+            "<ul>\n",
+            "<li>Really.</li>\n",
+            "</ul>\n",
+            "</div>\n",
+            "<div class=\"markdown-alert markdown-alert-caution\">\n",
+            "<p class=\"markdown-alert-title\">Mind the &lt;gap&gt;</p>\n",
+            "</div>\n",
+            "<blockquote>\n",
+            "<p>[!NOPE]\n",
+            "Just a quote.</p>\n",
+            "</blockquote>\n",
+        ),
+    );
+
+    html(
+        "> [!NOTE]\n> Hi.\n",
+        "<blockquote>\n<p>[!NOTE]\nHi.</p>\n</blockquote>\n",
+    );
+}
+
+#[test]
+fn alerts_sourcepos() {
+    sourcepos_opts(
+        "> [!TIP]\n>   Some *tip*.\n",
+        &[
+            "Document 1:1-2:15",
+            "Alert 1:1-2:15",
+            "Paragraph 2:5-2:15",
+            "Text 2:5-2:9",
+            "Emph 2:10-2:14",
+            "Text 2:11-2:13",
+            "Text 2:15-2:15",
+        ],
+        |opts| opts.extension.alerts = true,
+    );
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn json_round_trip() {
+    use json;
+
+    let mut options = ComrakOptions::default();
+    options.render.sourcepos = true;
+    options.extension.table = true;
+    options.extension.alerts = true;
+    options.extension.math = true;
+
+    let input = concat!(
+        "# Title\n",
+        "\n",
+        "> [!NOTE]\n",
+        "> Some *emphasis* and `code` and $x^2$.\n",
+        "\n",
+        "1. [a link](/url \"title\")\n",
+        "2. ![image](/img.png)\n",
+        "\n",
+        "| a | b |\n",
+        "|:--|--:|\n",
+        "| 1 | 2 |\n",
+        "\n",
+        "```rust\n",
+        "fn main() {}\n",
+        "```\n",
+    );
+
+    let arena = Arena::new();
+    let root = parse_document(&arena, input, &options);
+    let mut out = vec![];
+    json::format_document(root, &options, &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("\"Text\":\"emphasis\""));
+    assert!(out.contains("\"url\":\"/url\",\"title\":\"title\""));
+
+    let loaded = json::parse_document(&arena, &out).unwrap();
+    let (mut expected, mut actual) = (vec![], vec![]);
+    html::format_document(root, &options, &mut expected).unwrap();
+    html::format_document(loaded, &options, &mut actual).unwrap();
+    compare_strs(
+        &String::from_utf8(actual).unwrap(),
+        &String::from_utf8(expected).unwrap(),
+        "json",
+    );
+
+    let options_json = serde_json::to_string(&options).unwrap();
+    let options: ComrakOptions = serde_json::from_str(&options_json).unwrap();
+    assert!(options.extension.table);
+    assert!(options.render.sourcepos);
+
+    assert!(json::parse_document(&arena, "{\"value\":\"Nonsense\"}").is_err());
+}
+
+#[test]
+#[cfg(feature = "front-matter")]
+fn front_matter_parsing() {
+    use front_matter::{parse_document_with_front_matter, FrontMatterFormat, FrontMatterValue};
+
+    let arena = Arena::new();
+    let options = ComrakOptions::default();
+
+    let (root, result) = parse_document_with_front_matter(
+        &arena,
+        "---\ntitle: Hello\ntags: [a, b]\ndraft: false\n---\n\n# Hi\n",
+        &options,
+    );
+    let fm = result.unwrap().unwrap();
+    assert_eq!(fm.format, FrontMatterFormat::Yaml);
+    assert_eq!(
+        fm.value.get("title").and_then(|v| v.as_str()),
+        Some("Hello")
+    );
+    assert_eq!(
+        fm.value.get("tags"),
+        Some(&FrontMatterValue::Array(vec![
+            FrontMatterValue::String("a".into()),
+            FrontMatterValue::String("b".into()),
+        ]))
+    );
+    assert_eq!(fm.value.get("draft"), Some(&FrontMatterValue::Bool(false)));
+    let mut out = vec![];
+    html::format_document(root, &options, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "<h1>Hi</h1>\n");
+
+    let (_, result) = parse_document_with_front_matter(
+        &arena,
+        "+++\nweight = 3\n\n[extra]\nx = 1.5\n+++\nText\n",
+        &options,
+    );
+    let fm = result.unwrap().unwrap();
+    assert_eq!(fm.format, FrontMatterFormat::Toml);
+    assert_eq!(fm.value.get("weight"), Some(&FrontMatterValue::Integer(3)));
+    assert_eq!(
+        fm.value.get("extra").and_then(|v| v.get("x")),
+        Some(&FrontMatterValue::Float(1.5))
+    );
+
+    let (_, result) = parse_document_with_front_matter(&arena, "Text\n", &options);
+    assert!(result.unwrap().is_none());
+
+    // Without an explicit delimiter, anything but valid front matter is ordinary Markdown.
+    for &input in &[
+        "---\n\nIntro *text*\n\n---\n\nMore\n",
+        "---\n---\nText\n",
+        "---\nText\n",
+        "+++\na = 1\nb = \n+++\nText\n",
+    ] {
+        let (root, result) = parse_document_with_front_matter(&arena, input, &options);
+        assert!(result.unwrap().is_none());
+        let mut out = vec![];
+        html::format_document(root, &options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            ::markdown_to_html(input, &options)
+        );
+    }
+
+    let mut options = ComrakOptions::default();
+    options.extension.front_matter_delimiter = Some("+++".to_string());
+    let (_, result) =
+        parse_document_with_front_matter(&arena, "+++\na = 1\nb = \n+++\nText\n", &options);
+    let err = result.unwrap_err();
+    assert_eq!(err.format, FrontMatterFormat::Toml);
+    assert_eq!(err.line, Some(3));
+
+    options.extension.front_matter_delimiter = Some("---".to_string());
+    let (root, result) = parse_document_with_front_matter(&arena, "---\nText\n", &options);
+    assert_eq!(result.unwrap_err().line, Some(1));
+    let mut out = vec![];
+    html::format_document(root, &options, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "<hr />\n<p>Text</p>\n");
+}
+
+#[test]
+fn explicit_header_ids_are_unique() {
+    html_opts(
+        concat!(
+            "[[_TOC_]]\n",
+            "\n",
+            "# Foo\n",
+            "\n",
+            "# Bar {#foo}\n",
+            "\n",
+            "# Baz {#foo}\n",
+        ),
+        concat!(
+            "<nav class=\"table-of-contents\">\n",
+            "<ul>\n",
+            "<li><a href=\"#foo\">Foo</a></li>\n",
+            "<li><a href=\"#foo-1\">Bar</a></li>\n",
+            "<li><a href=\"#foo-2\">Baz</a></li>\n",
+            "</ul>\n",
+            "</nav>\n",
+            "<h1><a href=\"#foo\" aria-hidden=\"true\" class=\"anchor\" id=\"foo\"></a>Foo</h1>\n",
+            "<h1><a href=\"#foo-1\" aria-hidden=\"true\" class=\"anchor\" id=\"foo-1\"></a>Bar</h1>\n",
+            "<h1><a href=\"#foo-2\" aria-hidden=\"true\" class=\"anchor\" id=\"foo-2\"></a>Baz</h1>\n",
+        ),
+        |opts| {
+            opts.extension.attributes = true;
+            opts.extension.table_of_contents = true;
+            opts.extension.header_ids = Some("".to_string());
+        },
+    );
+}
+
+#[test]
+fn table_of_contents() {
+    html_opts(
+        concat!(
+            "[[_TOC_]]\n",
+            "\n",
+            "# Intro\n",
+            "## Getting `started`\n",
+            "#### Deep\n",
+            "## Usage & more\n",
+            "# Intro\n",
+        ),
+        concat!(
+            "<nav class=\"table-of-contents\">\n",
+            "<ul>\n",
+            "<li><a href=\"#user-content-intro\">Intro</a>\n",
+            "<ul>\n",
+            "<li><a href=\"#user-content-getting-started\">Getting started</a>\n",
+            "<ul>\n",
+            "<li><a href=\"#user-content-deep\">Deep</a></li>\n",
+            "</ul>\n",
+            "</li>\n",
+            "<li><a href=\"#user-content-usage--more\">Usage &amp; more</a></li>\n",
+            "</ul>\n",
+            "</li>\n",
+            "<li><a href=\"#user-content-intro-1\">Intro</a></li>\n",
+            "</ul>\n",
+            "</nav>\n",
+            "<h1><a href=\"#intro\" aria-hidden=\"true\" class=\"anchor\" id=\"user-content-intro\"></a>Intro</h1>\n",
+            "<h2><a href=\"#getting-started\" aria-hidden=\"true\" class=\"anchor\" id=\"user-content-getting-started\"></a>Getting <code>started</code></h2>\n",
+            "<h4><a href=\"#deep\" aria-hidden=\"true\" class=\"anchor\" id=\"user-content-deep\"></a>Deep</h4>\n",
+            "<h2><a href=\"#usage--more\" aria-hidden=\"true\" class=\"anchor\" id=\"user-content-usage--more\"></a>Usage &amp; more</h2>\n",
+            "<h1><a href=\"#intro-1\" aria-hidden=\"true\" class=\"anchor\" id=\"user-content-intro-1\"></a>Intro</h1>\n",
+        ),
+        |opts| {
+            opts.extension.table_of_contents = true;
+            opts.extension.header_ids = Some("user-content-".to_string());
+        },
+    );
+
+    html_opts!(
+        [extension.table_of_contents],
+        "Not [TOC] here.\n\n[TOC]\n",
+        "<p>Not [TOC] here.</p>\n<nav class=\"table-of-contents\">\n</nav>\n",
+    );
+
+    html("[TOC]\n", "<p>[TOC]</p>\n");
+}
+
+#[test]
+fn event_stream() {
+    use event::{self, Event};
+
+    let options = ComrakOptions::default();
+    let input = "# Hi\n\n- a `b`\n- ***\n\n> c\n";
+    let arena = Arena::new();
+    let kinds = ::events(&arena, input, &options)
+        .map(|event| match event {
+            Event::Start(value) => format!("+{}", value.xml_node_name()),
+            Event::End => "-".to_string(),
+            Event::Text(text) => String::from_utf8(text).unwrap(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds.join("|"),
+        concat!(
+            "+document|+heading|Hi|-|+list|+item|+paragraph|a |+code|-|-|-|",
+            "+item|+thematic_break|-|-|-|+block_quote|+paragraph|c|-|-|-",
+        )
+    );
+
+    let arena = Arena::new();
+    let mut out = vec![];
+    event::format_html(::events(&arena, input, &options), &options, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        ::markdown_to_html(input, &options)
+    );
+
+    let arena = Arena::new();
+    let mut out = vec![];
+    event::format_commonmark(::events(&arena, input, &options), &options, &mut out).unwrap();
+    let mut expected = vec![];
+    cm::format_document(
+        parse_document(&arena, input, &options),
+        &options,
+        &mut expected,
+    )
+    .unwrap();
+    assert_eq!(out, expected);
+
+    let arena = Arena::new();
+    let root = event::build_tree(
+        &arena,
+        vec![
+            Event::Start(NodeValue::Paragraph),
+            Event::Text(b"orphan".to_vec()),
+            Event::End,
+            Event::End,
+        ],
+    );
+    let mut out = vec![];
+    html::format_document(root, &options, &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "<p>orphan</p>\n");
+}
+
+#[test]
+fn event_stream_per_block() {
+    use event::{self, Event};
+
+    // A block's events are produced before the lines after it are parsed.
+    let options = ComrakOptions::default();
+    let input = "Para *x*.\n\n".repeat(100);
+    let arena = Arena::new();
+    let mut events = ::events(&arena, &input, &options);
+    let first = events
+        .by_ref()
+        .take(8)
+        .map(|event| match event {
+            Event::Start(value) => format!("+{}", value.xml_node_name()),
+            Event::End => "-".to_string(),
+            Event::Text(text) => String::from_utf8(text).unwrap(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(first.join("|"), "+document|+paragraph|Para |+emph|x|-|.|-");
+    assert!(arena.len() < 20, "{} nodes allocated", arena.len());
+    assert_eq!(
+        events.filter(|event| matches!(event, Event::End)).count(),
+        99 * 2 + 1
+    );
+
+    // Links can only use the reference definitions before them.
+    let input = "[a]\n\n[a]: /u\n\n[a]\n";
+    let mut out = vec![];
+    event::format_html(::events(&arena, input, &options), &options, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<p>[a]</p>\n<p><a href=\"/u\">a</a></p>\n"
+    );
+
+    // Footnotes are numbered across the whole document, which is parsed before any events are
+    // produced.
+    let mut options = ComrakOptions::default();
+    options.extension.footnotes = true;
+    let input = "- a[^2]\n\n[^1]: one\n\n```\nb[^1]\n```\n\n[^2]: two\n\nc[^1]\n";
+    let mut out = vec![];
+    event::format_html(::events(&arena, input, &options), &options, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        ::markdown_to_html(input, &options)
+    );
+    let mut out = vec![];
+    event::format_commonmark(::events(&arena, input, &options), &options, &mut out).unwrap();
+    let mut expected = vec![];
+    cm::format_document(
+        parse_document(&arena, input, &options),
+        &options,
+        &mut expected,
+    )
+    .unwrap();
+    compare_strs(
+        &String::from_utf8(out).unwrap(),
+        &String::from_utf8(expected).unwrap(),
+        "commonmark",
+    );
+}
+
+#[test]
+fn incremental_reparse() {
+    use incremental::IncrementalDocument;
+
+    let mut options = ComrakOptions::default();
+    options.render.sourcepos = true;
+    options.extension.table = true;
+
+    let xml_of = |root| {
+        let mut out = vec![];
+        xml::format_document(root, &options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    let arena = Arena::new();
+    let mut doc = IncrementalDocument::new(
+        &arena,
+        concat!(
+            "# Title\n",
+            "\n",
+            "Intro.\n",
+            "\n",
+            "Para one.\n",
+            "\n",
+            "- a\n",
+            "- b\n",
+            "\n",
+            "| x | y |\n",
+            "|---|---|\n",
+            "| 1 | 2 |\n",
+            "\n",
+            "[link][ref] end.\n",
+            "\n",
+            "[ref]: /url\n",
+        ),
+        &options,
+    );
+    // Each edit is followed by whether the title should survive it untouched.
+    let edits: &[(&str, &str, bool)] = &[
+        // Merge two blocks by removing the blank line between them.
+        ("Para one.\n\n", "Para one.\n", true),
+        // Continue the list across a blank line.
+        ("- b\n", "- b\n\n  more b\n", true),
+        // Open a fence which swallows the rest of the document, including the reference
+        // definition, then close it.
+        ("| x |", "```\n| x |", false),
+        (" end.\n", " end.\n```\n", false),
+        // Turn a paragraph into a setext heading.
+        ("Para one.\n", "Para one.\n===\n", true),
+        // Redefine the reference, which re-parses everything.
+        ("/url", "/other", false),
+        // Delete the title.
+        ("# Title\n", "", false),
+    ];
+
+    for &(from, to, keeps_title) in edits {
+        let title = doc.root().first_child().unwrap();
+        let start = doc.text().find(from).unwrap();
+        let changed = doc.edit(start..start + from.len(), to);
+        assert!(!changed.is_empty());
+
+        let full = parse_document(&arena, doc.text(), &options);
+        compare_strs(&xml_of(doc.root()), &xml_of(full), "incremental");
+        assert_eq!(
+            doc.root().first_child().unwrap().same_node(title),
+            keeps_title,
+            "{}",
+            from
+        );
+    }
+}
+
+#[test]
+fn incremental_arena_growth() {
+    use incremental::IncrementalDocument;
+
+    let options = ComrakOptions::default();
+    let arena = Arena::new();
+    let text = "One.\n\nTwo.\n\nThree.\n\nFour.\n";
+    let mut doc = IncrementalDocument::new(&arena, text, &options);
+
+    // Only the re-parsed blocks, and the document node they're parsed under, are allocated in
+    // the document's arena.
+    for _ in 0..3 {
+        let before = arena.len();
+        let start = doc.text().find("Two").unwrap();
+        let changed = doc.edit(start..start + 3, "2");
+        let allocated = changed
+            .iter()
+            .map(|n| n.descendants().count())
+            .sum::<usize>();
+        assert_eq!(arena.len() - before, allocated + 1);
+
+        let start = doc.text().find('2').unwrap();
+        doc.edit(start..start + 1, "Two");
+    }
+
+    // Footnote numbering depends on the whole document, so every edit re-parses all of it.
+    let mut options = ComrakOptions::default();
+    options.extension.footnotes = true;
+    let mut doc = IncrementalDocument::new(&arena, "A[^1].\n\nB.\n\n[^1]: C.\n", &options);
+    let start = doc.text().find('B').unwrap();
+    assert_eq!(doc.edit(start..start + 1, "b").len(), 3);
+}
+
+#[test]
+fn url_policy() {
+    html_opts(
+        concat!(
+            "[a](JavaScript:x) [b](mailto:b@example.com \"t\") [c](/rel) [d](#frag)\n",
+            "![*alt* text](data:image/png;base64,x) www.example.com app://y [[Page]]\n",
+        ),
+        concat!(
+            "<p>a <a href=\"mailto:b@example.com\" title=\"t\">b</a> <a href=\"/rel\">c</a> ",
+            "<a href=\"#frag\">d</a>\n",
+            "alt text <a href=\"http://www.example.com\">www.example.com</a> app://y ",
+            "<a href=\"Page\" data-wikilink=\"true\">Page</a></p>\n",
+        ),
+        |opts| {
+            opts.extension.autolink = true;
+            opts.extension.wikilinks = true;
+            let mut policy = UrlPolicy::allow(&["HTTP", "https", "mailto"]);
+            policy.rejected = RejectedUrl::TextOnly;
+            opts.render.url_policy = Some(policy);
+        },
+    );
+
+    html_opts(
+        "[a](vbscript:x) [b](javascript:x)\n",
+        "<p><a href=\"vbscript:x\">a</a> <a href=\"\">b</a></p>\n",
+        |opts| {
+            opts.render.unsafe_ = true;
+            opts.render.url_policy = Some(UrlPolicy::deny(&["javascript"]));
+        },
+    );
+
+    html_opts(
+        "[a](javascript:alert(1)) [b](vbscript:x) ![c](data:text/html,x) [d](ftp://y)\n",
+        "<p><a href=\"\">a</a> <a href=\"\">b</a> <img src=\"\" alt=\"c\" /> <a href=\"\">d</a></p>\n",
+        |opts| {
+            opts.render.url_policy = Some(UrlPolicy::deny(&["ftp"]));
+        },
+    );
+}
+
+#[test]
+fn url_rewriter() {
+    use adapters::UrlKind;
+    use plugins::base_url::BaseUrlResolver;
+
+    let mut options = ComrakOptions::default();
+    options.extension.autolink = true;
+    options.extension.wikilinks = true;
+
+    let rewriter = |url: &[u8], kind: UrlKind| {
+        let mut rewritten = format!("{:?}:", kind).into_bytes();
+        rewritten.extend_from_slice(url);
+        rewritten
+    };
+    let mut plugins = ComrakPlugins::default();
+    plugins.render.url_rewriter = Some(&rewriter);
+
+    let arena = Arena::new();
+    let root = parse_document(
+        &arena,
+        "[a](x) ![b](y) <https://z> www.example.com [[Page]]\n\n[www.x.com](http://www.x.com) [https://q](https://q)\n",
+        &options,
+    );
+    let mut out = vec![];
+    html::format_document_with_plugins(root, &options, &mut out, &plugins).unwrap();
+    compare_strs(
+        &String::from_utf8(out).unwrap(),
+        concat!(
+            "<p><a href=\"Link:x\">a</a> <img src=\"Image:y\" alt=\"b\" /> ",
+            "<a href=\"Autolink:https://z\">https://z</a> ",
+            "<a href=\"Autolink:http://www.example.com\">www.example.com</a> ",
+            "<a href=\"WikiLink:Page\" data-wikilink=\"true\">Page</a></p>\n",
+            "<p><a href=\"Link:http://www.x.com\">www.x.com</a> ",
+            "<a href=\"Link:https://q\">https://q</a></p>\n",
+        ),
+        "rewriter",
+    );
+
+    let resolver = BaseUrlResolver::new("/help");
+    assert_eq!(resolver.resolve(b"a/../b.md?x#y"), b"/b.md?x#y");
+    assert_eq!(resolver.resolve(b"?q"), b"/help?q");
+    assert_eq!(resolver.resolve(b"../../.."), b"/");
+    assert_eq!(resolver.resolve(b"//host/x"), b"//host/x");
+    assert_eq!(resolver.resolve(b"mailto:a@b.c"), b"mailto:a@b.c");
+
+    let resolver = BaseUrlResolver::new("http://example.com");
+    assert_eq!(resolver.resolve(b"a/./b"), b"http://example.com/a/b");
+}
+
+#[test]
+fn external_links() {
+    html_opts(
+        concat!(
+            "<https://Example.COM:8080/x> <http://user@evil.example.org/> [a](//cdn.net/a \"t\")\n",
+            "[b](mailto:x@y.z) [c](javascript:x) [d](ftp://files.net/) [e](#top)\n",
+        ),
+        concat!(
+            "<p><a href=\"https://Example.COM:8080/x\">https://Example.COM:8080/x</a> ",
+            "<a href=\"http://user@evil.example.org/\" rel=\"nofollow\" class=\"ext &amp; out\">http://user@evil.example.org/</a> ",
+            "<a href=\"//cdn.net/a\" title=\"t\" rel=\"nofollow\" class=\"ext &amp; out\">a</a>\n",
+            "<a href=\"mailto:x@y.z\">b</a> <a href=\"\">c</a> <a href=\"ftp://files.net/\">d</a> ",
+            "<a href=\"#top\">e</a></p>\n",
+        ),
+        |opts| {
+            opts.render.external_links = Some(ExternalLinks {
+                internal_hosts: vec!["example.com".into(), "evil".into()],
+                rel: Some("nofollow".into()),
+                target: None,
+                class: Some("ext & out".into()),
+            })
+        },
+    );
+}
+
+#[test]
+fn attributes() {
+    html_opts(
+        concat!(
+            "## Setup {#setup .wide}\n",
+            "\n",
+            "Usage {lang=en}\n",
+            "---\n",
+            "\n",
+            "``` rust {#main .numbered startFrom=\"10\"}\n",
+            "fn main() {}\n",
+            "```\n",
+            "\n",
+            "[a](/a \"t\"){.btn target=_blank} ![b](b.png){width=400} [c][]{#c} [d]{.no}\n",
+            "\n",
+            "[c]: /c\n",
+        ),
+        concat!(
+            "<h2 id=\"setup\" class=\"wide\">Setup</h2>\n",
+            "<h2 lang=\"en\">Usage</h2>\n",
+            "<pre id=\"main\" class=\"numbered\" startFrom=\"10\"><code class=\"language-rust\">fn main() {}\n",
+            "</code></pre>\n",
+            "<p><a href=\"/a\" title=\"t\" class=\"btn\" target=\"_blank\">a</a> ",
+            "<img src=\"b.png\" alt=\"b\" width=\"400\" /> <a href=\"/c\" id=\"c\">c</a> [d]{.no}</p>\n",
+        ),
+        |opts| opts.extension.attributes = true,
+    );
+
+    html_opts(
+        "# Intro {#start}\n\n# Start\n\n[x](/x){onclick=\"evil()\" href=/y style=\"color: red\" title=u}\n",
+        concat!(
+            "<h1><a href=\"#start\" aria-hidden=\"true\" class=\"anchor\" id=\"start\"></a>Intro</h1>\n",
+            "<h1><a href=\"#start-1\" aria-hidden=\"true\" class=\"anchor\" id=\"start-1\"></a>Start</h1>\n",
+            "<p><a href=\"/x\">x</a></p>\n",
+        ),
+        |opts| {
+            opts.extension.attributes = true;
+            opts.extension.header_ids = Some("".to_string());
+        },
+    );
+
+    html_opts(
+        "## Setup \\{#setup}\n\n[a](b)\\{.c}\n",
+        "<h2>Setup {#setup}</h2>\n<p><a href=\"b\">a</a>{.c}</p>\n",
+        |opts| opts.extension.attributes = true,
+    );
+
+    html(
+        "## Setup {#setup .wide}\n",
+        "<h2>Setup {#setup .wide}</h2>\n",
+    );
+}
+
+#[test]
+fn custom_containers() {
+    html_opts(
+        concat!(
+            ":::: tabs\n",
+            "::: tab One\n",
+            "Hello *there*\n",
+            ":::\n",
+            "::: tab Two\n",
+            "```\n",
+            ":::\n",
+            "```\n",
+            ":::\n",
+            "::::\n",
+            "\n",
+            "> ::: quoted\n",
+            "> text\n",
+            "\n",
+            "::: unclosed <x>\n",
+            "- item\n",
+            "  ::: nested\n",
+            "  text\n",
+            "  :::\n",
+        ),
+        concat!(
+            "<div class=\"tabs\">\n",
+            "<div class=\"tab\">\n",
+            "<p>Hello <em>there</em></p>\n",
+            "</div>\n",
+            "<div class=\"tab\">\n",
+            "<pre><code>:::\n",
+            "</code></pre>\n",
+            "</div>\n",
+            "</div>\n",
+            "<blockquote>\n",
+            "<div class=\"quoted\">\n",
+            "<p>text</p>\n",
+            "</div>\n",
+            "</blockquote>\n",
+            "<div class=\"unclosed\">\n",
+            "<ul>\n",
+            "<li>item\n",
+            "<div class=\"nested\">\n",
+            "<p>text</p>\n",
+            "</div>\n",
+            "</li>\n",
+            "</ul>\n",
+            "</div>\n",
+        ),
+        |opts| opts.extension.custom_containers = true,
+    );
+
+    html(":::\n::: note\n", "<p>:::\n::: note</p>\n");
+}
+
+#[test]
+fn custom_containers_sourcepos() {
+    sourcepos_opts(
+        "::: note Title\nText\n:::\n\n::: open\n",
+        &[
+            "Document 1:1-5:8",
+            "Container 1:1-3:3",
+            "Paragraph 2:1-2:4",
+            "Text 2:1-2:4",
+            "Container 5:1-5:8",
+        ],
+        |opts| opts.extension.custom_containers = true,
+    );
+}
+
+#[test]
+#[cfg(feature = "shortcodes")]
+fn shortcodes() {
+    html_opts(
+        "Shipped :tada: :+1: :acme: :nope: 10:30:45\n\n![a :smile:](x.png)\n",
+        concat!(
+            "<p>Shipped 🎉 👍 <img src=\"/acme.png\" /> :nope: 10:30:45</p>\n",
+            "<p><img src=\"x.png\" alt=\"a 😄\" /></p>\n",
+        ),
+        |opts| {
+            opts.extension.shortcodes = true;
+            opts.extension
+                .custom_shortcodes
+                .insert("acme".to_string(), "<img src=\"/acme.png\" />".to_string());
+        },
+    );
+
+    html("Shipped :tada:\n", "<p>Shipped :tada:</p>\n");
+    html_opts("Escaped \\:tada\\:\n", "<p>Escaped :tada:</p>\n", |opts| {
+        opts.extension.shortcodes = true
+    });
+}
+
+#[test]
+fn custom_shortcodes() {
+    html_opts(
+        "Made by :acme: :Acme: 10:30\n",
+        "<p>Made by <img src=\"/acme.png\" /> :Acme: 10:30</p>\n",
+        |opts| {
+            opts.extension.shortcodes = true;
+            opts.extension
+                .custom_shortcodes
+                .insert("acme".to_string(), "<img src=\"/acme.png\" />".to_string());
+        },
+    );
+}
+
+#[test]
+fn case_insensitive_safety() {
+    html(
+        "[a](javascript:a) [b](Javascript:b) [c](jaVascript:c) [d](data:xyz) [e](Data:xyz) [f](vbscripT:f) [g](FILE:g)\n",
+        "<p><a href=\"\">a</a> <a href=\"\">b</a> <a href=\"\">c</a> <a href=\"\">d</a> <a href=\"\">e</a> <a href=\"\">f</a> <a href=\"\">g</a></p>\n",
+    );
+}
+
+#[test]
+fn exercise_full_api() {
+    let arena = ::Arena::new();
+    let default_options = ::ComrakOptions::default();
+    let node = ::parse_document(&arena, "# My document\n", &default_options);
+    let mut buffer = vec![];
+
+    // Use every member of the exposed API without any defaults.
+    // Not looking for specific outputs, just want to know if the API changes shape.
+
+    let _: std::io::Result<()> = ::format_commonmark(node, &default_options, &mut buffer);
+
+    let _: std::io::Result<()> = ::format_html(node, &default_options, &mut buffer);
+
+    let _: std::io::Result<()> = ::format_xml(node, &default_options, &mut buffer);
+
+    let _: std::io::Result<()> = ::format_html_with_plugins(
+        node,
+        &default_options,
+        &mut buffer,
+        &::ComrakPlugins::default(),
+    );
+
+    let _: String = ::Anchorizer::new().anchorize("header".to_string());
+
+    let _: &::nodes::AstNode = ::parse_document(&arena, "document", &default_options);
+
+    let _: &::nodes::AstNode = ::parse_document_with_broken_link_callback(
+        &arena,
+        "document",
+        &default_options,
+        Some(&mut |_: &[u8]| Some((b"abc".to_vec(), b"xyz".to_vec()))),
+    );
+
+    let _ = ::ComrakOptions {
+        extension: ::ComrakExtensionOptions {
+            strikethrough: false,
+            tagfilter: false,
+            table: false,
+            autolink: false,
             tasklist: false,
             superscript: false,
+            highlight: false,
+            insert: false,
+            subscript: false,
+            spoiler: false,
+            underline: false,
             header_ids: Some("abc".to_string()),
             footnotes: false,
             description_lists: false,
             front_matter_delimiter: None,
+            wikilinks: false,
+            wikilinks_title_before_pipe: false,
+            math: false,
+            alerts: false,
+            table_of_contents: false,
+            attributes: false,
+            custom_containers: false,
+            shortcodes: false,
+            custom_shortcodes: HashMap::new(),
         },
         parse: ::ComrakParseOptions {
             smart: false,
@@ -1063,19 +2736,46 @@ fn exercise_full_api() {
             width: 123456,
             unsafe_: false,
             escape: false,
+            sanitize: Some(::sanitize::Sanitizer::default()),
+            url_policy: Some(::sanitize::UrlPolicy::allow(&["https"])),
+            external_links: Some(::html::ExternalLinks::default()),
+            footnote_id_prefix: Some("comment-1-".to_string()),
+            sourcepos: false,
         },
     };
 
     let _: String = ::markdown_to_html("# Yes", &default_options);
 
+    let _ = ::ComrakPlugins {
+        render: ::ComrakRenderPlugins {
+            codefence_syntax_highlighter: None,
+            node_renderer: None,
+            wikilink_resolver: None,
+            url_rewriter: None,
+            container_renderer: None,
+        },
+    };
+
+    let _: String =
+        ::markdown_to_html_with_plugins("# Yes", &default_options, &::ComrakPlugins::default());
+
     //
 
     let ast = node.data.borrow();
-    let _ = ast.start_line;
+    let _: ::nodes::Sourcepos = ast.sourcepos;
     match &ast.value {
         ::nodes::NodeValue::Document => {}
         ::nodes::NodeValue::FrontMatter(_) => {}
         ::nodes::NodeValue::BlockQuote => {}
+        ::nodes::NodeValue::Alert(na) => {
+            let _: ::nodes::AlertType = na.kind;
+            let _: &Option<Vec<u8>> = &na.title;
+        }
+        ::nodes::NodeValue::Container(nc) => {
+            let _: &Vec<u8> = &nc.name;
+            let _: &Vec<u8> = &nc.info;
+            let _: usize = nc.fence_length;
+        }
         ::nodes::NodeValue::List(nl) | ::nodes::NodeValue::Item(nl) => {
             match nl.list_type {
                 ::nodes::ListType::Bullet => {}
@@ -1109,8 +2809,11 @@ fn exercise_full_api() {
             let _: bool = nh.setext;
         }
         ::nodes::NodeValue::ThematicBreak => {}
-        ::nodes::NodeValue::FootnoteDefinition(name) => {
-            let _: &Vec<u8> = name;
+        ::nodes::NodeValue::TableOfContents => {}
+        ::nodes::NodeValue::FootnoteDefinition(nfd) => {
+            let _: &Vec<u8> = &nfd.name;
+            let _: u32 = nfd.ix;
+            let _: u32 = nfd.total_references;
         }
         ::nodes::NodeValue::Table(aligns) => {
             let _: &Vec<::nodes::TableAlignment> = aligns;
@@ -1143,12 +2846,31 @@ fn exercise_full_api() {
         ::nodes::NodeValue::Strong => {}
         ::nodes::NodeValue::Strikethrough => {}
         ::nodes::NodeValue::Superscript => {}
+        ::nodes::NodeValue::Highlight => {}
+        ::nodes::NodeValue::Insert => {}
+        ::nodes::NodeValue::Subscript => {}
+        ::nodes::NodeValue::SpoileredText => {}
+        ::nodes::NodeValue::Underline => {}
         ::nodes::NodeValue::Link(nl) | ::nodes::NodeValue::Image(nl) => {
             let _: Vec<u8> = nl.url;
             let _: Vec<u8> = nl.title;
         }
-        ::nodes::NodeValue::FootnoteReference(name) => {
-            let _: &Vec<u8> = name;
+        ::nodes::NodeValue::FootnoteReference(nfr) => {
+            let _: &Vec<u8> = &nfr.name;
+            let _: u32 = nfr.ix;
+            let _: u32 = nfr.ref_num;
+        }
+        ::nodes::NodeValue::WikiLink(nl) => {
+            let _: &Vec<u8> = &nl.url;
+        }
+        ::nodes::NodeValue::Math(nm) => {
+            let _: bool = nm.display;
+            let _: bool = nm.fenced;
+            let _: &Vec<u8> = &nm.literal;
+        }
+        ::nodes::NodeValue::ShortCode(nsc) => {
+            let _: &String = &nsc.code;
+            let _: &Option<String> = &nsc.emoji;
         }
     }
 }
@@ -0,0 +1,86 @@
+//! Tables of contents built from a document's headings.
+
+use html::{collect_text, heading_id, Anchorizer};
+use nodes::{AstNode, NodeValue};
+use parser::ComrakOptions;
+
+/// A heading in a table of contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    /// The heading's level, from 1 to 6.
+    pub level: u32,
+
+    /// The heading's text content, without any formatting.
+    pub text: String,
+
+    /// The heading's anchor ID, as given to it by the HTML formatter when the `header_ids`
+    /// option is set, including its prefix.
+    pub id: String,
+
+    /// Headings beneath this one in the document outline, i.e. those of a greater level which
+    /// follow it before the next heading of the same or a lesser level.
+    pub children: Vec<TocEntry>,
+}
+
+/// Builds a table of contents from the headings in the document.
+///
+/// Anchor IDs are computed the same way the HTML formatter computes them for the `header_ids`
+/// option, so they will match the rendered document when it is formatted with the same options.
+/// Without `header_ids` they are computed with no prefix, but the formatter won't emit them.
+///
+/// ```
+/// # use comrak::{parse_document, Arena, ComrakOptions};
+/// use comrak::toc;
+///
+/// let mut options = ComrakOptions::default();
+/// options.extension.header_ids = Some("user-content-".to_string());
+///
+/// let arena = Arena::new();
+/// let root = parse_document(&arena, "# Intro\n## Setup\n## Usage\n# Intro\n", &options);
+/// let entries = toc::build(root, &options);
+///
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!(entries[0].id, "user-content-intro");
+/// assert_eq!(entries[0].children[1].text, "Usage");
+/// assert_eq!(entries[1].id, "user-content-intro-1");
+/// ```
+pub fn build<'a>(root: &'a AstNode<'a>, options: &ComrakOptions) -> Vec<TocEntry> {
+    let prefix = options
+        .extension
+        .header_ids
+        .as_ref()
+        .map_or("", |prefix| prefix.as_str());
+    let mut anchorizer = Anchorizer::new();
+    let mut entries = vec![];
+
+    for node in root.descendants() {
+        let level = match node.data.borrow().value {
+            NodeValue::Heading(ref nh) => nh.level,
+            _ => continue,
+        };
+
+        let mut text = Vec::with_capacity(20);
+        collect_text(node, &mut text);
+        let text = String::from_utf8(text).unwrap();
+        let id = format!("{}{}", prefix, heading_id(&mut anchorizer, node));
+
+        insert(
+            &mut entries,
+            TocEntry {
+                level,
+                text,
+                id,
+                children: vec![],
+            },
+        );
+    }
+
+    entries
+}
+
+fn insert(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+    match entries.last_mut() {
+        Some(last) if last.level < entry.level => insert(&mut last.children, entry),
+        _ => entries.push(entry),
+    }
+}
@@ -0,0 +1,227 @@
+use html;
+use nodes::{AstNode, ListDelimType, ListType, NodeValue, TableAlignment};
+use parser::ComrakOptions;
+use std::io::{self, Write};
+
+/// Formats an AST as CommonMark XML, modified by the given options.
+///
+/// The output conforms to the [CommonMark DTD](https://github.com/commonmark/commonmark-spec/blob/master/CommonMark.dtd),
+/// with additional elements for the extension nodes.
+pub fn format_document<'a>(
+    root: &'a AstNode<'a>,
+    options: &ComrakOptions,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    output.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n")?;
+    output.write_all(b"<!DOCTYPE document SYSTEM \"CommonMark.dtd\">\n")?;
+    XmlFormatter::new(options, output).format(root)
+}
+
+struct XmlFormatter<'o> {
+    output: &'o mut dyn Write,
+    options: &'o ComrakOptions,
+    indent: usize,
+}
+
+impl<'o> XmlFormatter<'o> {
+    fn new(options: &'o ComrakOptions, output: &'o mut dyn Write) -> Self {
+        XmlFormatter {
+            options,
+            output,
+            indent: 0,
+        }
+    }
+
+    fn escape(&mut self, buffer: &[u8]) -> io::Result<()> {
+        html::escape(self.output, buffer)
+    }
+
+    fn format<'a>(&mut self, node: &'a AstNode<'a>) -> io::Result<()> {
+        enum Phase {
+            Pre,
+            Post,
+        }
+        let mut stack = vec![(node, Phase::Pre)];
+
+        while let Some((node, phase)) = stack.pop() {
+            match phase {
+                Phase::Pre => {
+                    self.format_node(node, true)?;
+                    stack.push((node, Phase::Post));
+                    for ch in node.reverse_children() {
+                        stack.push((ch, Phase::Pre));
+                    }
+                }
+                Phase::Post => {
+                    self.format_node(node, false)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_indent(&mut self) -> io::Result<()> {
+        for _ in 0..self.indent {
+            self.output.write_all(b" ")?;
+        }
+        Ok(())
+    }
+
+    fn format_node<'a>(&mut self, node: &'a AstNode<'a>, entering: bool) -> io::Result<()> {
+        let ast = node.data.borrow();
+        let name = ast.value.xml_node_name();
+
+        if !entering {
+            if node.first_child().is_some() {
+                self.indent -= 2;
+                self.write_indent()?;
+                writeln!(self.output, "</{}>", name)?;
+            }
+            return Ok(());
+        }
+
+        self.write_indent()?;
+        write!(self.output, "<{}", name)?;
+
+        if self.options.render.sourcepos && ast.sourcepos.start.line > 0 {
+            write!(self.output, " sourcepos=\"{}\"", ast.sourcepos)?;
+        }
+
+        let mut literal = None;
+
+        match ast.value {
+            NodeValue::Document => {
+                self.output
+                    .write_all(b" xmlns=\"http://commonmark.org/xml/1.0\"")?;
+            }
+            NodeValue::FrontMatter(ref text)
+            | NodeValue::Text(ref text)
+            | NodeValue::Code(ref text)
+            | NodeValue::HtmlInline(ref text) => {
+                literal = Some(text);
+            }
+            NodeValue::HtmlBlock(ref nhb) => {
+                literal = Some(&nhb.literal);
+            }
+            NodeValue::List(ref nl) => {
+                match nl.list_type {
+                    ListType::Bullet => {
+                        self.output.write_all(b" type=\"bullet\"")?;
+                    }
+                    ListType::Ordered => {
+                        write!(
+                            self.output,
+                            " type=\"ordered\" start=\"{}\" delim=\"{}\"",
+                            nl.start,
+                            match nl.delimiter {
+                                ListDelimType::Period => "period",
+                                ListDelimType::Paren => "paren",
+                            }
+                        )?;
+                    }
+                }
+                write!(self.output, " tight=\"{}\"", nl.tight)?;
+            }
+            NodeValue::Alert(ref na) => {
+                write!(self.output, " type=\"{}\"", na.kind.name().to_lowercase())?;
+                if let Some(ref title) = na.title {
+                    self.output.write_all(b" title=\"")?;
+                    self.escape(title)?;
+                    self.output.write_all(b"\"")?;
+                }
+            }
+            NodeValue::Container(ref nc) => {
+                self.output.write_all(b" name=\"")?;
+                self.escape(&nc.name)?;
+                self.output.write_all(b"\"")?;
+                if !nc.info.is_empty() {
+                    self.output.write_all(b" info=\"")?;
+                    self.escape(&nc.info)?;
+                    self.output.write_all(b"\"")?;
+                }
+            }
+            NodeValue::Heading(ref nh) => {
+                write!(self.output, " level=\"{}\"", nh.level)?;
+            }
+            NodeValue::CodeBlock(ref ncb) => {
+                if !ncb.info.is_empty() {
+                    self.output.write_all(b" info=\"")?;
+                    self.escape(&ncb.info)?;
+                    self.output.write_all(b"\"")?;
+                }
+                literal = Some(&ncb.literal);
+            }
+            NodeValue::Link(ref nl) | NodeValue::Image(ref nl) => {
+                self.output.write_all(b" destination=\"")?;
+                self.escape(&nl.url)?;
+                self.output.write_all(b"\" title=\"")?;
+                self.escape(&nl.title)?;
+                self.output.write_all(b"\"")?;
+            }
+            NodeValue::WikiLink(ref nl) => {
+                self.output.write_all(b" destination=\"")?;
+                self.escape(&nl.url)?;
+                self.output.write_all(b"\"")?;
+            }
+            NodeValue::ShortCode(ref nsc) => {
+                write!(self.output, " code=\"{}\"", nsc.code)?;
+                if let Some(ref emoji) = nsc.emoji {
+                    self.output.write_all(b" emoji=\"")?;
+                    self.escape(emoji.as_bytes())?;
+                    self.output.write_all(b"\"")?;
+                }
+            }
+            NodeValue::Math(ref nm) => {
+                write!(self.output, " display=\"{}\"", nm.display)?;
+                if nm.fenced {
+                    self.output.write_all(b" fenced=\"true\"")?;
+                }
+                literal = Some(&nm.literal);
+            }
+            NodeValue::TableCell => {
+                let row = node.parent().unwrap();
+                let table = row.parent().unwrap();
+                let ix = node.preceding_siblings().count() - 1;
+                if let NodeValue::Table(ref alignments) = table.data.borrow().value {
+                    let align = match alignments[ix] {
+                        TableAlignment::None => None,
+                        TableAlignment::Left => Some("left"),
+                        TableAlignment::Center => Some("center"),
+                        TableAlignment::Right => Some("right"),
+                    };
+                    if let Some(align) = align {
+                        write!(self.output, " align=\"{}\"", align)?;
+                    }
+                }
+            }
+            NodeValue::FootnoteDefinition(ref nfd) => {
+                self.output.write_all(b" label=\"")?;
+                self.escape(&nfd.name)?;
+                self.output.write_all(b"\"")?;
+            }
+            NodeValue::FootnoteReference(ref nfr) => {
+                self.output.write_all(b" label=\"")?;
+                self.escape(&nfr.name)?;
+                self.output.write_all(b"\"")?;
+            }
+            NodeValue::TaskItem(checked) => {
+                write!(self.output, " completed=\"{}\"", checked)?;
+            }
+            _ => (),
+        }
+
+        if let Some(literal) = literal {
+            self.output.write_all(b" xml:space=\"preserve\">")?;
+            self.escape(literal)?;
+            writeln!(self.output, "</{}>", name)?;
+        } else if node.first_child().is_some() {
+            self.output.write_all(b">\n")?;
+            self.indent += 2;
+        } else {
+            self.output.write_all(b" />\n")?;
+        }
+
+        Ok(())
+    }
+}